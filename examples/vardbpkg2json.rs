@@ -1,24 +1,33 @@
 
 
 // Example tool that parses the vardb and outputs it as JSON.
-// Usage: cargo run --example vardbpkg2json -- [path]
+// Usage: cargo run --example vardbpkg2json -- [--spdx|--cyclonedx] [path]
 // Default path is /var/db/pkg if no path is provided.
 
 use std::path::Path;
 use std::env;
+use vardbpkg::sbom::Sbom;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = env::args().collect();
-    let path = if args.len() > 1 {
-        &args[1]
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let format = match args.first().map(String::as_str) {
+        Some("--spdx") | Some("--cyclonedx") => Some(args.remove(0)),
+        _ => None,
+    };
+    let path = if !args.is_empty() {
+        &args[0]
     } else {
         "/var/db/pkg"
     };
 
-    println!("Scanning directory: {}", path);
+    eprintln!("Scanning directory: {}", path);
     let packages = vardbpkg::parse_vardb(Path::new(path));
-    
-    let json = serde_json::to_string_pretty(&packages)?;
+
+    let json = match format.as_deref() {
+        Some("--spdx") => serde_json::to_string_pretty(&Sbom::new(&packages).to_spdx())?,
+        Some("--cyclonedx") => serde_json::to_string_pretty(&Sbom::new(&packages).to_cyclonedx())?,
+        _ => serde_json::to_string_pretty(&packages)?,
+    };
     println!("{}", json);
 
     Ok(())
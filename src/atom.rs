@@ -0,0 +1,572 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use crate::error;
+use crate::fields::Fields;
+use crate::useflags::{is_valid_flag, FlagState};
+use crate::version::Version;
+use crate::{split_package_version, VarDbPkg};
+
+/// Error returned when a string is not a valid package atom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseAtomError {
+    atom: String,
+    reason: &'static str,
+}
+
+impl fmt::Display for ParseAtomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid atom {:?}: {}", self.atom, self.reason)
+    }
+}
+
+impl std::error::Error for ParseAtomError {}
+
+/// Blocker prefix of an atom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Blocker {
+    /// `!atom`
+    Weak,
+    /// `!!atom`
+    Strong,
+}
+
+/// Version operator of an atom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Operator {
+    /// `<`
+    Less,
+    /// `<=`
+    LessOrEqual,
+    /// `=`
+    Equal,
+    /// `=` with a trailing `*` on the version
+    EqualGlob,
+    /// `~`
+    Approximate,
+    /// `>=`
+    GreaterOrEqual,
+    /// `>`
+    Greater,
+}
+
+/// Slot operator of an atom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SlotOperator {
+    /// `:=` (rebuild on slot/subslot change)
+    Equal,
+    /// `:*` (any slot)
+    Star,
+}
+
+/// Kind of a USE dependency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum UseDepKind {
+    /// `foo`
+    Enabled,
+    /// `-foo`
+    Disabled,
+    /// `foo=`
+    Equal,
+    /// `!foo=`
+    NotEqual,
+    /// `foo?`
+    EnabledIf,
+    /// `!foo?`
+    DisabledIf,
+}
+
+/// A single USE dependency in `[...]`, e.g. `-static(+)`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct UseDep {
+    pub flag: String,
+    pub kind: UseDepKind,
+    /// `(+)` or `(-)`: assumed state if the flag is not in IUSE.
+    pub default: Option<bool>,
+}
+
+impl UseDep {
+    fn parse(s: &str) -> Option<Self> {
+        let (s, kind) = if let Some(rest) = s.strip_prefix('!') {
+            if let Some(flag) = rest.strip_suffix('=') {
+                (flag, UseDepKind::NotEqual)
+            } else {
+                (rest.strip_suffix('?')?, UseDepKind::DisabledIf)
+            }
+        } else if let Some(flag) = s.strip_suffix('=') {
+            (flag, UseDepKind::Equal)
+        } else if let Some(flag) = s.strip_suffix('?') {
+            (flag, UseDepKind::EnabledIf)
+        } else if let Some(flag) = s.strip_prefix('-') {
+            (flag, UseDepKind::Disabled)
+        } else {
+            (s, UseDepKind::Enabled)
+        };
+
+        let (flag, default) = if let Some(flag) = s.strip_suffix("(+)") {
+            (flag, Some(true))
+        } else if let Some(flag) = s.strip_suffix("(-)") {
+            (flag, Some(false))
+        } else {
+            (s, None)
+        };
+
+        if !is_valid_flag(flag) {
+            return None;
+        }
+        Some(UseDep {
+            flag: flag.to_string(),
+            kind,
+            default,
+        })
+    }
+}
+
+impl fmt::Display for UseDep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let default = match self.default {
+            Some(true) => "(+)",
+            Some(false) => "(-)",
+            None => "",
+        };
+        match self.kind {
+            UseDepKind::Enabled => write!(f, "{}{}", self.flag, default),
+            UseDepKind::Disabled => write!(f, "-{}{}", self.flag, default),
+            UseDepKind::Equal => write!(f, "{}{}=", self.flag, default),
+            UseDepKind::NotEqual => write!(f, "!{}{}=", self.flag, default),
+            UseDepKind::EnabledIf => write!(f, "{}{}?", self.flag, default),
+            UseDepKind::DisabledIf => write!(f, "!{}{}?", self.flag, default),
+        }
+    }
+}
+
+/// A Portage dependency atom such as `>=dev-libs/openssl-1.1.1:0/3=[ssl,-static(+)]::gentoo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageAtom {
+    pub blocker: Option<Blocker>,
+    pub operator: Option<Operator>,
+    pub category: String,
+    pub package: String,
+    pub version: Option<Version>,
+    pub slot: Option<String>,
+    pub subslot: Option<String>,
+    pub slot_operator: Option<SlotOperator>,
+    pub use_deps: Vec<UseDep>,
+    pub repository: Option<String>,
+}
+
+impl PackageAtom {
+    /// Parses an atom string.
+    pub fn parse(s: &str) -> Result<Self, ParseAtomError> {
+        let err = |reason| ParseAtomError {
+            atom: s.to_string(),
+            reason,
+        };
+
+        let mut rest = s.trim();
+        let blocker = if let Some(r) = rest.strip_prefix("!!") {
+            rest = r;
+            Some(Blocker::Strong)
+        } else if let Some(r) = rest.strip_prefix('!') {
+            rest = r;
+            Some(Blocker::Weak)
+        } else {
+            None
+        };
+
+        let mut operator = [
+            ("<=", Operator::LessOrEqual),
+            (">=", Operator::GreaterOrEqual),
+            ("<", Operator::Less),
+            (">", Operator::Greater),
+            ("=", Operator::Equal),
+            ("~", Operator::Approximate),
+        ]
+        .iter()
+        .find_map(|(prefix, op)| {
+            rest.strip_prefix(prefix).map(|r| {
+                rest = r;
+                *op
+            })
+        });
+
+        // USE dependencies, which may come before or after `::repo`
+        let mut use_deps = Vec::new();
+        let mut repository = None;
+        if let Some(open) = rest.find('[') {
+            let close = rest[open..].find(']').map(|i| open + i).ok_or_else(|| err("unterminated USE dependency"))?;
+            for flag in rest[open + 1..close].split(',') {
+                use_deps.push(UseDep::parse(flag.trim()).ok_or_else(|| err("invalid USE dependency"))?);
+            }
+            let after = &rest[close + 1..];
+            if let Some(repo) = after.strip_prefix("::") {
+                repository = Some(repo.to_string());
+            } else if !after.is_empty() {
+                return Err(err("trailing characters after USE dependency"));
+            }
+            rest = &rest[..open];
+        }
+        if let Some(idx) = rest.find("::") {
+            if repository.is_some() {
+                return Err(err("repository given twice"));
+            }
+            repository = Some(rest[idx + 2..].to_string());
+            rest = &rest[..idx];
+        }
+        if repository.as_deref().is_some_and(|r| r.is_empty()) {
+            return Err(err("empty repository"));
+        }
+
+        let mut slot = None;
+        let mut subslot = None;
+        let mut slot_operator = None;
+        if let Some(idx) = rest.find(':') {
+            let mut slot_part = &rest[idx + 1..];
+            rest = &rest[..idx];
+            if slot_part == "*" {
+                slot_operator = Some(SlotOperator::Star);
+                slot_part = "";
+            } else if let Some(s) = slot_part.strip_suffix('=') {
+                slot_operator = Some(SlotOperator::Equal);
+                slot_part = s;
+            }
+            if !slot_part.is_empty() {
+                let (s, sub) = match slot_part.split_once('/') {
+                    Some((s, sub)) => (s, Some(sub)),
+                    None => (slot_part, None),
+                };
+                if s.is_empty() || sub.is_some_and(str::is_empty) {
+                    return Err(err("invalid slot"));
+                }
+                slot = Some(s.to_string());
+                subslot = sub.map(str::to_string);
+            } else if slot_operator.is_none() {
+                return Err(err("empty slot"));
+            }
+        }
+
+        let glob = rest.ends_with('*');
+        if glob {
+            if operator != Some(Operator::Equal) {
+                return Err(err("'*' is only allowed with '='"));
+            }
+            rest = &rest[..rest.len() - 1];
+            operator = Some(Operator::EqualGlob);
+        }
+
+        let (category, name) = rest.split_once('/').ok_or_else(|| err("missing category"))?;
+        if category.is_empty() || name.is_empty() || name.contains('/') {
+            return Err(err("invalid category/package"));
+        }
+
+        let (package, version) = if operator.is_some() {
+            let (package, version) = split_package_version(name);
+            if version.is_empty() {
+                return Err(err("operator without version"));
+            }
+            let version = Version::parse(&version).map_err(|_| err("invalid version"))?;
+            (package, Some(version))
+        } else {
+            (name.to_string(), None)
+        };
+
+        Ok(PackageAtom {
+            blocker,
+            operator,
+            category: category.to_string(),
+            package,
+            version,
+            slot,
+            subslot,
+            slot_operator,
+            use_deps,
+            repository,
+        })
+    }
+
+    /// Returns `category/package`.
+    pub fn cp(&self) -> String {
+        format!("{}/{}", self.category, self.package)
+    }
+
+    /// Returns true if the installed package satisfies this atom.
+    ///
+    /// The blocker prefix is ignored, so `!foo/bar` matches installed `foo/bar`.
+    /// Conditional USE dependencies (`foo?`, `foo=`, ...) depend on the USE flags of the
+    /// depending package and are therefore not checked.
+    /// A package whose SLOT, repository or USE files cannot be read does not match.
+    pub fn matches(&self, pkg: &VarDbPkg) -> bool {
+        self.try_matches(pkg).unwrap_or(false)
+    }
+
+    fn try_matches(&self, pkg: &VarDbPkg) -> error::Result<bool> {
+        if self.category != pkg.category || self.package != pkg.package {
+            return Ok(false);
+        }
+        if !self.matches_version(&pkg.version) {
+            return Ok(false);
+        }
+
+        if self.slot.is_some() || self.subslot.is_some() {
+            let slot = pkg.value(Fields::SLOT)?;
+            let (pkg_slot, pkg_subslot) = slot.split_once('/').unwrap_or((slot, slot));
+            if self.slot.as_deref().is_some_and(|s| s != pkg_slot)
+                || self.subslot.as_deref().is_some_and(|s| s != pkg_subslot)
+            {
+                return Ok(false);
+            }
+        }
+        if let Some(repository) = &self.repository
+            && repository != pkg.value(Fields::REPOSITORY)?
+        {
+            return Ok(false);
+        }
+        if self.use_deps.is_empty() {
+            return Ok(true);
+        }
+
+        let flags = pkg.use_flags()?;
+        Ok(self.use_deps.iter().all(|dep| {
+            let state = match flags.state(&dep.flag) {
+                FlagState::Enabled => true,
+                FlagState::Disabled => false,
+                // The (+) and (-) defaults only apply to flags the package does not know.
+                // Without one, a requirement on such a flag is never satisfied, whichever state it asks for.
+                FlagState::NotInIuse => match dep.default {
+                    Some(default) => default,
+                    None => return !matches!(dep.kind, UseDepKind::Enabled | UseDepKind::Disabled),
+                },
+            };
+            match dep.kind {
+                UseDepKind::Enabled => state,
+                UseDepKind::Disabled => !state,
+                _ => true,
+            }
+        }))
+    }
+
+    /// Returns true if the given version satisfies the version operator of this atom.
+    pub fn matches_version(&self, version: &str) -> bool {
+        let (Some(op), Some(wanted)) = (self.operator, &self.version) else {
+            return true;
+        };
+        let Ok(version) = Version::parse(version) else {
+            return false;
+        };
+        match op {
+            Operator::Less => version < *wanted,
+            Operator::LessOrEqual => version <= *wanted,
+            Operator::Equal => version == *wanted,
+            Operator::Approximate => version.cmp_without_revision(wanted).is_eq(),
+            Operator::GreaterOrEqual => version >= *wanted,
+            Operator::Greater => version > *wanted,
+            Operator::EqualGlob => version.matches_glob(wanted.as_str()),
+        }
+    }
+
+    /// Returns all packages matching this atom.
+    pub fn select<'a>(&self, packages: &'a [VarDbPkg]) -> Vec<&'a VarDbPkg> {
+        packages.iter().filter(|pkg| self.matches(pkg)).collect()
+    }
+}
+
+impl fmt::Display for PackageAtom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.blocker {
+            Some(Blocker::Weak) => f.write_str("!")?,
+            Some(Blocker::Strong) => f.write_str("!!")?,
+            None => {}
+        }
+        f.write_str(match self.operator {
+            Some(Operator::Less) => "<",
+            Some(Operator::LessOrEqual) => "<=",
+            Some(Operator::Equal) | Some(Operator::EqualGlob) => "=",
+            Some(Operator::Approximate) => "~",
+            Some(Operator::GreaterOrEqual) => ">=",
+            Some(Operator::Greater) => ">",
+            None => "",
+        })?;
+        write!(f, "{}/{}", self.category, self.package)?;
+        if let Some(version) = &self.version {
+            write!(f, "-{}", version)?;
+        }
+        if self.operator == Some(Operator::EqualGlob) {
+            f.write_str("*")?;
+        }
+        if self.slot.is_some() || self.slot_operator.is_some() {
+            f.write_str(":")?;
+            if let Some(slot) = &self.slot {
+                f.write_str(slot)?;
+            }
+            if let Some(subslot) = &self.subslot {
+                write!(f, "/{}", subslot)?;
+            }
+            match self.slot_operator {
+                Some(SlotOperator::Equal) => f.write_str("=")?,
+                Some(SlotOperator::Star) => f.write_str("*")?,
+                None => {}
+            }
+        }
+        if let Some(repo) = &self.repository {
+            write!(f, "::{}", repo)?;
+        }
+        if !self.use_deps.is_empty() {
+            let deps: Vec<String> = self.use_deps.iter().map(|d| d.to_string()).collect();
+            write!(f, "[{}]", deps.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PackageAtom {
+    type Err = ParseAtomError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl Serialize for PackageAtom {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PackageAtom {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        PackageAtom::parse(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn installed(cpv: &str, slot: &str, usepkg: &str, iuse: &str) -> VarDbPkg {
+        let (category, rest) = cpv.split_once('/').unwrap();
+        let (package, version) = split_package_version(rest);
+        VarDbPkg {
+            category: category.to_string(),
+            package,
+            version,
+            slot: slot.to_string(),
+            usepkg: usepkg.to_string(),
+            iuse: iuse.to_string(),
+            repository: "gentoo".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_full_atom() {
+        let atom = PackageAtom::parse(">=dev-libs/openssl-1.1.1:0/3=[ssl,-static(+)]::gentoo").unwrap();
+        assert_eq!(atom.blocker, None);
+        assert_eq!(atom.operator, Some(Operator::GreaterOrEqual));
+        assert_eq!(atom.cp(), "dev-libs/openssl");
+        assert_eq!(atom.version.as_ref().unwrap().as_str(), "1.1.1");
+        assert_eq!(atom.slot.as_deref(), Some("0"));
+        assert_eq!(atom.subslot.as_deref(), Some("3"));
+        assert_eq!(atom.slot_operator, Some(SlotOperator::Equal));
+        assert_eq!(atom.use_deps.len(), 2);
+        assert_eq!(atom.use_deps[1].kind, UseDepKind::Disabled);
+        assert_eq!(atom.use_deps[1].default, Some(true));
+        assert_eq!(atom.repository.as_deref(), Some("gentoo"));
+        assert_eq!(atom.to_string(), ">=dev-libs/openssl-1.1.1:0/3=::gentoo[ssl,-static(+)]");
+    }
+
+    #[test]
+    fn test_parse_variants() {
+        let atom = PackageAtom::parse("=app-misc/foo-1.2*").unwrap();
+        assert_eq!(atom.operator, Some(Operator::EqualGlob));
+        assert_eq!(atom.to_string(), "=app-misc/foo-1.2*");
+
+        let atom = PackageAtom::parse("~app-misc/foo-1.2").unwrap();
+        assert_eq!(atom.operator, Some(Operator::Approximate));
+
+        let atom = PackageAtom::parse("!!app-misc/foo:*").unwrap();
+        assert_eq!(atom.blocker, Some(Blocker::Strong));
+        assert_eq!(atom.slot_operator, Some(SlotOperator::Star));
+        assert_eq!(atom.version, None);
+
+        let atom = PackageAtom::parse("!<sys-libs/glibc-2.40-r1:2.2::gentoo[!foo=,bar?]").unwrap();
+        assert_eq!(atom.blocker, Some(Blocker::Weak));
+        assert_eq!(atom.operator, Some(Operator::Less));
+        assert_eq!(atom.version.as_ref().unwrap().revision(), 1);
+        assert_eq!(atom.use_deps[0].kind, UseDepKind::NotEqual);
+        assert_eq!(atom.use_deps[1].kind, UseDepKind::EnabledIf);
+
+        let atom = PackageAtom::parse("dev-python/font-adobe-100dpi").unwrap();
+        assert_eq!(atom.package, "font-adobe-100dpi");
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for s in [
+            "foo",
+            "cat/",
+            ">=cat/pkg",
+            ">cat/pkg-1.2*",
+            "cat/pkg:",
+            "cat/pkg[foo",
+            "cat/pkg[?]",
+            "=cat/pkg-1.x",
+            "cat/pkg::",
+        ] {
+            assert!(PackageAtom::parse(s).is_err(), "{} should be invalid", s);
+        }
+    }
+
+    #[test]
+    fn test_matches_version_operators() {
+        let pkg = installed("dev-libs/foo-1.2.3-r1", "0", "", "");
+        let matches = |a: &str| PackageAtom::parse(a).unwrap().matches(&pkg);
+        assert!(matches("dev-libs/foo"));
+        assert!(!matches("dev-libs/bar"));
+        assert!(matches(">=dev-libs/foo-1.2.3"));
+        assert!(matches(">dev-libs/foo-1.2.3"));
+        assert!(!matches("<dev-libs/foo-1.2.3-r1"));
+        assert!(matches("<=dev-libs/foo-1.2.3-r1"));
+        assert!(matches("=dev-libs/foo-1.2.3-r1"));
+        assert!(!matches("=dev-libs/foo-1.2.3"));
+        assert!(matches("~dev-libs/foo-1.2.3"));
+        assert!(matches("=dev-libs/foo-1.2*"));
+        assert!(matches("=dev-libs/foo-1*"));
+        assert!(!matches("=dev-libs/foo-1.3*"));
+        assert!(!PackageAtom::parse("=dev-libs/foo-1.2*").unwrap().matches(&installed("dev-libs/foo-1.20", "0", "", "")));
+    }
+
+    #[test]
+    fn test_matches_slot_repo_and_use() {
+        let pkg = installed("dev-libs/openssl-3.0.13", "0/3", "ssl asm", "+asm ssl static-libs");
+        let matches = |a: &str| PackageAtom::parse(a).unwrap().matches(&pkg);
+        assert!(matches(">=dev-libs/openssl-1.1.1:0/3=[ssl,-static-libs(+)]::gentoo"));
+        assert!(matches("dev-libs/openssl:0"));
+        assert!(!matches("dev-libs/openssl:1"));
+        assert!(!matches("dev-libs/openssl:0/1.1"));
+        assert!(matches("dev-libs/openssl:="));
+        assert!(!matches("dev-libs/openssl::other"));
+        assert!(!matches("dev-libs/openssl[static-libs]"));
+        assert!(matches("dev-libs/openssl[-static-libs]"));
+        assert!(matches("dev-libs/openssl[missing(+)]"));
+        assert!(!matches("dev-libs/openssl[missing(-)]"));
+        assert!(!matches("dev-libs/openssl[missing]"));
+        assert!(!matches("dev-libs/openssl[-missing]"));
+        assert!(matches("dev-libs/openssl[-missing(-)]"));
+        assert!(matches("dev-libs/openssl[foo?,!bar=]"));
+    }
+
+    #[test]
+    fn test_select() {
+        let packages = vec![
+            installed("dev-lang/python-3.12.8", "3.12", "", ""),
+            installed("dev-lang/python-3.13.1", "3.13", "", ""),
+            installed("dev-lang/perl-5.40.0", "0/5.40", "", ""),
+        ];
+        let atom: PackageAtom = ">=dev-lang/python-3.13".parse().unwrap();
+        let selected = atom.select(&packages);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].slot, "3.13");
+        assert_eq!(PackageAtom::parse("dev-lang/python").unwrap().select(&packages).len(), 2);
+    }
+}
@@ -0,0 +1,320 @@
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// The type of a CONTENTS entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryType {
+    Dir,
+    Obj,
+    Sym,
+    Dev,
+    Fif,
+}
+
+impl EntryType {
+    /// Returns the keyword used for this type in the CONTENTS file.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryType::Dir => "dir",
+            EntryType::Obj => "obj",
+            EntryType::Sym => "sym",
+            EntryType::Dev => "dev",
+            EntryType::Fif => "fif",
+        }
+    }
+}
+
+/// A single line of a package's CONTENTS file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ContentsEntry {
+    /// A directory: `dir <path>`
+    Dir { path: PathBuf },
+    /// A regular file: `obj <path> <md5> <mtime>`
+    Obj { path: PathBuf, md5: String, mtime: u64 },
+    /// A symbolic link: `sym <path> -> <target> <mtime>`
+    Sym { path: PathBuf, target: PathBuf, mtime: u64 },
+    /// A device node: `dev <path>`
+    Dev { path: PathBuf },
+    /// A named pipe: `fif <path>`
+    Fif { path: PathBuf },
+}
+
+impl ContentsEntry {
+    /// Returns the installed path of the entry.
+    pub fn path(&self) -> &Path {
+        match self {
+            ContentsEntry::Dir { path }
+            | ContentsEntry::Obj { path, .. }
+            | ContentsEntry::Sym { path, .. }
+            | ContentsEntry::Dev { path }
+            | ContentsEntry::Fif { path } => path,
+        }
+    }
+
+    /// Returns the type of the entry.
+    pub fn entry_type(&self) -> EntryType {
+        match self {
+            ContentsEntry::Dir { .. } => EntryType::Dir,
+            ContentsEntry::Obj { .. } => EntryType::Obj,
+            ContentsEntry::Sym { .. } => EntryType::Sym,
+            ContentsEntry::Dev { .. } => EntryType::Dev,
+            ContentsEntry::Fif { .. } => EntryType::Fif,
+        }
+    }
+
+    /// Parses a single CONTENTS line.
+    /// Returns `None` if the line is empty or malformed.
+    pub fn parse_line(line: &str) -> Option<Self> {
+        Self::parse_line_bytes(line.as_bytes())
+    }
+
+    /// Parses a single CONTENTS line given as raw bytes.
+    /// Paths are taken over as they are, so names that are not valid UTF-8 are kept.
+    pub fn parse_line_bytes(line: &[u8]) -> Option<Self> {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let (kind, rest) = split_once(line, b" ")?;
+        if rest.is_empty() {
+            return None;
+        }
+
+        match kind {
+            b"dir" => Some(ContentsEntry::Dir { path: to_path(rest) }),
+            b"dev" => Some(ContentsEntry::Dev { path: to_path(rest) }),
+            b"fif" => Some(ContentsEntry::Fif { path: to_path(rest) }),
+            b"obj" => {
+                // The path may contain spaces, so md5 and mtime are taken from the end.
+                let (rest, mtime) = rsplit_once(rest, b" ")?;
+                let (path, md5) = rsplit_once(rest, b" ")?;
+                if path.is_empty() {
+                    return None;
+                }
+                Some(ContentsEntry::Obj {
+                    path: to_path(path),
+                    md5: std::str::from_utf8(md5).ok()?.to_string(),
+                    mtime: parse_u64(mtime)?,
+                })
+            }
+            b"sym" => {
+                let (rest, mtime) = split_sym_mtime(rest)?;
+                // Like Portage, the last " -> " separates the link from its target.
+                let (path, target) = rsplit_once(rest, b" -> ")?;
+                if path.is_empty() {
+                    return None;
+                }
+                Some(ContentsEntry::Sym {
+                    path: to_path(path),
+                    target: to_path(target),
+                    mtime,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Splits the trailing mtime off a `sym` line.
+/// Besides the plain `<mtime>`, the legacy notation is accepted, where old Portage versions
+/// wrote the whole `os.stat` tuple, e.g. `(41471, 1234, 2049, 1, 0, 0, 12L, 1100000000, 1100000000, 1100000000)`.
+/// The mtime is its 9th field.
+fn split_sym_mtime(rest: &[u8]) -> Option<(&[u8], u64)> {
+    if let Some(rest) = rest.strip_suffix(b")") {
+        let (rest, stat) = rsplit_once(rest, b" (")?;
+        let fields: Vec<&[u8]> = stat.split(|&b| b == b',').collect();
+        if fields.len() != 10 {
+            return None;
+        }
+        let mtime = fields[8].trim_ascii();
+        let mtime = mtime.strip_suffix(b"L").unwrap_or(mtime);
+        return Some((rest, parse_u64(mtime)?));
+    }
+    let (rest, mtime) = rsplit_once(rest, b" ")?;
+    Some((rest, parse_u64(mtime)?))
+}
+
+fn split_once<'a>(s: &'a [u8], sep: &[u8]) -> Option<(&'a [u8], &'a [u8])> {
+    let pos = s.windows(sep.len()).position(|w| w == sep)?;
+    Some((&s[..pos], &s[pos + sep.len()..]))
+}
+
+fn rsplit_once<'a>(s: &'a [u8], sep: &[u8]) -> Option<(&'a [u8], &'a [u8])> {
+    let pos = s.windows(sep.len()).rposition(|w| w == sep)?;
+    Some((&s[..pos], &s[pos + sep.len()..]))
+}
+
+fn parse_u64(s: &[u8]) -> Option<u64> {
+    std::str::from_utf8(s).ok()?.parse().ok()
+}
+
+fn to_path(s: &[u8]) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(s))
+}
+
+/// The parsed CONTENTS file of an installed package.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Contents {
+    entries: Vec<ContentsEntry>,
+}
+
+impl Contents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads and parses a CONTENTS file.
+    pub fn scan<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Ok(Self::parse_bytes(&fs::read(path)?))
+    }
+
+    /// Parses the content of a CONTENTS file.
+    /// Malformed lines are skipped.
+    pub fn parse(content: &str) -> Self {
+        Self::parse_bytes(content.as_bytes())
+    }
+
+    /// Parses the raw content of a CONTENTS file, keeping paths that are not valid UTF-8.
+    /// Malformed lines are skipped.
+    pub fn parse_bytes(content: &[u8]) -> Self {
+        let entries = content.split(|&b| b == b'\n').filter_map(ContentsEntry::parse_line_bytes).collect();
+        Self { entries }
+    }
+
+    /// Returns all entries in file order.
+    pub fn entries(&self) -> &[ContentsEntry] {
+        &self.entries
+    }
+
+    /// Returns an iterator over all entries.
+    pub fn iter(&self) -> std::slice::Iter<'_, ContentsEntry> {
+        self.entries.iter()
+    }
+
+    /// Returns the entry for the given path, if the package owns it.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&ContentsEntry> {
+        let path = path.as_ref();
+        self.entries.iter().find(|e| e.path() == path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<'a> IntoIterator for &'a Contents {
+    type Item = &'a ContentsEntry;
+    type IntoIter = std::slice::Iter<'a, ContentsEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_entry_types() {
+        let content = "\
+dir /usr
+dir /usr/bin
+obj /usr/bin/foo 0123456789abcdef0123456789abcdef 1700000000
+sym /usr/bin/bar -> foo 1700000001
+dev /dev/null
+fif /run/foo.fifo
+";
+        let contents = Contents::parse(content);
+        assert_eq!(contents.len(), 6);
+        assert_eq!(contents.entries()[0], ContentsEntry::Dir { path: PathBuf::from("/usr") });
+        assert_eq!(
+            contents.entries()[2],
+            ContentsEntry::Obj {
+                path: PathBuf::from("/usr/bin/foo"),
+                md5: "0123456789abcdef0123456789abcdef".to_string(),
+                mtime: 1700000000,
+            }
+        );
+        assert_eq!(
+            contents.entries()[3],
+            ContentsEntry::Sym {
+                path: PathBuf::from("/usr/bin/bar"),
+                target: PathBuf::from("foo"),
+                mtime: 1700000001,
+            }
+        );
+        assert_eq!(contents.entries()[4].entry_type(), EntryType::Dev);
+        assert_eq!(contents.entries()[5].entry_type(), EntryType::Fif);
+    }
+
+    #[test]
+    fn test_parse_paths_with_spaces() {
+        let contents = Contents::parse(
+            "dir /usr/share/My Dir\n\
+             obj /usr/share/My Dir/a file.txt d41d8cd98f00b204e9800998ecf8427e 1600000000\n\
+             sym /usr/share/My Dir/a link -> ../other dir/target file 1600000001\n",
+        );
+        assert_eq!(contents.len(), 3);
+        assert_eq!(contents.entries()[0].path(), Path::new("/usr/share/My Dir"));
+        assert_eq!(contents.entries()[1].path(), Path::new("/usr/share/My Dir/a file.txt"));
+        match &contents.entries()[2] {
+            ContentsEntry::Sym { path, target, mtime } => {
+                assert_eq!(path, Path::new("/usr/share/My Dir/a link"));
+                assert_eq!(target, Path::new("../other dir/target file"));
+                assert_eq!(*mtime, 1600000001);
+            }
+            other => panic!("unexpected entry {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_legacy_sym_mtime() {
+        let entry = ContentsEntry::parse_line(
+            "sym /lib/libfoo.so -> libfoo.so.1 (41471, 1234L, 2049L, 1, 0, 0, 12L, 1099999999, 1100000000, 1100000001)",
+        )
+        .unwrap();
+        assert_eq!(
+            entry,
+            ContentsEntry::Sym {
+                path: PathBuf::from("/lib/libfoo.so"),
+                target: PathBuf::from("libfoo.so.1"),
+                mtime: 1100000000,
+            }
+        );
+        assert!(ContentsEntry::parse_line("sym /lib/libfoo.so -> libfoo.so.1 (1100000000, 12L)").is_none());
+    }
+
+    #[test]
+    fn test_parse_non_utf8_paths() {
+        let contents = Contents::parse_bytes(b"dir /opt/caf\xe9\nobj /opt/caf\xe9/men\xfc.txt abc 1\nsym /opt/l\xe9 -> caf\xe9 2\n");
+        assert_eq!(contents.len(), 3);
+        let expected = OsStr::from_bytes(b"/opt/caf\xe9/men\xfc.txt");
+        assert_eq!(contents.entries()[1].path().as_os_str(), expected);
+        assert!(contents.get(Path::new(expected)).is_some());
+        match &contents.entries()[2] {
+            ContentsEntry::Sym { target, .. } => assert_eq!(target.as_os_str(), OsStr::from_bytes(b"caf\xe9")),
+            other => panic!("unexpected entry {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_malformed_lines() {
+        let contents = Contents::parse("\nfoo /bar\nobj /usr/bin/foo\nsym /a b 1\nobj /x abc notanumber\ndir \n");
+        assert!(contents.is_empty());
+    }
+
+    #[test]
+    fn test_get() {
+        let contents = Contents::parse("dir /etc\nobj /etc/foo.conf abc 1\n");
+        assert_eq!(contents.get("/etc/foo.conf").map(|e| e.entry_type()), Some(EntryType::Obj));
+        assert!(contents.get("/etc/bar.conf").is_none());
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+use crate::VarDbPkg;
+
+/// Packages whose NVD vendor and product differ from the Gentoo package name.
+/// `None` marks packages without a CPE.
+const BUILTIN: &[(&str, Option<(&str, &str)>)] = &[
+    ("app-admin/sudo", Some(("sudo_project", "sudo"))),
+    ("app-arch/bzip2", Some(("bzip", "bzip2"))),
+    ("app-arch/xz-utils", Some(("tukaani", "xz"))),
+    ("app-crypt/gnupg", Some(("gnupg", "gnupg"))),
+    ("app-editors/vim", Some(("vim", "vim"))),
+    ("app-shells/bash", Some(("gnu", "bash"))),
+    ("dev-db/postgresql", Some(("postgresql", "postgresql"))),
+    ("dev-db/sqlite", Some(("sqlite", "sqlite"))),
+    ("dev-lang/perl", Some(("perl", "perl"))),
+    ("dev-lang/python", Some(("python", "python"))),
+    ("dev-lang/rust", Some(("rust-lang", "rust"))),
+    ("dev-libs/expat", Some(("libexpat_project", "libexpat"))),
+    ("dev-libs/glib", Some(("gnome", "glib"))),
+    ("dev-libs/libxml2", Some(("xmlsoft", "libxml2"))),
+    ("dev-libs/openssl", Some(("openssl", "openssl"))),
+    ("dev-vcs/git", Some(("git-scm", "git"))),
+    ("media-libs/libpng", Some(("libpng", "libpng"))),
+    ("net-dns/bind", Some(("isc", "bind"))),
+    ("net-misc/curl", Some(("haxx", "curl"))),
+    ("net-misc/openssh", Some(("openbsd", "openssh"))),
+    ("sys-apps/systemd", Some(("systemd_project", "systemd"))),
+    ("sys-devel/gcc", Some(("gnu", "gcc"))),
+    ("sys-kernel/linux-firmware", None),
+    ("sys-libs/glibc", Some(("gnu", "glibc"))),
+    ("sys-libs/ncurses", Some(("gnu", "ncurses"))),
+    ("sys-libs/readline", Some(("gnu", "readline"))),
+    ("sys-libs/zlib", Some(("zlib", "zlib"))),
+    ("www-servers/apache", Some(("apache", "http_server"))),
+    ("www-servers/nginx", Some(("f5", "nginx"))),
+];
+
+/// The built-in mapping, shared by `VarDbPkg::cpe`.
+pub(crate) static BUILTIN_CPE_MAP: LazyLock<CpeMap> = LazyLock::new(CpeMap::new);
+
+/// Categories of packages that install no upstream software and therefore have no CPE.
+const NO_CPE_CATEGORIES: &[&str] = &["acct-group", "acct-user", "virtual"];
+
+/// Maps Gentoo packages to CPE vendor and product names.
+///
+/// Packages without an entry use their package name as both vendor and product,
+/// which matches many but by far not all NVD entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpeMap {
+    entries: BTreeMap<String, Option<(String, String)>>,
+}
+
+impl CpeMap {
+    /// Returns a map with the built-in entries for well-known packages.
+    pub fn new() -> Self {
+        let entries = BUILTIN
+            .iter()
+            .map(|(cp, names)| (cp.to_string(), names.map(|(v, p)| (v.to_string(), p.to_string()))))
+            .collect();
+        CpeMap { entries }
+    }
+
+    /// Returns a map without any entries.
+    pub fn empty() -> Self {
+        CpeMap { entries: BTreeMap::new() }
+    }
+
+    /// Maps `category/package` to a vendor and product, replacing an existing entry.
+    pub fn insert(&mut self, cp: &str, vendor: &str, product: &str) {
+        self.entries.insert(cp.to_string(), Some((vendor.to_string(), product.to_string())));
+    }
+
+    /// Marks `category/package` as having no CPE.
+    pub fn exclude(&mut self, cp: &str) {
+        self.entries.insert(cp.to_string(), None);
+    }
+
+    /// Adds the entries of a mapping file, one `category/package vendor product` line per package.
+    /// A `-` instead of vendor and product excludes the package. Comments and malformed lines are skipped.
+    pub fn with_overrides(mut self, content: &str) -> Self {
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [cp, "-"] => self.exclude(cp),
+                [cp, vendor, product] => self.insert(cp, vendor, product),
+                _ => {}
+            }
+        }
+        self
+    }
+
+    /// Returns the vendor and product of a package, `None` if it has no CPE.
+    pub fn lookup(&self, category: &str, package: &str) -> Option<(String, String)> {
+        match self.entries.get(&format!("{}/{}", category, package)) {
+            Some(names) => names.clone(),
+            None if NO_CPE_CATEGORIES.contains(&category) => None,
+            None => {
+                let name = package.to_ascii_lowercase();
+                Some((name.clone(), name))
+            }
+        }
+    }
+
+    /// Returns a best-effort CPE 2.3 name of an installed package, e.g.
+    /// `cpe:2.3:a:gnu:bash:5.2_p37:*:*:*:*:*:*:*`. The Gentoo revision is not part of the version.
+    pub fn cpe(&self, pkg: &VarDbPkg) -> Option<String> {
+        let (vendor, product) = self.lookup(&pkg.category, &pkg.package)?;
+        let version = match pkg.parsed_version() {
+            Ok(version) => version.without_revision().to_string(),
+            Err(_) => pkg.version.clone(),
+        };
+        Some(format!(
+            "cpe:2.3:a:{}:{}:{}:*:*:*:*:*:*:*",
+            escape(&vendor),
+            escape(&product),
+            escape(&version)
+        ))
+    }
+}
+
+impl Default for CpeMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Escapes a CPE 2.3 formatted string attribute.
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pkg(category: &str, package: &str, version: &str) -> VarDbPkg {
+        VarDbPkg {
+            category: category.to_string(),
+            package: package.to_string(),
+            version: version.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cpe() {
+        let map = CpeMap::new();
+        assert_eq!(map.cpe(&pkg("dev-libs", "openssl", "3.0.13-r2")).unwrap(), "cpe:2.3:a:openssl:openssl:3.0.13:*:*:*:*:*:*:*");
+        assert_eq!(map.cpe(&pkg("net-misc", "curl", "8.7.1")).unwrap(), "cpe:2.3:a:haxx:curl:8.7.1:*:*:*:*:*:*:*");
+        assert_eq!(map.cpe(&pkg("dev-libs", "Foo", "1.0")).unwrap(), "cpe:2.3:a:foo:foo:1.0:*:*:*:*:*:*:*");
+        assert_eq!(map.cpe(&pkg("x11-libs", "gtk+", "2.24.33-r3")).unwrap(), "cpe:2.3:a:gtk\\+:gtk\\+:2.24.33:*:*:*:*:*:*:*");
+        assert_eq!(map.cpe(&pkg("virtual", "libc", "1-r1")), None);
+        assert_eq!(map.cpe(&pkg("sys-kernel", "linux-firmware", "20240909")), None);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let map = CpeMap::new().with_overrides(
+            "# local mapping\nnet-misc/curl curl curl\nvirtual/libc gnu glibc\napp-shells/bash -\nmalformed\n",
+        );
+        assert_eq!(map.lookup("net-misc", "curl"), Some(("curl".to_string(), "curl".to_string())));
+        assert_eq!(map.lookup("virtual", "libc"), Some(("gnu".to_string(), "glibc".to_string())));
+        assert_eq!(map.lookup("app-shells", "bash"), None);
+        assert_eq!(CpeMap::empty().lookup("app-shells", "bash"), Some(("bash".to_string(), "bash".to_string())));
+    }
+}
@@ -0,0 +1,412 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use crate::atom::PackageAtom;
+use crate::useflags::is_valid_flag;
+
+/// Error returned when a dependency specification cannot be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDepError {
+    reason: String,
+}
+
+impl ParseDepError {
+    pub(crate) fn new<S: Into<String>>(reason: S) -> Self {
+        Self { reason: reason.into() }
+    }
+}
+
+impl fmt::Display for ParseDepError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid dependency specification: {}", self.reason)
+    }
+}
+
+impl std::error::Error for ParseDepError {}
+
+/// The dependency classes recorded in the vardb.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DepKind {
+    Depend,
+    Rdepend,
+    Bdepend,
+    Pdepend,
+    Idepend,
+}
+
+impl DepKind {
+    pub const ALL: [DepKind; 5] = [
+        DepKind::Depend,
+        DepKind::Rdepend,
+        DepKind::Bdepend,
+        DepKind::Pdepend,
+        DepKind::Idepend,
+    ];
+
+    /// Returns the name of the vardb file holding this class.
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            DepKind::Depend => "DEPEND",
+            DepKind::Rdepend => "RDEPEND",
+            DepKind::Bdepend => "BDEPEND",
+            DepKind::Pdepend => "PDEPEND",
+            DepKind::Idepend => "IDEPEND",
+        }
+    }
+}
+
+/// A node of a dependency specification tree.
+///
+/// The tree is generic over its leaves so that the same grammar can be used for
+/// package atoms and other specifications such as LICENSE.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DepSpec<T = PackageAtom> {
+    /// A single item, e.g. an atom.
+    Leaf(T),
+    /// All children are required: `( a b )` or the top level.
+    AllOf(Vec<DepSpec<T>>),
+    /// At least one child is required: `|| ( a b )`.
+    AnyOf(Vec<DepSpec<T>>),
+    /// Children apply only if the flag is enabled (`flag? ( )`) or disabled (`!flag? ( )`).
+    UseConditional {
+        flag: String,
+        negated: bool,
+        children: Vec<DepSpec<T>>,
+    },
+}
+
+impl DepSpec<PackageAtom> {
+    /// Parses a dependency specification of package atoms.
+    /// The result is always an `AllOf` node holding the top-level items.
+    pub fn parse(s: &str) -> Result<Self, ParseDepError> {
+        Self::parse_with(s, |token| PackageAtom::parse(token).map_err(|e| ParseDepError::new(e.to_string())))
+    }
+}
+
+impl<T> DepSpec<T> {
+    /// Parses a specification using `leaf` to parse the individual items.
+    pub fn parse_with<F>(s: &str, mut leaf: F) -> Result<Self, ParseDepError>
+    where
+        F: FnMut(&str) -> Result<T, ParseDepError>,
+    {
+        let children = parse_tree(s, &mut |node| match node {
+            Node::Leaf(token) => Ok(DepSpec::Leaf(leaf(token)?)),
+            Node::Group(Group::All, children) => Ok(DepSpec::AllOf(children)),
+            Node::Group(Group::Any, children) => Ok(DepSpec::AnyOf(children)),
+            Node::Group(group, _) => Err(ParseDepError::new(format!("unexpected {:?} group", group.operator()))),
+            Node::Conditional { flag, negated, children } => Ok(DepSpec::UseConditional {
+                flag: flag.to_string(),
+                negated,
+                children,
+            }),
+        })?;
+        Ok(DepSpec::AllOf(children))
+    }
+
+    /// Resolves all USE conditionals with the given flag state.
+    /// The result contains only `AllOf`, `AnyOf` and `Leaf` nodes.
+    pub fn evaluate<F>(&self, is_enabled: F) -> DepSpec<T>
+    where
+        F: Fn(&str) -> bool,
+        T: Clone,
+    {
+        self.evaluate_ref(&is_enabled)
+    }
+
+    fn evaluate_ref<F>(&self, is_enabled: &F) -> DepSpec<T>
+    where
+        F: Fn(&str) -> bool,
+        T: Clone,
+    {
+        match self {
+            DepSpec::Leaf(item) => DepSpec::Leaf(item.clone()),
+            DepSpec::AllOf(children) => DepSpec::AllOf(evaluate_children(children, is_enabled, false)),
+            DepSpec::AnyOf(children) => DepSpec::AnyOf(evaluate_children(children, is_enabled, true)),
+            DepSpec::UseConditional { flag, negated, children } => {
+                if is_enabled(flag) != *negated {
+                    DepSpec::AllOf(evaluate_children(children, is_enabled, false))
+                } else {
+                    DepSpec::AllOf(Vec::new())
+                }
+            }
+        }
+    }
+
+    /// Returns all leaves of the tree, regardless of groups and conditionals.
+    pub fn leaves(&self) -> Vec<&T> {
+        let mut out = Vec::new();
+        self.collect_leaves(&mut out);
+        out
+    }
+
+    fn collect_leaves<'a>(&'a self, out: &mut Vec<&'a T>) {
+        match self {
+            DepSpec::Leaf(item) => out.push(item),
+            DepSpec::AllOf(children) | DepSpec::AnyOf(children) | DepSpec::UseConditional { children, .. } => {
+                for child in children {
+                    child.collect_leaves(out);
+                }
+            }
+        }
+    }
+
+    /// Returns true if the tree holds no leaves.
+    pub fn is_empty(&self) -> bool {
+        self.leaves().is_empty()
+    }
+}
+
+/// Evaluates children, dropping conditionals that do not apply and flattening those that do.
+/// Inside an any-of group a conditional with several children stays one alternative,
+/// so `|| ( foo? ( a b ) c )` becomes `|| ( ( a b ) c )`.
+fn evaluate_children<T: Clone, F: Fn(&str) -> bool>(
+    children: &[DepSpec<T>],
+    is_enabled: &F,
+    any_of: bool,
+) -> Vec<DepSpec<T>> {
+    let mut out = Vec::new();
+    for child in children {
+        match child {
+            DepSpec::UseConditional { flag, negated, children } => {
+                if is_enabled(flag) != *negated {
+                    let mut inner = evaluate_children(children, is_enabled, false);
+                    if any_of && inner.len() > 1 {
+                        out.push(DepSpec::AllOf(inner));
+                    } else {
+                        out.append(&mut inner);
+                    }
+                }
+            }
+            DepSpec::AllOf(inner) => {
+                let inner = evaluate_children(inner, is_enabled, false);
+                if !inner.is_empty() {
+                    out.push(DepSpec::AllOf(inner));
+                }
+            }
+            DepSpec::AnyOf(inner) => {
+                // An any-of group emptied by conditionals is satisfied and can be dropped
+                let inner = evaluate_children(inner, is_enabled, true);
+                if !inner.is_empty() {
+                    out.push(DepSpec::AnyOf(inner));
+                }
+            }
+            DepSpec::Leaf(item) => out.push(DepSpec::Leaf(item.clone())),
+        }
+    }
+    out
+}
+
+/// The group operators of dependency-like specifications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Group {
+    /// `( )`
+    All,
+    /// `|| ( )`
+    Any,
+    /// `^^ ( )`, only valid in REQUIRED_USE.
+    ExactlyOne,
+    /// `?? ( )`, only valid in REQUIRED_USE.
+    AtMostOne,
+}
+
+impl Group {
+    fn operator(self) -> &'static str {
+        match self {
+            Group::All => "(",
+            Group::Any => "||",
+            Group::ExactlyOne => "^^",
+            Group::AtMostOne => "??",
+        }
+    }
+}
+
+/// A parsed item handed to the builder of `parse_tree`, with its children already built.
+pub(crate) enum Node<'a, N> {
+    Leaf(&'a str),
+    Group(Group, Vec<N>),
+    Conditional { flag: &'a str, negated: bool, children: Vec<N> },
+}
+
+/// Parses the groups and USE conditionals shared by DEPEND, LICENSE, REQUIRED_USE and the like,
+/// leaving the leaves and the allowed groups to `build`. Returns the top-level items.
+pub(crate) fn parse_tree<'a, N, F>(s: &'a str, build: &mut F) -> Result<Vec<N>, ParseDepError>
+where
+    F: FnMut(Node<'a, N>) -> Result<N, ParseDepError>,
+{
+    parse_group(&mut s.split_whitespace(), build, false)
+}
+
+fn parse_group<'a, N, I, F>(tokens: &mut I, build: &mut F, nested: bool) -> Result<Vec<N>, ParseDepError>
+where
+    I: Iterator<Item = &'a str>,
+    F: FnMut(Node<'a, N>) -> Result<N, ParseDepError>,
+{
+    let mut children = Vec::new();
+    while let Some(token) = tokens.next() {
+        let group = match token {
+            ")" => {
+                if nested {
+                    return Ok(children);
+                }
+                return Err(ParseDepError::new("unbalanced ')'"));
+            }
+            "(" => Some(Group::All),
+            "||" => Some(Group::Any),
+            "^^" => Some(Group::ExactlyOne),
+            "??" => Some(Group::AtMostOne),
+            _ => None,
+        };
+        let node = if let Some(group) = group {
+            if group != Group::All {
+                expect_open(tokens, token)?;
+            }
+            Node::Group(group, parse_group(tokens, build, true)?)
+        } else if let Some(flag) = token.strip_suffix('?') {
+            let (flag, negated) = match flag.strip_prefix('!') {
+                Some(f) => (f, true),
+                None => (flag, false),
+            };
+            if !is_valid_flag(flag) {
+                return Err(ParseDepError::new(format!("invalid conditional {:?}", token)));
+            }
+            expect_open(tokens, token)?;
+            Node::Conditional {
+                flag,
+                negated,
+                children: parse_group(tokens, build, true)?,
+            }
+        } else {
+            Node::Leaf(token)
+        };
+        children.push(build(node)?);
+    }
+    if nested {
+        return Err(ParseDepError::new("missing ')'"));
+    }
+    Ok(children)
+}
+
+fn expect_open<'a, I: Iterator<Item = &'a str>>(tokens: &mut I, after: &str) -> Result<(), ParseDepError> {
+    match tokens.next() {
+        Some("(") => Ok(()),
+        _ => Err(ParseDepError::new(format!("expected '(' after {:?}", after))),
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for DepSpec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn join<T: fmt::Display>(f: &mut fmt::Formatter<'_>, children: &[DepSpec<T>]) -> fmt::Result {
+            for (i, child) in children.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(" ")?;
+                }
+                match child {
+                    DepSpec::AllOf(_) => write!(f, "{:#}", child)?,
+                    _ => write!(f, "{}", child)?,
+                }
+            }
+            Ok(())
+        }
+
+        match self {
+            DepSpec::Leaf(item) => write!(f, "{}", item),
+            DepSpec::AllOf(children) => {
+                // The root is written without parentheses
+                if f.alternate() {
+                    f.write_str("( ")?;
+                    join(f, children)?;
+                    f.write_str(" )")
+                } else {
+                    join(f, children)
+                }
+            }
+            DepSpec::AnyOf(children) => {
+                f.write_str("|| ( ")?;
+                join(f, children)?;
+                f.write_str(" )")
+            }
+            DepSpec::UseConditional { flag, negated, children } => {
+                write!(f, "{}{}? ( ", if *negated { "!" } else { "" }, flag)?;
+                join(f, children)?;
+                f.write_str(" )")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const RDEPEND: &str = "dev-libs/libpcre2:= || ( dev-libs/openssl:0= dev-libs/libressl ) \
+        ssl? ( >=dev-libs/openssl-1.1.1:0= ) !ssl? ( net-libs/gnutls ) \
+        ldap? ( net-nds/openldap sasl? ( dev-libs/cyrus-sasl ) )";
+
+    #[test]
+    fn test_parse_tree() {
+        let spec = DepSpec::parse(RDEPEND).unwrap();
+        let DepSpec::AllOf(children) = &spec else {
+            panic!("root must be AllOf");
+        };
+        assert_eq!(children.len(), 5);
+        assert!(matches!(&children[1], DepSpec::AnyOf(c) if c.len() == 2));
+        assert!(matches!(&children[3], DepSpec::UseConditional { flag, negated: true, .. } if flag == "ssl"));
+        assert_eq!(spec.leaves().len(), 7);
+        assert_eq!(spec.to_string(), RDEPEND.split_whitespace().collect::<Vec<_>>().join(" "));
+    }
+
+    #[test]
+    fn test_nested_all_of() {
+        let spec = DepSpec::parse("|| ( ( a/b c/d ) e/f )").unwrap();
+        assert_eq!(spec.to_string(), "|| ( ( a/b c/d ) e/f )");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for s in ["( dev-libs/foo", "dev-libs/foo )", "|| dev-libs/foo", "ssl? dev-libs/foo", "?", "foo", "^^ ( dev-libs/foo )", "-ssl? ( dev-libs/foo )"] {
+            assert!(DepSpec::parse(s).is_err(), "{} should fail", s);
+        }
+        assert!(DepSpec::parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let spec = DepSpec::parse(RDEPEND).unwrap();
+        let use_flags: HashSet<&str> = ["ssl", "sasl"].into_iter().collect();
+        let reduced = spec.evaluate(|flag| use_flags.contains(flag));
+        assert_eq!(
+            reduced.to_string(),
+            "dev-libs/libpcre2:= || ( dev-libs/openssl:0= dev-libs/libressl ) >=dev-libs/openssl-1.1.1:0="
+        );
+
+        let reduced = spec.evaluate(|flag| flag == "ldap");
+        assert_eq!(
+            reduced.to_string(),
+            "dev-libs/libpcre2:= || ( dev-libs/openssl:0= dev-libs/libressl ) net-libs/gnutls net-nds/openldap"
+        );
+    }
+
+    #[test]
+    fn test_evaluate_drops_empty_any_of() {
+        let spec = DepSpec::parse("|| ( foo? ( a/b ) bar? ( c/d ) ) e/f").unwrap();
+        assert_eq!(spec.evaluate(|_| false).to_string(), "e/f");
+        assert_eq!(spec.evaluate(|f| f == "bar").to_string(), "|| ( c/d ) e/f");
+    }
+
+    #[test]
+    fn test_evaluate_conditional_in_any_of() {
+        let spec = DepSpec::parse("|| ( foo? ( a/a b/b ) c/c bar? ( d/d ) )").unwrap();
+        let reduced = spec.evaluate(|f| f == "foo" || f == "bar");
+        assert_eq!(reduced.to_string(), "|| ( ( a/a b/b ) c/c d/d )");
+        let DepSpec::AllOf(root) = &reduced else { panic!("root must be AllOf") };
+        assert!(matches!(&root[0], DepSpec::AnyOf(alternatives) if alternatives.len() == 3));
+        assert_eq!(spec.evaluate(|_| false).to_string(), "|| ( c/c )");
+    }
+
+    #[test]
+    fn test_parse_with_custom_leaf() {
+        let spec = DepSpec::parse_with("|| ( MIT Apache-2.0 ) BSD", |t| Ok(t.to_string())).unwrap();
+        assert_eq!(spec.leaves(), ["MIT", "Apache-2.0", "BSD"]);
+    }
+}
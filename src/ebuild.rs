@@ -51,15 +51,15 @@ impl EbuildData {
             }
 
             // Ignore shell functions: blafasel() { ... }
-            if (trimmed.contains("()") && (trimmed.contains('{') || lines.peek().map_or(false, |l| l.trim().starts_with('{')))) ||
-               (trimmed.starts_with("function ") && (trimmed.contains('{') || lines.peek().map_or(false, |l| l.trim().starts_with('{')))) {
+            if (trimmed.contains("()") && (trimmed.contains('{') || lines.peek().is_some_and(|l| l.trim().starts_with('{')))) ||
+               (trimmed.starts_with("function ") && (trimmed.contains('{') || lines.peek().is_some_and(|l| l.trim().starts_with('{')))) {
                 // Simple skipping of functions (until the closing brace)
-                let mut brace_count = 0;
+                let mut brace_count: i64 = 0;
                 let mut current_line_content = trimmed.to_string();
                 
                 loop {
-                    brace_count += current_line_content.chars().filter(|&c| c == '{').count();
-                    brace_count -= current_line_content.chars().filter(|&c| c == '}').count();
+                    brace_count += current_line_content.chars().filter(|&c| c == '{').count() as i64;
+                    brace_count -= current_line_content.chars().filter(|&c| c == '}').count() as i64;
                     
                     if brace_count <= 0 && current_line_content.contains('}') {
                         break;
@@ -85,7 +85,7 @@ impl EbuildData {
                 let mut value_part = trimmed[eq_idx + 1..].trim();
                 
                 // Safety check for empty value_part length when accessing chars (though trim() handles empty)
-                if value_part.is_empty() && !lines.peek().map_or(false, |l| l.trim().starts_with('(')) {
+                if value_part.is_empty() && !lines.peek().is_some_and(|l| l.trim().starts_with('(')) {
                     data.insert(name.to_string(), String::new());
                     continue;
                 }
@@ -102,7 +102,7 @@ impl EbuildData {
 
                 let raw_value;
 
-                if value_part.starts_with('(') || (value_part.is_empty() && lines.peek().map_or(false, |l| l.trim().starts_with('('))) {
+                if value_part.starts_with('(') || (value_part.is_empty() && lines.peek().is_some_and(|l| l.trim().starts_with('('))) {
                     // Array assignment
                     let mut array_content = String::new();
                     let mut current_part = value_part.to_string();
@@ -125,13 +125,13 @@ impl EbuildData {
                             }
                         }
                     } else {
-                        if current_part.starts_with('(') {
-                            array_content.push_str(&current_part[1..]);
+                        if let Some(rest) = current_part.strip_prefix('(') {
+                            array_content.push_str(rest);
                         } else {
                             array_content.push_str(&current_part);
                         }
                         
-                        while let Some(next_line) = lines.next() {
+                        for next_line in lines.by_ref() {
                             let next_trimmed = next_line.trim();
                             if let Some(end_idx) = next_trimmed.find(')') {
                                 array_content.push(' ');
@@ -150,7 +150,7 @@ impl EbuildData {
                     let quote = value_part.chars().next().unwrap();
                     let mut quoted_content = value_part[1..].to_string();
                     
-                    while let Some(next_line) = lines.next() {
+                    for next_line in lines.by_ref() {
                         quoted_content.push(' ');
                         let next_trimmed = next_line.trim();
                         if let Some(end_idx) = next_trimmed.find(quote) {
@@ -174,17 +174,17 @@ impl EbuildData {
 
                 // Immediate resolution of self-references to support extensions
                 let mut final_value = raw_value;
-                if final_value.contains(&format!("${{{}}}", name.to_uppercase())) || final_value.contains(&format!("${}", name.to_uppercase())) {
-                    if let Some(old_val) = data.get(name) {
-                        final_value = final_value.replace(&format!("${{{}}}", name.to_uppercase()), old_val);
-                        final_value = final_value.replace(&format!("${}", name.to_uppercase()), old_val);
-                    }
+                if (final_value.contains(&format!("${{{}}}", name.to_uppercase())) || final_value.contains(&format!("${}", name.to_uppercase())))
+                    && let Some(old_val) = data.get(name)
+                {
+                    final_value = final_value.replace(&format!("${{{}}}", name.to_uppercase()), old_val);
+                    final_value = final_value.replace(&format!("${}", name.to_uppercase()), old_val);
                 }
-                if final_value.contains(&format!("${{{}}}", name.to_lowercase())) || final_value.contains(&format!("${}", name.to_lowercase())) {
-                    if let Some(old_val) = data.get(name) {
-                        final_value = final_value.replace(&format!("${{{}}}", name.to_lowercase()), old_val);
-                        final_value = final_value.replace(&format!("${}", name.to_lowercase()), old_val);
-                    }
+                if (final_value.contains(&format!("${{{}}}", name.to_lowercase())) || final_value.contains(&format!("${}", name.to_lowercase())))
+                    && let Some(old_val) = data.get(name)
+                {
+                    final_value = final_value.replace(&format!("${{{}}}", name.to_lowercase()), old_val);
+                    final_value = final_value.replace(&format!("${}", name.to_lowercase()), old_val);
                 }
 
                 data.insert(name.to_string(), final_value);
@@ -205,34 +205,34 @@ impl EbuildData {
         for _ in 0..2 {
             let mut updates = Vec::new();
             for key in &keys {
-                if let Some(value) = self.variables.get(key) {
-                    if value.contains('$') {
-                        let mut new_value = value.clone();
-                        let mut changed = false;
+                if let Some(value) = self.variables.get(key)
+                    && value.contains('$')
+                {
+                    let mut new_value = value.clone();
+                    let mut changed = false;
                         
-                        for (vname, vval) in &self.variables {
-                            // Look for ${VAR} or $VAR
-                            let patterns = vec![format!("${{{}}}", vname.to_uppercase()), format!("${}", vname.to_uppercase())];
-                            for pattern in patterns {
-                                if new_value.contains(&pattern) {
-                                    new_value = new_value.replace(&pattern, vval);
-                                    changed = true;
-                                }
+                    for (vname, vval) in &self.variables {
+                        // Look for ${VAR} or $VAR
+                        let patterns = vec![format!("${{{}}}", vname.to_uppercase()), format!("${}", vname.to_uppercase())];
+                        for pattern in patterns {
+                            if new_value.contains(&pattern) {
+                                new_value = new_value.replace(&pattern, vval);
+                                changed = true;
                             }
+                        }
                             
-                            // Also support lowercase if needed, ebuilds mostly use uppercase
-                            let patterns_lc = vec![format!("${{{}}}", vname.to_lowercase()), format!("${}", vname.to_lowercase())];
-                            for pattern in patterns_lc {
-                                if new_value.contains(&pattern) {
-                                    new_value = new_value.replace(&pattern, vval);
-                                    changed = true;
-                                }
+                        // Also support lowercase if needed, ebuilds mostly use uppercase
+                        let patterns_lc = vec![format!("${{{}}}", vname.to_lowercase()), format!("${}", vname.to_lowercase())];
+                        for pattern in patterns_lc {
+                            if new_value.contains(&pattern) {
+                                new_value = new_value.replace(&pattern, vval);
+                                changed = true;
                             }
                         }
+                    }
                         
-                        if changed {
-                            updates.push((key.clone(), new_value));
-                        }
+                    if changed {
+                        updates.push((key.clone(), new_value));
                     }
                 }
             }
@@ -300,6 +300,14 @@ mod tests {
         let _ = EbuildData::parse("function test() {");
     }
 
+    #[test]
+    fn test_unbalanced_closing_braces() {
+        // More closing than opening braces must end the function instead of underflowing
+        let content = "src_install() {\n  foo\n}}\nVAR=val";
+        let data = EbuildData::parse(content);
+        assert_eq!(data["var"], "val");
+    }
+
     #[test]
     fn test_scan_all_example_files() {
         // 1. nginx-1.29.3.ebuild
@@ -0,0 +1,462 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// The value of a shell variable.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum Value {
+    /// A plain variable, `declare -- X="..."`.
+    Scalar(String),
+    /// An indexed array, `declare -a X=([0]="..." [1]="...")`, in index order.
+    Array(Vec<String>),
+    /// An associative array, `declare -A X=([key]="..." )`.
+    Assoc(BTreeMap<String, String>),
+}
+
+/// A variable of the saved build environment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Variable {
+    /// The `declare` attribute letters, e.g. `x` or `ir`. Empty for `declare --`.
+    pub attributes: String,
+    pub value: Value,
+}
+
+impl Variable {
+    /// Returns true if the variable is exported (`declare -x`).
+    pub fn is_exported(&self) -> bool {
+        self.attributes.contains('x')
+    }
+
+    /// Returns true if the variable is read-only (`declare -r`).
+    pub fn is_readonly(&self) -> bool {
+        self.attributes.contains('r')
+    }
+
+    /// Returns the value of a scalar variable.
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.value {
+            Value::Scalar(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements of an indexed array.
+    pub fn as_array(&self) -> Option<&[String]> {
+        match &self.value {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+/// The bash environment saved after the build (`environment.bz2`).
+///
+/// Unlike `EbuildData`, which reads the ebuild, the environment holds the final
+/// values after all eclasses were sourced, plus every shell function defined.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Environment {
+    pub variables: BTreeMap<String, Variable>,
+    /// Function bodies by name, without the enclosing braces.
+    pub functions: BTreeMap<String, String>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads and parses a bzip2 compressed environment file.
+    pub fn scan<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::read(fs::File::open(path)?)
+    }
+
+    /// Decompresses and parses a bzip2 compressed environment.
+    pub fn read<R: Read>(reader: R) -> std::io::Result<Self> {
+        let mut content = Vec::new();
+        bzip2::read::MultiBzDecoder::new(reader).read_to_end(&mut content)?;
+        Ok(Self::parse(&String::from_utf8_lossy(&content)))
+    }
+
+    /// Parses the output of `declare -p` and `declare -f`, as written by Portage.
+    /// Lines that are neither a variable nor a function are skipped.
+    pub fn parse(content: &str) -> Self {
+        let mut env = Environment::new();
+        let mut rest = content;
+
+        while !rest.is_empty() {
+            let (line, _) = rest.split_once('\n').unwrap_or((rest, ""));
+
+            if let Some(declaration) = rest.strip_prefix("declare ") {
+                // The value may continue on the following lines, so parse from `rest`
+                let (attributes, assignment) = split_attributes(declaration);
+                let (variable, consumed) = parse_assignment(assignment, attributes);
+                if let Some((name, variable)) = variable {
+                    env.variables.insert(name, variable);
+                }
+                rest = skip_line(&assignment[consumed..]);
+            } else if let Some(name) = function_name(line) {
+                let (body, remaining) = function_body(skip_line(rest));
+                env.functions.insert(name.to_string(), body);
+                rest = remaining;
+            } else {
+                rest = skip_line(rest);
+            }
+        }
+
+        env
+    }
+
+    /// Returns a variable by name.
+    pub fn variable(&self, name: &str) -> Option<&Variable> {
+        self.variables.get(name)
+    }
+
+    /// Returns the value of a scalar variable.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.variables.get(name).and_then(Variable::as_str)
+    }
+
+    /// Returns the body of a function.
+    pub fn function(&self, name: &str) -> Option<&str> {
+        self.functions.get(name).map(String::as_str)
+    }
+
+    /// Returns the exported variables, i.e. the environment of the build process.
+    pub fn exported(&self) -> impl Iterator<Item = (&str, &Variable)> {
+        self.variables.iter().filter(|(_, v)| v.is_exported()).map(|(k, v)| (k.as_str(), v))
+    }
+}
+
+/// Splits `-x -a NAME=...` into the attribute letters and the assignment.
+fn split_attributes(declaration: &str) -> (String, &str) {
+    let mut attributes = String::new();
+    let mut rest = declaration.trim_start_matches(' ');
+    while let Some(flags) = rest.strip_prefix('-') {
+        let end = flags.find(char::is_whitespace).unwrap_or(flags.len());
+        attributes.extend(flags[..end].chars().filter(|c| *c != '-'));
+        rest = flags[end..].trim_start_matches(' ');
+    }
+    (attributes, rest)
+}
+
+/// Parses `NAME` or `NAME=value` and returns the variable with the number of bytes consumed.
+fn parse_assignment(input: &str, attributes: String) -> (Option<(String, Variable)>, usize) {
+    let name_len = input.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(input.len());
+    if name_len == 0 {
+        return (None, 0);
+    }
+    let name = input[..name_len].to_string();
+    let Some(value) = input[name_len..].strip_prefix('=') else {
+        let value = if attributes.contains('A') {
+            Value::Assoc(BTreeMap::new())
+        } else if attributes.contains('a') {
+            Value::Array(Vec::new())
+        } else {
+            Value::Scalar(String::new())
+        };
+        return (Some((name, Variable { attributes, value })), name_len);
+    };
+
+    let start = name_len + 1;
+    let (value, consumed) = if let Some(list) = value.strip_prefix('(') {
+        let (elements, consumed) = parse_compound(list);
+        let value = if attributes.contains('A') {
+            Value::Assoc(elements.into_iter().collect())
+        } else {
+            let mut indexed: Vec<(usize, String)> =
+                elements.into_iter().map(|(k, v)| (k.parse().unwrap_or(usize::MAX), v)).collect();
+            indexed.sort_by_key(|(i, _)| *i);
+            Value::Array(indexed.into_iter().map(|(_, v)| v).collect())
+        };
+        (value, consumed + 1)
+    } else {
+        let (word, consumed) = parse_word(value);
+        (Value::Scalar(word), consumed)
+    };
+    (Some((name, Variable { attributes, value })), start + consumed)
+}
+
+/// Parses the elements of `[key]="value" ... )` up to the closing parenthesis.
+fn parse_compound(input: &str) -> (Vec<(String, String)>, usize) {
+    let mut elements = Vec::new();
+    let mut pos = 0;
+    loop {
+        pos += input[pos..].len() - input[pos..].trim_start().len();
+        let rest = &input[pos..];
+        if rest.is_empty() {
+            return (elements, pos);
+        }
+        if rest.starts_with(')') {
+            return (elements, pos + 1);
+        }
+        let Some(subscript) = rest.strip_prefix('[') else {
+            // An element without subscript gets the next index
+            let (value, consumed) = parse_word(rest);
+            elements.push((elements.len().to_string(), value));
+            pos += consumed.max(1);
+            continue;
+        };
+        let (key, key_len) = parse_word_until(subscript, ']');
+        let after_key = &subscript[key_len..];
+        let after_key = after_key.strip_prefix(']').unwrap_or(after_key);
+        let after_eq = after_key.strip_prefix('=').unwrap_or(after_key);
+        let (value, consumed) = parse_word(after_eq);
+        pos = input.len() - after_eq.len() + consumed;
+        elements.push((key, value));
+    }
+}
+
+/// Parses a shell word, resolving quotes, up to unquoted whitespace or `)`.
+fn parse_word(input: &str) -> (String, usize) {
+    parse_word_until(input, ')')
+}
+
+fn parse_word_until(input: &str, terminator: char) -> (String, usize) {
+    let mut word = String::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(i, c)) = chars.peek() {
+        match c {
+            _ if c.is_whitespace() || c == terminator => return (word, i),
+            '"' => {
+                chars.next();
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.peek() {
+                            Some(&(_, next @ ('"' | '\\' | '$' | '`'))) => {
+                                word.push(next);
+                                chars.next();
+                            }
+                            Some(&(_, '\n')) => {
+                                chars.next();
+                            }
+                            _ => word.push('\\'),
+                        },
+                        _ => word.push(c),
+                    }
+                }
+            }
+            '\'' => {
+                chars.next();
+                for (_, c) in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                    word.push(c);
+                }
+            }
+            '$' if input[i..].starts_with("$'") => {
+                chars.next();
+                chars.next();
+                // Numeric escapes give raw bytes, which are decoded together with the text
+                let mut bytes = Vec::new();
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\'' => break,
+                        '\\' => unescape_ansi_c(&mut chars, &mut bytes),
+                        _ => push_char(&mut bytes, c),
+                    }
+                }
+                word.push_str(&String::from_utf8_lossy(&bytes));
+            }
+            '\\' => {
+                chars.next();
+                if let Some((_, c)) = chars.next() {
+                    word.push(c);
+                }
+            }
+            _ => {
+                word.push(c);
+                chars.next();
+            }
+        }
+    }
+    (word, input.len())
+}
+
+/// Decodes one backslash escape of a `$'...'` string.
+/// Like bash, `\xHH` and `\NNN` stand for single bytes rather than characters.
+fn unescape_ansi_c<I>(chars: &mut std::iter::Peekable<I>, bytes: &mut Vec<u8>)
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    let Some((_, c)) = chars.next() else {
+        bytes.push(b'\\');
+        return;
+    };
+    // Folds up to `max_digits` further digits into `value`, returning it with the number of digits read
+    let mut number = |radix: u32, mut value: u32, max_digits: usize| {
+        let mut digits = 0;
+        while digits < max_digits {
+            match chars.peek().and_then(|(_, d)| d.to_digit(radix)) {
+                Some(d) => {
+                    value = value * radix + d;
+                    digits += 1;
+                    chars.next();
+                }
+                None => break,
+            }
+        }
+        (value, digits)
+    };
+    match c {
+        'n' => bytes.push(b'\n'),
+        't' => bytes.push(b'\t'),
+        'r' => bytes.push(b'\r'),
+        'a' => bytes.push(0x07),
+        'b' => bytes.push(0x08),
+        'e' | 'E' => bytes.push(0x1b),
+        'f' => bytes.push(0x0c),
+        'v' => bytes.push(0x0b),
+        'x' => match number(16, 0, 2) {
+            (_, 0) => bytes.extend_from_slice(b"\\x"),
+            (value, _) => bytes.push(value as u8),
+        },
+        '0'..='7' => {
+            let (value, _) = number(8, c.to_digit(8).unwrap_or(0), 2);
+            // Three octal digits can exceed a byte; bash keeps the low eight bits
+            bytes.push((value & 0xff) as u8);
+        }
+        _ => push_char(bytes, c),
+    }
+}
+
+fn push_char(bytes: &mut Vec<u8>, c: char) {
+    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+/// Returns the function name if the line starts a function definition, `name () `.
+fn function_name(line: &str) -> Option<&str> {
+    let name = line.trim_end().strip_suffix("()")?.trim_end();
+    let valid = !name.is_empty() && !name.contains(char::is_whitespace) && !name.contains(['=', '"', '\'']);
+    valid.then_some(name)
+}
+
+/// Reads a function body from its opening `{` line up to the closing `}` line.
+fn function_body(input: &str) -> (String, &str) {
+    let mut rest = input;
+    if rest.split('\n').next().is_some_and(|line| line.trim() == "{") {
+        rest = skip_line(rest);
+    }
+    let mut lines = Vec::new();
+    while !rest.is_empty() {
+        let (line, _) = rest.split_once('\n').unwrap_or((rest, ""));
+        rest = skip_line(rest);
+        if line.trim_end() == "}" {
+            break;
+        }
+        lines.push(line.trim_end());
+    }
+    (lines.join("\n"), rest)
+}
+
+fn skip_line(input: &str) -> &str {
+    input.split_once('\n').map(|(_, rest)| rest).unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const SAMPLE: &str = r#"declare -x ABI="amd64"
+declare -- DESCRIPTION="The standard GNU Bourne again shell"
+declare -x CFLAGS="-O2 -pipe"
+declare -ir EAPI_NUMBER="8"
+declare -x UNSET_BUT_EXPORTED
+declare -a PATCHES=([0]="/var/tmp/portage/app-shells/bash-5.2_p37/files/bash-5.2-a.patch" [1]="b \"quoted\".patch")
+declare -A DEFAULTS=([key]="value" ["with space"]="v2" )
+declare -- MULTI="line one
+line two"
+declare -- ANSI=$'tab\there\nnewline'
+declare -- PYTHON_COMPAT=$'python3_12'
+__eapi6_src_install ()
+{
+    if [[ -f Makefile ]]; then
+        emake DESTDIR="${D}" install;
+    fi
+}
+src_configure ()
+{
+    local myconf=(--with-curses);
+    econf "${myconf[@]}"
+}
+"#;
+
+    #[test]
+    fn test_parse_variables() {
+        let env = Environment::parse(SAMPLE);
+        assert_eq!(env.get("ABI"), Some("amd64"));
+        assert!(env.variable("ABI").unwrap().is_exported());
+        assert_eq!(env.get("DESCRIPTION"), Some("The standard GNU Bourne again shell"));
+        assert_eq!(env.variable("DESCRIPTION").unwrap().attributes, "");
+        assert!(env.variable("EAPI_NUMBER").unwrap().is_readonly());
+        assert_eq!(env.get("UNSET_BUT_EXPORTED"), Some(""));
+        assert_eq!(env.get("MULTI"), Some("line one\nline two"));
+        assert_eq!(env.get("ANSI"), Some("tab\there\nnewline"));
+        assert_eq!(env.get("PYTHON_COMPAT"), Some("python3_12"));
+        assert_eq!(
+            env.variable("PATCHES").unwrap().as_array().unwrap(),
+            ["/var/tmp/portage/app-shells/bash-5.2_p37/files/bash-5.2-a.patch", "b \"quoted\".patch"]
+        );
+        let Value::Assoc(defaults) = &env.variable("DEFAULTS").unwrap().value else {
+            panic!("not an associative array");
+        };
+        assert_eq!(defaults["key"], "value");
+        assert_eq!(defaults["with space"], "v2");
+        assert_eq!(env.exported().map(|(name, _)| name).collect::<Vec<_>>(), ["ABI", "CFLAGS", "UNSET_BUT_EXPORTED"]);
+    }
+
+    #[test]
+    fn test_ansi_c_escapes() {
+        let env = Environment::parse(
+            "declare -- ONE=$'a\\1b'\ndeclare -- TWO=$'\\12'\ndeclare -- THREE=$'\\101\\0101'\ndeclare -- HEX=$'\\x41\\x\\xg'\n",
+        );
+        assert_eq!(env.get("ONE"), Some("a\u{1}b"));
+        assert_eq!(env.get("TWO"), Some("\n"));
+        assert_eq!(env.get("THREE"), Some("A\u{8}1"));
+        assert_eq!(env.get("HEX"), Some("A\\x\\xg"));
+    }
+
+    #[test]
+    fn test_ansi_c_escapes_are_bytes() {
+        let env = Environment::parse(
+            "declare -- HEX=$'caf\\xc3\\xa9'\ndeclare -- OCT=$'\\303\\251t\\303\\251'\ndeclare -- RAW=$'a\\377b'\ndeclare -- MIX=$'é\\x41'\n",
+        );
+        assert_eq!(env.get("HEX"), Some("café"));
+        assert_eq!(env.get("OCT"), Some("été"));
+        assert_eq!(env.get("RAW"), Some("a\u{fffd}b"));
+        assert_eq!(env.get("MIX"), Some("éA"));
+    }
+
+    #[test]
+    fn test_parse_functions() {
+        let env = Environment::parse(SAMPLE);
+        assert_eq!(env.functions.len(), 2);
+        assert_eq!(
+            env.function("src_configure"),
+            Some("    local myconf=(--with-curses);\n    econf \"${myconf[@]}\"")
+        );
+        assert!(env.function("__eapi6_src_install").unwrap().contains("emake DESTDIR"));
+        // Function bodies do not leak into the variables
+        assert!(env.variable("myconf").is_none());
+    }
+
+    #[test]
+    fn test_scan_bz2() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("environment.bz2");
+        let mut encoder = bzip2::write::BzEncoder::new(fs::File::create(&path).unwrap(), bzip2::Compression::fast());
+        encoder.write_all(SAMPLE.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        assert_eq!(Environment::scan(&path).unwrap(), Environment::parse(SAMPLE));
+
+        fs::write(&path, b"BZh").unwrap();
+        assert!(Environment::scan(&path).is_err());
+    }
+}
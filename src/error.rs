@@ -0,0 +1,134 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::depend::ParseDepError;
+
+/// Errors returned by the fallible parsing functions.
+#[derive(Debug)]
+pub enum Error {
+    /// The path does not exist.
+    NotFound { path: PathBuf },
+    /// The path exists but access was denied.
+    PermissionDenied { path: PathBuf },
+    /// A file is not valid UTF-8.
+    InvalidUtf8 { path: PathBuf },
+    /// A directory could not be listed.
+    UnreadableDirectory { path: PathBuf, source: io::Error },
+    /// Any other I/O error.
+    Io { path: PathBuf, source: io::Error },
+    /// A package directory name without a version.
+    InvalidPackageDir { path: PathBuf },
+    /// A file that must hold a number holds something else.
+    InvalidNumber { path: PathBuf },
+    /// A file holding a dependency specification, e.g. RDEPEND, that cannot be parsed.
+    InvalidSpec { path: PathBuf, source: ParseDepError },
+    /// A file is not in the expected format, e.g. a corrupt bzip2 stream.
+    /// A file is not in the expected format, e.g. malformed XML or JSON or a corrupt bzip2 stream.
+    InvalidFile { path: PathBuf, message: String },
+}
+
+impl Error {
+    /// Classifies an I/O error that occurred while reading a file.
+    pub(crate) fn from_io<P: AsRef<Path>>(path: P, source: io::Error) -> Self {
+        let path = path.as_ref().to_path_buf();
+        match source.kind() {
+            io::ErrorKind::NotFound => Error::NotFound { path },
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied { path },
+            io::ErrorKind::InvalidData => Error::InvalidUtf8 { path },
+            _ => Error::Io { path, source },
+        }
+    }
+
+    /// Classifies an I/O error that occurred while listing a directory.
+    pub(crate) fn from_read_dir<P: AsRef<Path>>(path: P, source: io::Error) -> Self {
+        let path = path.as_ref().to_path_buf();
+        match source.kind() {
+            io::ErrorKind::NotFound => Error::NotFound { path },
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied { path },
+            _ => Error::UnreadableDirectory { path, source },
+        }
+    }
+
+    /// Returns the path the error refers to, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::NotFound { path }
+            | Error::PermissionDenied { path }
+            | Error::InvalidUtf8 { path }
+            | Error::UnreadableDirectory { path, .. }
+            | Error::Io { path, .. }
+            | Error::InvalidPackageDir { path }
+            | Error::InvalidNumber { path }
+            | Error::InvalidSpec { path, .. }
+            | Error::InvalidFile { path, .. } => Some(path),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound { path } => write!(f, "{}: not found", path.display()),
+            Error::PermissionDenied { path } => write!(f, "{}: permission denied", path.display()),
+            Error::InvalidUtf8 { path } => write!(f, "{}: invalid UTF-8", path.display()),
+            Error::UnreadableDirectory { path, source } => {
+                write!(f, "{}: unreadable directory: {}", path.display(), source)
+            }
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::InvalidPackageDir { path } => {
+                write!(f, "{}: directory name has no version", path.display())
+            }
+            Error::InvalidNumber { path } => write!(f, "{}: not a number", path.display()),
+            Error::InvalidSpec { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::InvalidFile { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::UnreadableDirectory { source, .. } | Error::Io { source, .. } => Some(source),
+            Error::InvalidSpec { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A non-fatal problem found while scanning the vardb.
+#[derive(Debug)]
+pub struct Diagnostic {
+    /// `category/package-version` of the affected package, if the problem is package specific.
+    pub cpv: Option<String>,
+    pub error: Error,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.cpv {
+            Some(cpv) => write!(f, "{}: {}", cpv, self.error),
+            None => self.error.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_io_classification() {
+        let e = Error::from_io("/x", io::Error::from(io::ErrorKind::NotFound));
+        assert!(matches!(e, Error::NotFound { .. }));
+        let e = Error::from_io("/x", io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(e, Error::PermissionDenied { .. }));
+        let e = Error::from_io("/x", io::Error::from(io::ErrorKind::InvalidData));
+        assert!(matches!(e, Error::InvalidUtf8 { .. }));
+        let e = Error::from_read_dir("/x", io::Error::other("boom"));
+        assert!(matches!(e, Error::UnreadableDirectory { .. }));
+        assert_eq!(e.path(), Some(Path::new("/x")));
+        assert_eq!(e.to_string(), "/x: unreadable directory: boom");
+    }
+}
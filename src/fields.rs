@@ -0,0 +1,239 @@
+use bitflags::bitflags;
+use std::sync::OnceLock;
+
+bitflags! {
+    /// Selects which vardb files of a package are read.
+    ///
+    /// `category`, `package`, `version` and `path` come from the directory name
+    /// and are always available. Fields that were not selected can be loaded later
+    /// with `VarDbPkg::load`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct Fields: u64 {
+        const BUILD_TIME = 1 << 0;
+        const DESCRIPTION = 1 << 1;
+        const HOMEPAGE = 1 << 2;
+        const IUSE = 1 << 3;
+        const KEYWORDS = 1 << 4;
+        const LICENSE = 1 << 5;
+        const RDEPEND = 1 << 6;
+        const DEPEND = 1 << 7;
+        const BDEPEND = 1 << 8;
+        const PDEPEND = 1 << 9;
+        const IDEPEND = 1 << 10;
+        const REPOSITORY = 1 << 11;
+        const SLOT = 1 << 12;
+        const USE = 1 << 13;
+        const EAPI = 1 << 14;
+        const BINPKGMD5 = 1 << 15;
+        /// The ebuild, parsed with `EbuildData::scan`.
+        const EBUILD = 1 << 16;
+        const SRC_URI = 1 << 17;
+        const RESTRICT = 1 << 18;
+        const PROPERTIES = 1 << 19;
+        const REQUIRED_USE = 1 << 20;
+        const INHERITED = 1 << 21;
+        const DEFINED_PHASES = 1 << 22;
+        const IUSE_EFFECTIVE = 1 << 23;
+        const PKGUSE = 1 << 24;
+        const CHOST = 1 << 25;
+        const CBUILD = 1 << 26;
+        const CTARGET = 1 << 27;
+        const CFLAGS = 1 << 28;
+        const CXXFLAGS = 1 << 29;
+        const LDFLAGS = 1 << 30;
+        const FEATURES = 1 << 31;
+        const COUNTER = 1 << 32;
+        const SIZE = 1 << 33;
+        const BUILD_ID = 1 << 34;
+        const PF = 1 << 35;
+        /// The CATEGORY file; overrides the category taken from the directory name.
+        const CATEGORY = 1 << 36;
+        const PROVIDES = 1 << 37;
+        const REQUIRES = 1 << 38;
+        /// Whether `environment.bz2` exists.
+        const ENVIRONMENT = 1 << 39;
+        /// All files not covered by another field, see `VarDbPkg::extra`.
+        const EXTRA = 1 << 40;
+
+        /// All dependency classes.
+        const DEPENDENCIES = Self::RDEPEND.bits() | Self::DEPEND.bits() | Self::BDEPEND.bits()
+            | Self::PDEPEND.bits() | Self::IDEPEND.bits();
+        /// The compiler settings the package was built with.
+        const BUILD_FLAGS = Self::CHOST.bits() | Self::CBUILD.bits() | Self::CTARGET.bits()
+            | Self::CFLAGS.bits() | Self::CXXFLAGS.bits() | Self::LDFLAGS.bits() | Self::FEATURES.bits();
+        /// All metadata, i.e. everything but the ebuild.
+        const METADATA = Self::BUILD_TIME.bits() | Self::DESCRIPTION.bits() | Self::HOMEPAGE.bits()
+            | Self::IUSE.bits() | Self::KEYWORDS.bits() | Self::LICENSE.bits() | Self::DEPENDENCIES.bits()
+            | Self::REPOSITORY.bits() | Self::SLOT.bits() | Self::USE.bits() | Self::EAPI.bits()
+            | Self::BINPKGMD5.bits() | Self::SRC_URI.bits() | Self::RESTRICT.bits() | Self::PROPERTIES.bits()
+            | Self::REQUIRED_USE.bits() | Self::INHERITED.bits() | Self::DEFINED_PHASES.bits()
+            | Self::IUSE_EFFECTIVE.bits() | Self::PKGUSE.bits() | Self::BUILD_FLAGS.bits() | Self::COUNTER.bits()
+            | Self::SIZE.bits() | Self::BUILD_ID.bits() | Self::PF.bits() | Self::CATEGORY.bits()
+            | Self::PROVIDES.bits() | Self::REQUIRES.bits() | Self::ENVIRONMENT.bits() | Self::EXTRA.bits();
+    }
+}
+
+/// How the content of a vardb file is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    /// A single value on the first line, e.g. SLOT or COUNTER.
+    Token,
+    /// A whitespace separated list that may be wrapped over several lines, e.g. USE or RDEPEND.
+    /// Whitespace is normalized to single spaces.
+    List,
+    /// Free text, e.g. DESCRIPTION. Lines are trimmed and joined with a space.
+    Text,
+    /// Line oriented records, e.g. PROVIDES or CONTENTS. Lines are kept as they are.
+    Lines,
+}
+
+impl FileKind {
+    /// Normalizes the raw content of a file of this kind.
+    pub fn normalize(self, content: &str) -> String {
+        match self {
+            FileKind::Token => content.lines().next().map(|l| l.trim().to_string()).unwrap_or_default(),
+            FileKind::List => content.split_whitespace().collect::<Vec<_>>().join(" "),
+            FileKind::Text => content
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .collect::<Vec<_>>()
+                .join(" "),
+            FileKind::Lines => content.trim_end_matches(['\n', '\r']).to_string(),
+        }
+    }
+
+    /// Returns the kind of a vardb file by its name. Unknown files are treated as `Lines`.
+    pub fn of_file(name: &str) -> FileKind {
+        match name {
+            "BUILD_TIME" | "repository" | "SLOT" | "EAPI" | "BINPKGMD5" | "CHOST" | "CBUILD" | "CTARGET"
+            | "COUNTER" | "SIZE" | "BUILD_ID" | "PF" | "CATEGORY" => FileKind::Token,
+            "DESCRIPTION" => FileKind::Text,
+            "IUSE" | "KEYWORDS" | "LICENSE" | "RDEPEND" | "DEPEND" | "BDEPEND" | "PDEPEND" | "IDEPEND"
+            | "HOMEPAGE" | "USE" | "SRC_URI" | "RESTRICT" | "PROPERTIES" | "REQUIRED_USE" | "INHERITED"
+            | "DEFINED_PHASES" | "IUSE_EFFECTIVE" | "PKGUSE" | "CFLAGS" | "CXXFLAGS" | "LDFLAGS"
+            | "FEATURES" => FileKind::List,
+            _ => FileKind::Lines,
+        }
+    }
+}
+
+impl Fields {
+    /// Returns the vardb file name of a single field, `None` for the ebuild and for combined sets.
+    pub fn file_name(self) -> Option<&'static str> {
+        FILE_NAMES.iter().find(|(field, _)| *field == self).map(|(_, name)| *name)
+    }
+
+    /// Iterates over the single fields stored in plain vardb files, with their file names.
+    pub(crate) fn files(self) -> impl Iterator<Item = (Fields, &'static str)> {
+        FILE_NAMES.iter().copied().filter(move |(field, _)| self.contains(*field))
+    }
+}
+
+/// Returns true if the file of a package directory is covered by a field or
+/// read separately (CONTENTS, NEEDED, the ebuild, ...), i.e. is not an extra file.
+pub(crate) fn is_known_file(name: &str) -> bool {
+    FILE_NAMES.iter().any(|(_, n)| *n == name)
+        || matches!(name, "CONTENTS" | "NEEDED" | "NEEDED.ELF.2" | "environment.bz2")
+        || name.ends_with(".ebuild")
+}
+
+/// Values of single fields that were read on demand, see `VarDbPkg::value`.
+/// The slots are only allocated once the first value is read.
+#[derive(Debug, Default)]
+pub(crate) struct FieldCache(OnceLock<Box<[OnceLock<String>]>>);
+
+impl FieldCache {
+    pub(crate) fn slot(&self, field: Fields) -> &OnceLock<String> {
+        let slots = self.0.get_or_init(|| (0..=Fields::all().bits().ilog2()).map(|_| OnceLock::new()).collect());
+        &slots[field.bits().trailing_zeros() as usize]
+    }
+}
+
+const FILE_NAMES: &[(Fields, &str)] = &[
+    (Fields::BUILD_TIME, "BUILD_TIME"),
+    (Fields::DESCRIPTION, "DESCRIPTION"),
+    (Fields::HOMEPAGE, "HOMEPAGE"),
+    (Fields::IUSE, "IUSE"),
+    (Fields::KEYWORDS, "KEYWORDS"),
+    (Fields::LICENSE, "LICENSE"),
+    (Fields::RDEPEND, "RDEPEND"),
+    (Fields::DEPEND, "DEPEND"),
+    (Fields::BDEPEND, "BDEPEND"),
+    (Fields::PDEPEND, "PDEPEND"),
+    (Fields::IDEPEND, "IDEPEND"),
+    (Fields::REPOSITORY, "repository"),
+    (Fields::SLOT, "SLOT"),
+    (Fields::USE, "USE"),
+    (Fields::EAPI, "EAPI"),
+    (Fields::BINPKGMD5, "BINPKGMD5"),
+    (Fields::SRC_URI, "SRC_URI"),
+    (Fields::RESTRICT, "RESTRICT"),
+    (Fields::PROPERTIES, "PROPERTIES"),
+    (Fields::REQUIRED_USE, "REQUIRED_USE"),
+    (Fields::INHERITED, "INHERITED"),
+    (Fields::DEFINED_PHASES, "DEFINED_PHASES"),
+    (Fields::IUSE_EFFECTIVE, "IUSE_EFFECTIVE"),
+    (Fields::PKGUSE, "PKGUSE"),
+    (Fields::CHOST, "CHOST"),
+    (Fields::CBUILD, "CBUILD"),
+    (Fields::CTARGET, "CTARGET"),
+    (Fields::CFLAGS, "CFLAGS"),
+    (Fields::CXXFLAGS, "CXXFLAGS"),
+    (Fields::LDFLAGS, "LDFLAGS"),
+    (Fields::FEATURES, "FEATURES"),
+    (Fields::COUNTER, "COUNTER"),
+    (Fields::SIZE, "SIZE"),
+    (Fields::BUILD_ID, "BUILD_ID"),
+    (Fields::PF, "PF"),
+    (Fields::CATEGORY, "CATEGORY"),
+    (Fields::PROVIDES, "PROVIDES"),
+    (Fields::REQUIRES, "REQUIRES"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_names() {
+        assert_eq!(Fields::SLOT.file_name(), Some("SLOT"));
+        assert_eq!(Fields::REPOSITORY.file_name(), Some("repository"));
+        assert_eq!(Fields::EBUILD.file_name(), None);
+        assert_eq!(Fields::ENVIRONMENT.file_name(), None);
+        assert_eq!((Fields::SLOT | Fields::USE).file_name(), None);
+        assert_eq!(Fields::METADATA.files().count(), FILE_NAMES.len());
+        assert_eq!(Fields::DEPENDENCIES.files().count(), 5);
+        assert!(!Fields::METADATA.contains(Fields::EBUILD));
+        assert_eq!(Fields::METADATA | Fields::EBUILD, Fields::all());
+    }
+
+    #[test]
+    fn test_file_kinds() {
+        assert_eq!(FileKind::of_file("SLOT"), FileKind::Token);
+        assert_eq!(FileKind::of_file("RDEPEND"), FileKind::List);
+        assert_eq!(FileKind::of_file("DESCRIPTION"), FileKind::Text);
+        assert_eq!(FileKind::of_file("PROVIDES"), FileKind::Lines);
+        assert_eq!(FileKind::of_file("NEEDED.ELF.2"), FileKind::Lines);
+        for (_, name) in FILE_NAMES {
+            // Every known file besides the line oriented ones has an explicit kind
+            if !matches!(*name, "PROVIDES" | "REQUIRES") {
+                assert_ne!(FileKind::of_file(name), FileKind::Lines, "{}", name);
+            }
+        }
+
+        assert_eq!(FileKind::Token.normalize("  0/3  \nignored\n"), "0/3");
+        assert_eq!(FileKind::List.normalize("a/b\n\tc/d  e/f\n"), "a/b c/d e/f");
+        assert_eq!(FileKind::Text.normalize("  A long\n description \n\n"), "A long description");
+        assert_eq!(FileKind::Lines.normalize("x86_64: a\nx86_32: b\n"), "x86_64: a\nx86_32: b");
+        assert_eq!(FileKind::Token.normalize(""), "");
+    }
+
+    #[test]
+    fn test_is_known_file() {
+        assert!(is_known_file("COUNTER"));
+        assert!(is_known_file("CONTENTS"));
+        assert!(is_known_file("bash-5.2_p37.ebuild"));
+        assert!(!is_known_file("ASFLAGS"));
+    }
+}
@@ -0,0 +1,490 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use crate::error::Error;
+use crate::fields::Fields;
+use crate::version::Version;
+use crate::VarDbPkg;
+
+/// The comparison of a GLSA version range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeOp {
+    Eq,
+    Ge,
+    Gt,
+    Le,
+    Lt,
+    /// `rge`: the same version with a revision greater or equal, e.g. `~foo-1.0` and at least `-r2`.
+    RevGe,
+    RevGt,
+    RevLe,
+    RevLt,
+}
+
+impl RangeOp {
+    /// Parses the `range` attribute, e.g. `ge` or `rlt`.
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "eq" => RangeOp::Eq,
+            "ge" => RangeOp::Ge,
+            "gt" => RangeOp::Gt,
+            "le" => RangeOp::Le,
+            "lt" => RangeOp::Lt,
+            "rge" => RangeOp::RevGe,
+            "rgt" => RangeOp::RevGt,
+            "rle" => RangeOp::RevLe,
+            "rlt" => RangeOp::RevLt,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RangeOp::Eq => "eq",
+            RangeOp::Ge => "ge",
+            RangeOp::Gt => "gt",
+            RangeOp::Le => "le",
+            RangeOp::Lt => "lt",
+            RangeOp::RevGe => "rge",
+            RangeOp::RevGt => "rgt",
+            RangeOp::RevLe => "rle",
+            RangeOp::RevLt => "rlt",
+        }
+    }
+}
+
+/// A `<vulnerable>` or `<unaffected>` version range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionRange {
+    pub op: RangeOp,
+    /// The version, with an optional trailing `*` for `eq` ranges.
+    pub version: String,
+    /// The slot the range applies to, `None` for all slots.
+    pub slot: Option<String>,
+}
+
+impl VersionRange {
+    /// Returns true if the installed version and slot fall into the range.
+    /// Unparsable versions never match.
+    pub fn matches(&self, version: &str, slot: &str) -> bool {
+        self.matches_slot(Some(slot), false) && self.matches_version(version)
+    }
+
+    /// Returns true if the range applies to the slot. `unknown` is returned for an unknown
+    /// slot if the range is limited to a slot.
+    fn matches_slot(&self, slot: Option<&str>, unknown: bool) -> bool {
+        match (self.slot.as_deref(), slot) {
+            (None | Some("*"), _) => true,
+            (Some(_), None) => unknown,
+            (Some(range_slot), Some(slot)) => range_slot == slot.split('/').next().unwrap_or_default(),
+        }
+    }
+
+    fn matches_version(&self, version: &str) -> bool {
+        let Ok(installed) = Version::parse(version) else {
+            return false;
+        };
+        if let (RangeOp::Eq, Some(prefix)) = (self.op, self.version.strip_suffix('*')) {
+            return installed.matches_glob(prefix);
+        }
+        let Ok(range) = Version::parse(&self.version) else {
+            return false;
+        };
+        let same_version = || installed.cmp_without_revision(&range).is_eq();
+        match self.op {
+            RangeOp::Eq => installed == range,
+            RangeOp::Ge => installed >= range,
+            RangeOp::Gt => installed > range,
+            RangeOp::Le => installed <= range,
+            RangeOp::Lt => installed < range,
+            RangeOp::RevGe => same_version() && installed.revision() >= range.revision(),
+            RangeOp::RevGt => same_version() && installed.revision() > range.revision(),
+            RangeOp::RevLe => same_version() && installed.revision() <= range.revision(),
+            RangeOp::RevLt => same_version() && installed.revision() < range.revision(),
+        }
+    }
+}
+
+impl fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.op.as_str(), self.version)?;
+        if let Some(slot) = &self.slot {
+            write!(f, " (slot {})", slot)?;
+        }
+        Ok(())
+    }
+}
+
+/// A `<package>` of the `<affected>` section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlsaPackage {
+    /// `category/package`
+    pub name: String,
+    /// Architectures the advisory applies to, `["*"]` for all. Not checked by `Glsa::affects`.
+    pub arch: Vec<String>,
+    /// Whether the update can be applied automatically.
+    pub auto: bool,
+    pub vulnerable: Vec<VersionRange>,
+    pub unaffected: Vec<VersionRange>,
+}
+
+impl GlsaPackage {
+    /// Returns true if an installed package is affected: it matches a vulnerable
+    /// range and no unaffected range, like `glsa-check` decides. SLOT is read on demand;
+    /// if it cannot be read, the package is assumed to be in every vulnerable slot and in
+    /// no unaffected one.
+    pub fn affects(&self, pkg: &VarDbPkg) -> bool {
+        let cp = format!("{}/{}", pkg.category, pkg.package);
+        if cp != self.name {
+            return false;
+        }
+        let slot = pkg.value(Fields::SLOT).ok();
+        self.vulnerable.iter().any(|r| r.matches_slot(slot, true) && r.matches_version(&pkg.version))
+            && !self.unaffected.iter().any(|r| r.matches_slot(slot, false) && r.matches_version(&pkg.version))
+    }
+}
+
+/// A Gentoo Linux Security Advisory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Glsa {
+    /// The id, e.g. `202401-01`.
+    pub id: String,
+    pub title: String,
+    pub synopsis: String,
+    pub product: String,
+    pub announced: String,
+    pub revised: String,
+    /// Bug numbers in the Gentoo bug tracker.
+    pub bugs: Vec<String>,
+    /// `local` or `remote`.
+    pub access: String,
+    /// The `type` of `<impact>`, e.g. `high`.
+    pub severity: String,
+    /// The text of `<description>`, with whitespace collapsed.
+    pub description: String,
+    /// The text of `<resolution>`, with whitespace collapsed.
+    pub resolution: String,
+    /// The references, usually CVE identifiers.
+    pub references: Vec<String>,
+    pub packages: Vec<GlsaPackage>,
+}
+
+impl Glsa {
+    /// Reads and parses a GLSA XML file.
+    pub fn scan<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|e| Error::from_io(path, e))?;
+        Self::parse(&content).map_err(|e| Error::InvalidFile {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })
+    }
+
+    /// Parses the content of a GLSA XML file.
+    pub fn parse(xml: &str) -> Result<Self, ParseGlsaError> {
+        let options = roxmltree::ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        };
+        let doc = roxmltree::Document::parse_with_options(xml, options).map_err(|e| ParseGlsaError::new(e.to_string()))?;
+        let root = doc.root_element();
+        if !root.has_tag_name("glsa") {
+            return Err(ParseGlsaError::new(format!("unexpected root element <{}>", root.tag_name().name())));
+        }
+        let mut glsa = Glsa {
+            id: root.attribute("id").ok_or_else(|| ParseGlsaError::new("missing id"))?.to_string(),
+            ..Default::default()
+        };
+
+        for node in root.children().filter(|n| n.is_element()) {
+            match node.tag_name().name() {
+                "title" => glsa.title = text(node),
+                "synopsis" => glsa.synopsis = text(node),
+                "product" => glsa.product = text(node),
+                "announced" => glsa.announced = text(node),
+                "revised" => glsa.revised = text(node),
+                "bug" => glsa.bugs.push(text(node)),
+                "access" => glsa.access = text(node),
+                "impact" => glsa.severity = node.attribute("type").unwrap_or_default().to_string(),
+                "description" => glsa.description = text(node),
+                "resolution" => glsa.resolution = text(node),
+                "references" => {
+                    glsa.references = node.children().filter(|n| n.has_tag_name("uri")).map(text).collect();
+                }
+                "affected" => {
+                    for package in node.children().filter(|n| n.has_tag_name("package")) {
+                        glsa.packages.push(parse_package(package)?);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(glsa)
+    }
+
+    /// Returns true if an installed package is affected by this advisory.
+    pub fn affects(&self, pkg: &VarDbPkg) -> bool {
+        self.packages.iter().any(|p| p.affects(pkg))
+    }
+}
+
+fn parse_package(node: roxmltree::Node) -> Result<GlsaPackage, ParseGlsaError> {
+    let name = node.attribute("name").ok_or_else(|| ParseGlsaError::new("<package> without name"))?;
+    let mut package = GlsaPackage {
+        name: name.to_string(),
+        arch: node.attribute("arch").unwrap_or("*").split_whitespace().map(str::to_string).collect(),
+        auto: node.attribute("auto") == Some("yes"),
+        vulnerable: Vec::new(),
+        unaffected: Vec::new(),
+    };
+    for child in node.children().filter(|n| n.is_element()) {
+        let ranges = match child.tag_name().name() {
+            "vulnerable" => &mut package.vulnerable,
+            "unaffected" => &mut package.unaffected,
+            _ => continue,
+        };
+        let op = child.attribute("range").unwrap_or_default();
+        let op = RangeOp::parse(op).ok_or_else(|| ParseGlsaError::new(format!("{}: invalid range {:?}", name, op)))?;
+        ranges.push(VersionRange {
+            op,
+            version: text(child),
+            slot: child.attribute("slot").map(str::to_string),
+        });
+    }
+    Ok(package)
+}
+
+/// Returns the text of an element and its children, with whitespace collapsed.
+fn text(node: roxmltree::Node) -> String {
+    let text: String = node.descendants().filter(|n| n.is_text()).filter_map(|n| n.text()).collect();
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// An error returned when a GLSA cannot be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseGlsaError {
+    message: String,
+}
+
+impl ParseGlsaError {
+    fn new<S: Into<String>>(message: S) -> Self {
+        ParseGlsaError { message: message.into() }
+    }
+}
+
+impl fmt::Display for ParseGlsaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid GLSA: {}", self.message)
+    }
+}
+
+impl std::error::Error for ParseGlsaError {}
+
+/// An installed package affected by an advisory.
+#[derive(Debug, Clone, Copy)]
+pub struct Affected<'a> {
+    /// Index of the package in the slice passed to `GlsaDb::check`.
+    pub package: usize,
+    pub glsa: &'a Glsa,
+}
+
+/// A set of GLSAs, usually the `metadata/glsa` directory of the Gentoo repository.
+///
+/// ```no_run
+/// use vardbpkg::glsa::GlsaDb;
+///
+/// let packages = vardbpkg::parse_vardb("/var/db/pkg");
+/// let db = GlsaDb::scan("/var/db/repos/gentoo/metadata/glsa").unwrap();
+/// for affected in db.check(&packages) {
+///     let pkg = &packages[affected.package];
+///     println!("{} {}/{}-{}: {}", affected.glsa.id, pkg.category, pkg.package, pkg.version, affected.glsa.title);
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct GlsaDb {
+    glsas: Vec<Glsa>,
+    /// Files that could not be read or parsed.
+    pub errors: Vec<Error>,
+}
+
+impl GlsaDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads all `glsa-*.xml` files of a directory, sorted by id.
+    /// Fails only if the directory cannot be listed; invalid files are collected in `errors`.
+    pub fn scan<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        let dir = dir.as_ref();
+        let mut db = GlsaDb::new();
+        for entry in fs::read_dir(dir).map_err(|e| Error::from_read_dir(dir, e))? {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    db.errors.push(Error::from_read_dir(dir, e));
+                    continue;
+                }
+            };
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if !(name.starts_with("glsa-") && name.ends_with(".xml")) {
+                continue;
+            }
+            match Glsa::scan(&path) {
+                Ok(glsa) => db.glsas.push(glsa),
+                Err(e) => db.errors.push(e),
+            }
+        }
+        db.glsas.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(db)
+    }
+
+    pub fn insert(&mut self, glsa: Glsa) {
+        self.glsas.push(glsa);
+    }
+
+    pub fn glsas(&self) -> &[Glsa] {
+        &self.glsas
+    }
+
+    /// Returns the advisory with the given id, e.g. `202401-01`.
+    pub fn get(&self, id: &str) -> Option<&Glsa> {
+        self.glsas.iter().find(|g| g.id == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.glsas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.glsas.is_empty()
+    }
+
+    /// Returns the advisories affecting an installed package.
+    pub fn affecting<'a>(&'a self, pkg: &'a VarDbPkg) -> impl Iterator<Item = &'a Glsa> + 'a {
+        self.glsas.iter().filter(move |g| g.affects(pkg))
+    }
+
+    /// Checks installed packages against all advisories, like `glsa-check -l` listing only affected ones.
+    /// The result is ordered by package, then advisory.
+    pub fn check<'a>(&'a self, packages: &[VarDbPkg]) -> Vec<Affected<'a>> {
+        let mut affected = Vec::new();
+        for (idx, pkg) in packages.iter().enumerate() {
+            for glsa in self.glsas.iter().filter(|g| g.affects(pkg)) {
+                affected.push(Affected { package: idx, glsa });
+            }
+        }
+        affected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn testdata() -> &'static Path {
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata"))
+    }
+
+    fn pkg(version: &str, slot: &str) -> VarDbPkg {
+        VarDbPkg {
+            category: "sys-libs".to_string(),
+            package: "glibc".to_string(),
+            version: version.to_string(),
+            slot: slot.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let glsa = Glsa::scan(testdata().join("glsa/glsa-202501-01.xml")).unwrap();
+        assert_eq!(glsa.id, "202501-01");
+        assert_eq!(glsa.bugs, ["940001", "940002"]);
+        assert_eq!(glsa.severity, "high");
+        assert_eq!(glsa.description, "A flaw was found in the handling of exported functions.");
+        assert_eq!(glsa.references, ["CVE-2025-0001"]);
+        let package = &glsa.packages[0];
+        assert_eq!(package.name, "app-shells/bash");
+        assert!(package.auto);
+        assert_eq!(package.arch, ["*"]);
+        assert_eq!(package.vulnerable[0].to_string(), "lt 5.2_p40");
+
+        assert!(Glsa::parse("<advisory/>").is_err());
+        assert!(Glsa::parse("<glsa id=\"1\"><affected><package name=\"a/b\"><vulnerable range=\"xx\">1</vulnerable></package></affected></glsa>").is_err());
+    }
+
+    #[test]
+    fn test_ranges() {
+        let glsa = Glsa::scan(testdata().join("glsa/glsa-202502-03.xml")).unwrap();
+        assert!(glsa.affects(&pkg("2.40-r7", "2.2")));
+        assert!(!glsa.affects(&pkg("2.40-r8", "2.2")));
+        assert!(!glsa.affects(&pkg("2.41", "2.2")));
+        // rge: fixed in a revision of an older version
+        assert!(!glsa.affects(&pkg("2.39-r10", "2.2")));
+        assert!(!glsa.affects(&pkg("2.39-r11", "2.2")));
+        assert!(glsa.affects(&pkg("2.39-r9", "2.2")));
+        assert!(glsa.affects(&pkg("2.39", "2.2")));
+        assert!(!glsa.affects(&pkg("invalid", "2.2")));
+
+        let range = |op, version: &str, slot: Option<&str>| VersionRange {
+            op,
+            version: version.to_string(),
+            slot: slot.map(str::to_string),
+        };
+        assert!(range(RangeOp::Eq, "1.2*", None).matches("1.2.3", "0"));
+        assert!(range(RangeOp::Eq, "1.2*", None).matches("1.2_rc1", "0"));
+        assert!(!range(RangeOp::Eq, "1.2*", None).matches("1.20", "0"));
+        assert!(range(RangeOp::Eq, "1.2", None).matches("1.2-r0", "0"));
+        assert!(range(RangeOp::RevLt, "1.2-r3", None).matches("1.2-r2", "0"));
+        assert!(!range(RangeOp::RevLt, "1.2-r3", None).matches("1.1", "0"));
+        assert!(range(RangeOp::Lt, "8.3", Some("0")).matches("8.2", "0/8"));
+        assert!(!range(RangeOp::Lt, "8.3", Some("7")).matches("8.2", "0/8"));
+        assert!(range(RangeOp::Lt, "8.3", Some("*")).matches("8.2", "0/8"));
+
+        let package = GlsaPackage {
+            name: "sys-libs/glibc".to_string(),
+            arch: vec!["*".to_string()],
+            auto: true,
+            vulnerable: vec![range(RangeOp::Lt, "2.41", Some("2.2"))],
+            unaffected: vec![range(RangeOp::RevGe, "2.40-r8", Some("2.2"))],
+        };
+        assert!(!package.affects(&pkg("2.40-r9", "2.2")));
+        assert!(!package.affects(&pkg("2.40-r7", "2.3")));
+        // SLOT is read on demand, an unreadable one cannot rule out a vulnerable slot
+        let dir = tempdir().unwrap();
+        let lazy = VarDbPkg { path: dir.path().to_path_buf(), ..pkg("2.40-r9", "") };
+        fs::write(dir.path().join("SLOT"), "2.2\n").unwrap();
+        assert!(!package.affects(&lazy));
+        let lazy = VarDbPkg { path: dir.path().to_path_buf(), ..pkg("2.40-r9", "") };
+        fs::write(dir.path().join("SLOT"), b"\xff\n").unwrap();
+        assert!(package.affects(&lazy));
+    }
+
+    #[test]
+    fn test_check_testdata() {
+        let packages = crate::parse_vardb(testdata().join("vardb"));
+        let db = GlsaDb::scan(testdata().join("glsa")).unwrap();
+        assert!(db.errors.is_empty());
+        assert_eq!(db.len(), 3);
+        let affected: Vec<(&str, &str)> =
+            db.check(&packages).iter().map(|a| (packages[a.package].package.as_str(), a.glsa.id.as_str())).collect();
+        assert_eq!(affected, [("bash", "202501-01"), ("readline", "202503-05")]);
+
+        let mut readline = pkg("8.2_p13", "7");
+        readline.package = "readline".to_string();
+        assert_eq!(db.affecting(&readline).count(), 0);
+    }
+
+    #[test]
+    fn test_scan_errors() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("glsa-202401-01.xml"), "<glsa id=\"202401-01\"></glsa>").unwrap();
+        fs::write(dir.path().join("glsa-202401-02.xml"), "<glsa").unwrap();
+        fs::write(dir.path().join("README"), "not a GLSA").unwrap();
+        let db = GlsaDb::scan(dir.path()).unwrap();
+        assert_eq!(db.len(), 1);
+        assert!(matches!(&db.errors[..], [Error::InvalidFile { .. }]));
+        assert!(matches!(GlsaDb::scan(dir.path().join("missing")), Err(Error::NotFound { .. })));
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::{BTreeSet, HashMap};
+use crate::atom::PackageAtom;
+use crate::depend::{DepKind, DepSpec};
+use crate::error::Error;
+use crate::VarDbPkg;
+
+/// Dependency graph of the installed packages.
+///
+/// Nodes are indices into the package slice the graph was built from. An edge
+/// `a -> b` means that package `a` depends on the installed package `b`.
+/// Only USE-reduced dependencies are considered; for `|| ( )` groups the first
+/// alternative satisfied by an installed package is used, like Portage does.
+#[derive(Debug)]
+pub struct DepGraph<'a> {
+    packages: &'a [VarDbPkg],
+    edges: Vec<BTreeSet<usize>>,
+    reverse: Vec<BTreeSet<usize>>,
+    errors: Vec<(usize, DepKind, Error)>,
+}
+
+impl<'a> DepGraph<'a> {
+    /// Builds the runtime dependency graph from RDEPEND and PDEPEND.
+    pub fn build(packages: &'a [VarDbPkg]) -> Self {
+        Self::build_with(packages, &[DepKind::Rdepend, DepKind::Pdepend])
+    }
+
+    /// Builds the graph from the given dependency classes.
+    pub fn build_with(packages: &'a [VarDbPkg], kinds: &[DepKind]) -> Self {
+        let mut by_cp: HashMap<(&str, &str), Vec<usize>> = HashMap::new();
+        for (idx, pkg) in packages.iter().enumerate() {
+            by_cp.entry((pkg.category.as_str(), pkg.package.as_str())).or_default().push(idx);
+        }
+        let resolve = |atom: &PackageAtom| -> Vec<usize> {
+            by_cp
+                .get(&(atom.category.as_str(), atom.package.as_str()))
+                .map(|ids| ids.iter().copied().filter(|&i| atom.matches(&packages[i])).collect())
+                .unwrap_or_default()
+        };
+
+        let mut graph = DepGraph {
+            packages,
+            edges: vec![BTreeSet::new(); packages.len()],
+            reverse: vec![BTreeSet::new(); packages.len()],
+            errors: Vec::new(),
+        };
+
+        for (idx, pkg) in packages.iter().enumerate() {
+            for &kind in kinds {
+                match pkg.effective_dependencies(kind) {
+                    Ok(spec) => {
+                        let mut targets = BTreeSet::new();
+                        collect_targets(&spec, &resolve, &mut targets);
+                        for target in targets {
+                            graph.edges[idx].insert(target);
+                            graph.reverse[target].insert(idx);
+                        }
+                    }
+                    Err(e) => graph.errors.push((idx, kind, e)),
+                }
+            }
+        }
+
+        graph
+    }
+
+    /// Returns the packages the graph was built from.
+    pub fn packages(&self) -> &'a [VarDbPkg] {
+        self.packages
+    }
+
+    /// Returns the package for a node index.
+    pub fn package(&self, idx: usize) -> &'a VarDbPkg {
+        &self.packages[idx]
+    }
+
+    /// Returns the node indices of all packages matching an atom.
+    pub fn find(&self, atom: &PackageAtom) -> Vec<usize> {
+        (0..self.packages.len()).filter(|&i| atom.matches(&self.packages[i])).collect()
+    }
+
+    /// Returns the dependency specifications that could not be read or parsed.
+    pub fn errors(&self) -> &[(usize, DepKind, Error)] {
+        &self.errors
+    }
+
+    /// Returns the direct dependencies of a package.
+    pub fn dependencies(&self, idx: usize) -> impl Iterator<Item = usize> + '_ {
+        self.edges[idx].iter().copied()
+    }
+
+    /// Returns the packages directly depending on a package (`equery depends`).
+    pub fn reverse_dependencies(&self, idx: usize) -> impl Iterator<Item = usize> + '_ {
+        self.reverse[idx].iter().copied()
+    }
+
+    /// Returns everything a package pulls in transitively, excluding the package itself
+    /// unless it is part of a cycle.
+    pub fn transitive_dependencies(&self, idx: usize) -> BTreeSet<usize> {
+        walk(&self.edges, [idx])
+    }
+
+    /// Returns all packages depending on a package directly or indirectly.
+    pub fn transitive_reverse_dependencies(&self, idx: usize) -> BTreeSet<usize> {
+        walk(&self.reverse, [idx])
+    }
+
+    /// Returns all packages reachable from the given roots, including the roots.
+    pub fn reachable(&self, roots: &[usize]) -> BTreeSet<usize> {
+        let mut reached = walk(&self.edges, roots.iter().copied());
+        reached.extend(roots.iter().copied());
+        reached
+    }
+
+    /// Returns all packages not reachable from the given roots (e.g. the world set).
+    /// These are the candidates `emerge --depclean` would remove.
+    pub fn unreachable(&self, roots: &[usize]) -> Vec<usize> {
+        let reached = self.reachable(roots);
+        (0..self.packages.len()).filter(|i| !reached.contains(i)).collect()
+    }
+
+    /// Returns the strongly connected components with more than one package
+    /// or a self-dependency, i.e. the dependency cycles.
+    pub fn cycles(&self) -> Vec<Vec<usize>> {
+        self.strongly_connected_components()
+            .into_iter()
+            .filter(|scc| scc.len() > 1 || self.edges[scc[0]].contains(&scc[0]))
+            .collect()
+    }
+
+    /// Returns all packages ordered so that dependencies come before their dependents.
+    /// Packages in a cycle are kept together in an arbitrary but stable order.
+    pub fn topological_order(&self) -> Vec<usize> {
+        self.strongly_connected_components().into_iter().flatten().collect()
+    }
+
+    /// Tarjan's algorithm, iterative to cope with long dependency chains.
+    /// Components are returned with dependencies first.
+    fn strongly_connected_components(&self) -> Vec<Vec<usize>> {
+        let n = self.packages.len();
+        let mut index = vec![usize::MAX; n];
+        let mut lowlink = vec![0; n];
+        let mut on_stack = vec![false; n];
+        let mut stack = Vec::new();
+        let mut components = Vec::new();
+        let mut next_index = 0;
+
+        for start in 0..n {
+            if index[start] != usize::MAX {
+                continue;
+            }
+            let mut call_stack: Vec<(usize, Vec<usize>)> = Vec::new();
+            index[start] = next_index;
+            lowlink[start] = next_index;
+            next_index += 1;
+            stack.push(start);
+            on_stack[start] = true;
+            call_stack.push((start, self.edges[start].iter().rev().copied().collect()));
+
+            while let Some((node, pending)) = call_stack.last_mut() {
+                let node = *node;
+                if let Some(next) = pending.pop() {
+                    if index[next] == usize::MAX {
+                        index[next] = next_index;
+                        lowlink[next] = next_index;
+                        next_index += 1;
+                        stack.push(next);
+                        on_stack[next] = true;
+                        call_stack.push((next, self.edges[next].iter().rev().copied().collect()));
+                    } else if on_stack[next] {
+                        lowlink[node] = lowlink[node].min(index[next]);
+                    }
+                    continue;
+                }
+
+                call_stack.pop();
+                if let Some((parent, _)) = call_stack.last() {
+                    lowlink[*parent] = lowlink[*parent].min(lowlink[node]);
+                }
+                if lowlink[node] == index[node] {
+                    let mut component = Vec::new();
+                    while let Some(member) = stack.pop() {
+                        on_stack[member] = false;
+                        component.push(member);
+                        if member == node {
+                            break;
+                        }
+                    }
+                    component.sort_unstable();
+                    components.push(component);
+                }
+            }
+        }
+
+        components
+    }
+}
+
+/// Collects the installed packages satisfying a USE-reduced dependency tree.
+fn collect_targets<F>(spec: &DepSpec, resolve: &F, out: &mut BTreeSet<usize>)
+where
+    F: Fn(&PackageAtom) -> Vec<usize>,
+{
+    match spec {
+        DepSpec::Leaf(atom) => {
+            if atom.blocker.is_none() {
+                out.extend(resolve(atom));
+            }
+        }
+        DepSpec::AllOf(children) | DepSpec::UseConditional { children, .. } => {
+            for child in children {
+                collect_targets(child, resolve, out);
+            }
+        }
+        DepSpec::AnyOf(children) => {
+            for child in children {
+                let mut candidate = BTreeSet::new();
+                collect_targets(child, resolve, &mut candidate);
+                if !candidate.is_empty() {
+                    out.extend(candidate);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn walk<I: IntoIterator<Item = usize>>(edges: &[BTreeSet<usize>], start: I) -> BTreeSet<usize> {
+    let mut seen = BTreeSet::new();
+    let mut todo: Vec<usize> = start.into_iter().flat_map(|s| edges[s].iter().copied()).collect();
+    while let Some(node) = todo.pop() {
+        if seen.insert(node) {
+            todo.extend(edges[node].iter().copied());
+        }
+    }
+    seen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::split_package_version;
+
+    fn pkg(cpv: &str, usepkg: &str, rdepend: &str, pdepend: &str) -> VarDbPkg {
+        let (category, rest) = cpv.split_once('/').unwrap();
+        let (package, version) = split_package_version(rest);
+        VarDbPkg {
+            category: category.to_string(),
+            package,
+            version,
+            slot: "0".to_string(),
+            usepkg: usepkg.to_string(),
+            rdepend: rdepend.to_string(),
+            pdepend: pdepend.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn sample() -> Vec<VarDbPkg> {
+        vec![
+            /* 0 */ pkg("sys-libs/zlib-1.3.1", "", "", ""),
+            /* 1 */ pkg("dev-libs/openssl-3.3.2", "", "sys-libs/zlib", ""),
+            /* 2 */ pkg("net-misc/curl-8.11.1", "ssl", "ssl? ( dev-libs/openssl:0= ) gnutls? ( net-libs/gnutls )", ""),
+            /* 3 */ pkg("net-libs/gnutls-3.8.8", "", "", ""),
+            /* 4 */ pkg("app-misc/a-1", "", "|| ( app-misc/missing net-libs/gnutls dev-libs/openssl ) !app-misc/b", ""),
+            /* 5 */ pkg("app-misc/b-1", "", "app-misc/c", ""),
+            /* 6 */ pkg("app-misc/c-1", "", "", "app-misc/b"),
+        ]
+    }
+
+    #[test]
+    fn test_direct_and_reverse_dependencies() {
+        let packages = sample();
+        let graph = DepGraph::build(&packages);
+        assert_eq!(graph.dependencies(2).collect::<Vec<_>>(), [1]);
+        assert_eq!(graph.dependencies(4).collect::<Vec<_>>(), [3]);
+        assert_eq!(graph.reverse_dependencies(1).collect::<Vec<_>>(), [2]);
+        assert_eq!(graph.reverse_dependencies(3).collect::<Vec<_>>(), [4]);
+        assert!(graph.errors().is_empty());
+    }
+
+    #[test]
+    fn test_transitive() {
+        let packages = sample();
+        let graph = DepGraph::build(&packages);
+        assert_eq!(graph.transitive_dependencies(2).into_iter().collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(graph.transitive_reverse_dependencies(0).into_iter().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(graph.unreachable(&[2]), [3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_cycles_and_topological_order() {
+        let packages = sample();
+        let graph = DepGraph::build(&packages);
+        assert_eq!(graph.cycles(), vec![vec![5, 6]]);
+
+        let order = graph.topological_order();
+        assert_eq!(order.len(), packages.len());
+        let pos = |i: usize| order.iter().position(|&x| x == i).unwrap();
+        assert!(pos(0) < pos(1));
+        assert!(pos(1) < pos(2));
+        assert!(pos(3) < pos(4));
+    }
+
+    #[test]
+    fn test_find_and_errors() {
+        let mut packages = sample();
+        packages.push(pkg("app-misc/broken-1", "", "|| app-misc/a", ""));
+        let graph = DepGraph::build(&packages);
+        assert_eq!(graph.find(&PackageAtom::parse(">=dev-libs/openssl-3").unwrap()), [1]);
+        assert_eq!(graph.errors().len(), 1);
+        assert_eq!(graph.errors()[0].0, 7);
+    }
+}
@@ -1,15 +1,54 @@
+pub mod atom;
+pub mod contents;
+pub mod cpe;
+pub mod depend;
 pub mod ebuild;
+#[cfg(feature = "environment")]
+pub mod environment;
+pub mod error;
+pub mod fields;
+#[cfg(feature = "glsa")]
+pub mod glsa;
+pub mod graph;
+pub mod license;
+pub mod linkage;
+pub mod orphan;
+pub mod osv;
+pub mod owner;
+pub mod purl;
+pub mod required_use;
+pub mod sbom;
+pub mod useflags;
+pub mod vardb;
+pub mod verify;
+pub mod version;
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::contents::Contents;
+use crate::cpe::BUILTIN_CPE_MAP;
+use crate::depend::{DepKind, DepSpec, ParseDepError};
 use crate::ebuild::EbuildData;
+use crate::error::{Diagnostic, Error};
+use crate::fields::{FieldCache, FileKind, Fields};
+use crate::license::{parse_license, LicenseSpec};
+use crate::linkage::{NeededElf, Sonames};
+use crate::required_use::{RequiredUse, Violation};
+use crate::useflags::UseFlags;
+use crate::vardb::{PackageEntry, VarDb};
+use crate::version::{ParseVersionError, Version};
 
 /// Represents a package in the Gentoo vardb.
 #[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct VarDbPkg {
     pub category: String,
     pub package: String,
     pub version: String,
+    /// The raw BUILD_TIME; `build_time` returns it as a timestamp.
     pub buildtime: String,
     pub description: String,
     pub homepage: String,
@@ -17,100 +56,644 @@ pub struct VarDbPkg {
     pub keywords: String,
     pub license: String,
     pub rdepend: String,
+    pub depend: String,
+    pub bdepend: String,
+    pub pdepend: String,
+    pub idepend: String,
     pub repository: String,
     pub slot: String,
     pub usepkg: String,
     pub eapi: String,
     pub binpkgmd5: String,
+    pub src_uri: String,
+    pub restrict: String,
+    pub properties: String,
+    pub required_use: String,
+    pub inherited: String,
+    pub defined_phases: String,
+    pub iuse_effective: String,
+    pub pkguse: String,
+    pub chost: String,
+    pub cbuild: String,
+    pub ctarget: String,
+    pub cflags: String,
+    pub cxxflags: String,
+    pub ldflags: String,
+    pub features: String,
+    /// Installation counter, increasing with every merge.
+    /// Use `counter` to read it on demand.
+    pub counter: Option<u64>,
+    /// Total size of the installed files in bytes.
+    pub size: Option<u64>,
+    /// Build id of the binary package the package was installed from.
+    pub build_id: Option<u64>,
+    /// `package-version`, as written by Portage.
+    pub pf: String,
+    pub provides: String,
+    pub requires: String,
+    /// Whether the saved build environment `environment.bz2` exists.
+    pub has_environment: bool,
+    /// Files of the package directory not covered by any other field, by file name.
+    pub extra: BTreeMap<String, String>,
     pub ebuild_data: EbuildData,
+    /// Path of the package directory inside the vardb.
+    pub path: PathBuf,
+    /// The fields read from the vardb so far.
+    #[serde(skip)]
+    pub loaded_fields: Fields,
+    #[serde(skip)]
+    cache: FieldCache,
 }
 
-/// Parses the entire vardb at the given path.
-/// Typically this is `/var/db/pkg`.
-pub fn parse_vardb<P: AsRef<Path>>(path: P) -> Vec<VarDbPkg> {
-    let mut packages = Vec::new();
-    let root = path.as_ref();
-
-    if let Ok(entries) = fs::read_dir(root) {
-        for entry in entries.flatten() {
-            let category_path = entry.path();
-            if category_path.is_dir() {
-                let category_name = entry.file_name().to_string_lossy().into_owned();
-
-                if let Ok(pkg_entries) = fs::read_dir(&category_path) {
-                    for pkg_entry in pkg_entries.flatten() {
-                        let pkg_path = pkg_entry.path();
-                        if pkg_path.is_dir() {
-                            let pkg_dir_name = pkg_entry.file_name().to_string_lossy().into_owned();
-                            if let Some(pkg_info) =
-                                parse_package_dir(&category_name, &pkg_dir_name, &pkg_path)
-                            {
-                                packages.push(pkg_info);
-                            }
+impl VarDbPkg {
+    /// Reads the given fields from the package directory unless they are already loaded.
+    /// All fields are attempted; only the first error is returned and later ones are dropped.
+    /// Fields that failed to read stay unloaded.
+    pub fn load(&mut self, fields: Fields) -> error::Result<()> {
+        let mut errors = Vec::new();
+        self.read_fields(fields - self.loaded_fields, &mut errors);
+        match errors.into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the value of a single text field, reading it on first access.
+    /// Returns `Error::InvalidSpec` for numeric and boolean fields, the ebuild and combined sets;
+    /// use the struct fields instead.
+    pub fn field(&mut self, field: Fields) -> error::Result<&str> {
+        if self.field_value(field).is_none() {
+            return Err(Error::InvalidSpec {
+                path: self.path.clone(),
+                source: ParseDepError::new(format!("{field:?} is not a text field")),
+            });
+        }
+        self.load(field)?;
+        Ok(self.field_value(field).unwrap_or_default())
+    }
+
+    /// Returns the parsed ebuild, reading it on first access.
+    pub fn ebuild(&mut self) -> error::Result<&EbuildData> {
+        self.load(Fields::EBUILD)?;
+        Ok(&self.ebuild_data)
+    }
+
+    /// Reads all files not covered by another field into `extra`.
+    fn read_extra_files(&mut self, errors: &mut Vec<Error>) -> error::Result<()> {
+        let entries = fs::read_dir(&self.path).map_err(|e| Error::from_read_dir(&self.path, e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| Error::from_read_dir(&self.path, e))?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if fields::is_known_file(&name) || !entry.path().is_file() {
+                continue;
+            }
+            match read_value(entry.path(), FileKind::of_file(&name)) {
+                Ok(value) => {
+                    self.extra.insert(name, value.unwrap_or_default());
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn field_value(&self, field: Fields) -> Option<&str> {
+        let value = match field {
+            Fields::BUILD_TIME => &self.buildtime,
+            Fields::DESCRIPTION => &self.description,
+            Fields::HOMEPAGE => &self.homepage,
+            Fields::IUSE => &self.iuse,
+            Fields::KEYWORDS => &self.keywords,
+            Fields::LICENSE => &self.license,
+            Fields::RDEPEND => &self.rdepend,
+            Fields::DEPEND => &self.depend,
+            Fields::BDEPEND => &self.bdepend,
+            Fields::PDEPEND => &self.pdepend,
+            Fields::IDEPEND => &self.idepend,
+            Fields::REPOSITORY => &self.repository,
+            Fields::SLOT => &self.slot,
+            Fields::USE => &self.usepkg,
+            Fields::EAPI => &self.eapi,
+            Fields::BINPKGMD5 => &self.binpkgmd5,
+            Fields::SRC_URI => &self.src_uri,
+            Fields::RESTRICT => &self.restrict,
+            Fields::PROPERTIES => &self.properties,
+            Fields::REQUIRED_USE => &self.required_use,
+            Fields::INHERITED => &self.inherited,
+            Fields::DEFINED_PHASES => &self.defined_phases,
+            Fields::IUSE_EFFECTIVE => &self.iuse_effective,
+            Fields::PKGUSE => &self.pkguse,
+            Fields::CHOST => &self.chost,
+            Fields::CBUILD => &self.cbuild,
+            Fields::CTARGET => &self.ctarget,
+            Fields::CFLAGS => &self.cflags,
+            Fields::CXXFLAGS => &self.cxxflags,
+            Fields::LDFLAGS => &self.ldflags,
+            Fields::FEATURES => &self.features,
+            Fields::PF => &self.pf,
+            Fields::PROVIDES => &self.provides,
+            Fields::REQUIRES => &self.requires,
+            Fields::CATEGORY => &self.category,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn field_mut(&mut self, field: Fields) -> Option<&mut String> {
+        let value = match field {
+            Fields::BUILD_TIME => &mut self.buildtime,
+            Fields::DESCRIPTION => &mut self.description,
+            Fields::HOMEPAGE => &mut self.homepage,
+            Fields::IUSE => &mut self.iuse,
+            Fields::KEYWORDS => &mut self.keywords,
+            Fields::LICENSE => &mut self.license,
+            Fields::RDEPEND => &mut self.rdepend,
+            Fields::DEPEND => &mut self.depend,
+            Fields::BDEPEND => &mut self.bdepend,
+            Fields::PDEPEND => &mut self.pdepend,
+            Fields::IDEPEND => &mut self.idepend,
+            Fields::REPOSITORY => &mut self.repository,
+            Fields::SLOT => &mut self.slot,
+            Fields::USE => &mut self.usepkg,
+            Fields::EAPI => &mut self.eapi,
+            Fields::BINPKGMD5 => &mut self.binpkgmd5,
+            Fields::SRC_URI => &mut self.src_uri,
+            Fields::RESTRICT => &mut self.restrict,
+            Fields::PROPERTIES => &mut self.properties,
+            Fields::REQUIRED_USE => &mut self.required_use,
+            Fields::INHERITED => &mut self.inherited,
+            Fields::DEFINED_PHASES => &mut self.defined_phases,
+            Fields::IUSE_EFFECTIVE => &mut self.iuse_effective,
+            Fields::PKGUSE => &mut self.pkguse,
+            Fields::CHOST => &mut self.chost,
+            Fields::CBUILD => &mut self.cbuild,
+            Fields::CTARGET => &mut self.ctarget,
+            Fields::CFLAGS => &mut self.cflags,
+            Fields::CXXFLAGS => &mut self.cxxflags,
+            Fields::LDFLAGS => &mut self.ldflags,
+            Fields::FEATURES => &mut self.features,
+            Fields::PF => &mut self.pf,
+            Fields::PROVIDES => &mut self.provides,
+            Fields::REQUIRES => &mut self.requires,
+            Fields::CATEGORY => &mut self.category,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Returns the value of a single metadata field for the derived accessors.
+    /// A field that was neither loaded nor set is read from the package directory on first
+    /// access and cached, so the accessors also work for packages read with a partial `Fields` mask.
+    pub(crate) fn value(&self, field: Fields) -> error::Result<&str> {
+        let value = self.field_value(field).unwrap_or_default();
+        if self.loaded_fields.contains(field) || !value.is_empty() || self.path.as_os_str().is_empty() {
+            return Ok(value);
+        }
+        let Some(name) = field.file_name() else {
+            return Ok(value);
+        };
+        let slot = self.cache.slot(field);
+        if let Some(cached) = slot.get() {
+            return Ok(cached);
+        }
+        let read = read_value(self.path.join(name), FileKind::of_file(name))?.unwrap_or_default();
+        Ok(slot.get_or_init(|| read))
+    }
+
+    /// Reads the given fields, collecting errors. Fields that failed stay unloaded.
+    fn read_fields(&mut self, fields: Fields, errors: &mut Vec<Error>) {
+        for (field, name) in fields.files() {
+            let path = self.path.join(name);
+            let value = match read_value(&path, FileKind::of_file(name)) {
+                Ok(value) => value,
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            };
+            match field {
+                Fields::COUNTER | Fields::SIZE | Fields::BUILD_ID => {
+                    let number = match value.as_deref().map(str::parse::<u64>) {
+                        Some(Ok(number)) => Some(number),
+                        Some(Err(_)) => {
+                            errors.push(Error::InvalidNumber { path });
+                            None
                         }
+                        None => None,
+                    };
+                    match field {
+                        Fields::COUNTER => self.counter = number,
+                        Fields::SIZE => self.size = number,
+                        _ => self.build_id = number,
+                    }
+                }
+                Fields::BUILD_TIME => {
+                    let value = value.unwrap_or_default();
+                    if !value.is_empty() && value.parse::<u64>().is_err() {
+                        errors.push(Error::InvalidNumber { path });
                     }
+                    self.buildtime = value;
+                }
+                // The directory name already gives the category
+                Fields::CATEGORY if value.as_deref().is_none_or(str::is_empty) => {}
+                _ => {
+                    if let Some(slot) = self.field_mut(field) {
+                        *slot = value.unwrap_or_default();
+                    }
+                }
+            }
+            self.loaded_fields |= field;
+        }
+
+        if fields.contains(Fields::ENVIRONMENT) {
+            self.has_environment = self.path.join("environment.bz2").is_file();
+            self.loaded_fields |= Fields::ENVIRONMENT;
+        }
+
+        if fields.contains(Fields::EXTRA) {
+            match self.read_extra_files(errors) {
+                Ok(()) => self.loaded_fields |= Fields::EXTRA,
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if fields.contains(Fields::EBUILD) {
+            let ebuild_filename = format!("{}-{}.ebuild", self.package, self.version);
+            let ebuild_path = self.path.join(ebuild_filename);
+            match EbuildData::scan(&ebuild_path) {
+                Ok(ebuild_data) => {
+                    self.ebuild_data = ebuild_data;
+                    self.loaded_fields |= Fields::EBUILD;
                 }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => self.loaded_fields |= Fields::EBUILD,
+                Err(e) => errors.push(Error::from_io(&ebuild_path, e)),
+            }
+        }
+    }
+
+    /// Reads and parses the CONTENTS file of this package.
+    /// The file list is not loaded by `parse_vardb` since it can be large.
+    pub fn contents(&self) -> error::Result<Contents> {
+        let path = self.path.join("CONTENTS");
+        Contents::scan(&path).map_err(|e| Error::from_io(path, e))
+    }
+
+    /// Reads and parses the NEEDED.ELF.2 file of this package.
+    pub fn needed(&self) -> error::Result<NeededElf> {
+        let path = self.path.join("NEEDED.ELF.2");
+        NeededElf::scan(&path).map_err(|e| Error::from_io(path, e))
+    }
+
+    /// Returns the sonames this package provides, parsed from PROVIDES.
+    pub fn provided_sonames(&self) -> error::Result<Sonames> {
+        Ok(Sonames::parse(self.value(Fields::PROVIDES)?))
+    }
+
+    /// Returns the sonames this package links against, parsed from REQUIRES.
+    pub fn required_sonames(&self) -> error::Result<Sonames> {
+        Ok(Sonames::parse(self.value(Fields::REQUIRES)?))
+    }
+
+    /// Reads and decodes the saved build environment, `environment.bz2`.
+    /// A file that cannot be decompressed is reported as `Error::InvalidFile`.
+    #[cfg(feature = "environment")]
+    pub fn environment(&self) -> error::Result<environment::Environment> {
+        let path = self.path.join("environment.bz2");
+        let file = fs::File::open(&path).map_err(|e| Error::from_io(&path, e))?;
+        environment::Environment::read(file).map_err(|e| Error::InvalidFile { message: e.to_string(), path })
+    }
+
+    /// Returns the raw dependency string of the given class.
+    ///
+    /// This and the other accessors deriving data from fields read a field that was not
+    /// loaded on first use; an unreadable file is reported as an error.
+    pub fn dependency_string(&self, kind: DepKind) -> error::Result<&str> {
+        self.value(match kind {
+            DepKind::Depend => Fields::DEPEND,
+            DepKind::Rdepend => Fields::RDEPEND,
+            DepKind::Bdepend => Fields::BDEPEND,
+            DepKind::Pdepend => Fields::PDEPEND,
+            DepKind::Idepend => Fields::IDEPEND,
+        })
+    }
+
+    /// Parses the dependency specification of the given class into a tree.
+    pub fn dependencies(&self, kind: DepKind) -> error::Result<DepSpec> {
+        DepSpec::parse(self.dependency_string(kind)?).map_err(|source| Error::InvalidSpec {
+            path: self.path.join(kind.file_name()),
+            source,
+        })
+    }
+
+    /// Returns the dependencies of the given class with all USE conditionals
+    /// resolved against the USE flags the package was built with.
+    pub fn effective_dependencies(&self, kind: DepKind) -> error::Result<DepSpec> {
+        let flags = self.use_flags()?;
+        Ok(self.dependencies(kind)?.evaluate(|flag| flags.is_enabled(flag)))
+    }
+
+    /// Returns the USE flags of this package, combined from IUSE, IUSE_EFFECTIVE and USE.
+    pub fn use_flags(&self) -> error::Result<UseFlags> {
+        Ok(UseFlags::new(self.value(Fields::IUSE)?, self.value(Fields::IUSE_EFFECTIVE)?, self.value(Fields::USE)?))
+    }
+
+    /// Parses REQUIRED_USE into a tree.
+    pub fn parsed_required_use(&self) -> error::Result<RequiredUse> {
+        RequiredUse::parse(self.value(Fields::REQUIRED_USE)?).map_err(|source| Error::InvalidSpec {
+            path: self.path.join("REQUIRED_USE"),
+            source,
+        })
+    }
+
+    /// Checks REQUIRED_USE against the USE flags the package was built with.
+    /// An empty result means the constraints hold; violations typically follow manual USE changes.
+    pub fn required_use_violations(&self) -> error::Result<Vec<Violation>> {
+        let flags = self.use_flags()?;
+        Ok(self.parsed_required_use()?.violations(|flag| flags.is_enabled(flag)))
+    }
+
+    /// Parses LICENSE into a tree.
+    pub fn licenses(&self) -> error::Result<LicenseSpec> {
+        parse_license(self.value(Fields::LICENSE)?).map_err(|source| Error::InvalidSpec {
+            path: self.path.join("LICENSE"),
+            source,
+        })
+    }
+
+    /// Returns LICENSE with all USE conditionals resolved against the USE flags
+    /// the package was built with.
+    pub fn effective_licenses(&self) -> error::Result<LicenseSpec> {
+        let flags = self.use_flags()?;
+        Ok(self.licenses()?.evaluate(|flag| flags.is_enabled(flag)))
+    }
+
+    /// Returns the package URL, e.g. `pkg:ebuild/dev-libs/openssl@3.0.13?repository_url=...`.
+    /// The repository URL is added for the repositories in `purl::REPOSITORY_URLS`;
+    /// it is left out if the repository is unknown or cannot be read.
+    pub fn purl(&self) -> String {
+        let repository = self.value(Fields::REPOSITORY).unwrap_or_default();
+        purl::purl(self, purl::repository_url(repository))
+    }
+
+    /// Returns the package URL with the given repository URL, e.g. for an overlay.
+    pub fn purl_with_repository_url(&self, repository_url: Option<&str>) -> String {
+        purl::purl(self, repository_url)
+    }
+
+    /// Returns a best-effort CPE 2.3 name using the built-in vendor and product mapping.
+    /// Use `CpeMap::cpe` to override the mapping.
+    pub fn cpe(&self) -> Option<String> {
+        BUILTIN_CPE_MAP.cpe(self)
+    }
+
+    /// Returns BUILD_TIME as a timestamp, `None` if the file is missing.
+    /// This is the canonical accessor; it reads the field on demand and fails if it is not a number.
+    pub fn build_time(&self) -> error::Result<Option<SystemTime>> {
+        let seconds = self.number(Fields::BUILD_TIME)?;
+        Ok(seconds.and_then(|s| UNIX_EPOCH.checked_add(Duration::from_secs(s))))
+    }
+
+    /// Returns COUNTER, reading it on demand. `None` if the file is missing.
+    pub fn counter(&self) -> error::Result<Option<u64>> {
+        if self.loaded_fields.contains(Fields::COUNTER) || self.counter.is_some() {
+            return Ok(self.counter);
+        }
+        self.number(Fields::COUNTER)
+    }
+
+    /// Returns true if the package was built before the given time.
+    /// Packages without BUILD_TIME are never reported.
+    pub fn built_before(&self, time: SystemTime) -> error::Result<bool> {
+        Ok(self.build_time()?.is_some_and(|t| t < time))
+    }
+
+    /// Orders packages by COUNTER, i.e. the order they were installed in.
+    /// Packages without a readable COUNTER sort first, ties are ordered by `cmp_cpv`.
+    pub fn cmp_install_order(&self, other: &Self) -> Ordering {
+        let counter = |pkg: &Self| pkg.counter().ok().flatten();
+        counter(self).cmp(&counter(other)).then_with(|| self.cmp_cpv(other))
+    }
+
+    /// Orders packages by BUILD_TIME, oldest first.
+    /// Packages without a readable BUILD_TIME sort first, ties are ordered by `cmp_install_order`.
+    pub fn cmp_build_time(&self, other: &Self) -> Ordering {
+        let build_time = |pkg: &Self| pkg.build_time().ok().flatten();
+        build_time(self).cmp(&build_time(other)).then_with(|| self.cmp_install_order(other))
+    }
+
+    /// Parses a numeric field, `None` if it is empty.
+    fn number(&self, field: Fields) -> error::Result<Option<u64>> {
+        let value = self.value(field)?;
+        if value.is_empty() {
+            return Ok(None);
+        }
+        let path = self.path.join(field.file_name().unwrap_or_default());
+        value.parse().map(Some).map_err(|_| Error::InvalidNumber { path })
+    }
+
+    /// Parses `version` according to the PMS version grammar.
+    pub fn parsed_version(&self) -> Result<Version, ParseVersionError> {
+        Version::parse(&self.version)
+    }
+
+    /// Orders packages by category, package name and PMS version.
+    /// Unparsable versions are compared as plain strings and sort before valid ones.
+    pub fn cmp_cpv(&self, other: &Self) -> Ordering {
+        self.category
+            .cmp(&other.category)
+            .then_with(|| self.package.cmp(&other.package))
+            .then_with(|| match (self.parsed_version(), other.parsed_version()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                (Ok(_), Err(_)) => Ordering::Greater,
+                (Err(_), Ok(_)) => Ordering::Less,
+                (Err(_), Err(_)) => self.version.cmp(&other.version),
+            })
+    }
+}
+
+/// The packages found by `try_parse_vardb` and the problems encountered on the way.
+#[derive(Debug, Default)]
+pub struct VarDbScan {
+    pub packages: Vec<VarDbPkg>,
+    /// Non-fatal problems, e.g. unreadable metadata files or category directories.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Parses the entire vardb at the given path.
+/// Typically this is `/var/db/pkg`.
+///
+/// All errors are ignored; use `try_parse_vardb` to tell a broken vardb from an empty one.
+pub fn parse_vardb<P: AsRef<Path>>(path: P) -> Vec<VarDbPkg> {
+    try_parse_vardb(path).map(|scan| scan.packages).unwrap_or_default()
+}
+
+/// Parses the entire vardb at the given path, reporting errors.
+///
+/// Fails if the vardb directory itself cannot be read. Problems with single
+/// categories, packages or files are collected in `VarDbScan::diagnostics`;
+/// a package with an unreadable metadata file is still returned with that field left empty.
+/// Packages are returned sorted by category and directory name.
+pub fn try_parse_vardb<P: AsRef<Path>>(path: P) -> error::Result<VarDbScan> {
+    try_parse_vardb_with(path, Fields::all())
+}
+
+/// Parses the entire vardb like `try_parse_vardb`, reading only the selected fields.
+pub fn try_parse_vardb_with<P: AsRef<Path>>(path: P, fields: Fields) -> error::Result<VarDbScan> {
+    let (dirs, diagnostics) = list_package_dirs(path.as_ref())?;
+    let loaded = dirs.iter().map(|dir| load_package(&dir.category, dir.dir_name(), &dir.path, fields));
+    Ok(collect_scan(&dirs, loaded, diagnostics))
+}
+
+/// Parses the entire vardb like `parse_vardb`, loading the packages on the rayon thread pool.
+/// The result is identical to the sequential version, including its ordering.
+#[cfg(feature = "parallel")]
+pub fn parse_vardb_parallel<P: AsRef<Path>>(path: P) -> Vec<VarDbPkg> {
+    try_parse_vardb_parallel(path).map(|scan| scan.packages).unwrap_or_default()
+}
+
+/// Parses the entire vardb like `try_parse_vardb`, loading the packages on the rayon thread pool.
+/// The result is identical to the sequential version, including its ordering.
+#[cfg(feature = "parallel")]
+pub fn try_parse_vardb_parallel<P: AsRef<Path>>(path: P) -> error::Result<VarDbScan> {
+    try_parse_vardb_parallel_with(path, Fields::all())
+}
+
+/// Parses the entire vardb like `try_parse_vardb_with`, loading the packages on the rayon thread pool.
+#[cfg(feature = "parallel")]
+pub fn try_parse_vardb_parallel_with<P: AsRef<Path>>(path: P, fields: Fields) -> error::Result<VarDbScan> {
+    use rayon::prelude::*;
+
+    let (dirs, diagnostics) = list_package_dirs(path.as_ref())?;
+    let loaded: Vec<_> = dirs
+        .par_iter()
+        .map(|dir| load_package(&dir.category, dir.dir_name(), &dir.path, fields))
+        .collect();
+    Ok(collect_scan(&dirs, loaded.into_iter(), diagnostics))
+}
+
+/// Lists all package directories of the vardb, sorted by category and directory name.
+fn list_package_dirs(root: &Path) -> error::Result<(Vec<PackageEntry>, Vec<Diagnostic>)> {
+    let mut dirs = Vec::new();
+    let mut diagnostics = Vec::new();
+    for entry in VarDb::open(root)?.entries() {
+        match entry {
+            Ok(entry) => dirs.push(entry),
+            Err(error) => diagnostics.push(Diagnostic { cpv: None, error }),
+        }
+    }
+    Ok((dirs, diagnostics))
+}
+
+/// Combines the loaded packages (in the order of `dirs`) into a `VarDbScan`.
+fn collect_scan<I>(dirs: &[PackageEntry], loaded: I, diagnostics: Vec<Diagnostic>) -> VarDbScan
+where
+    I: Iterator<Item = error::Result<(VarDbPkg, Vec<Error>)>>,
+{
+    let mut scan = VarDbScan { packages: Vec::new(), diagnostics };
+    for (dir, result) in dirs.iter().zip(loaded) {
+        match result {
+            Ok((pkg, errors)) => {
+                scan.diagnostics.extend(errors.into_iter().map(|error| Diagnostic { cpv: Some(dir.cpv()), error }));
+                scan.packages.push(pkg);
             }
+            Err(error) => scan.diagnostics.push(Diagnostic { cpv: Some(dir.cpv()), error }),
         }
     }
+    scan
+}
+
+/// Parses a single package directory, e.g. `/var/db/pkg/app-shells/bash-5.2_p37`.
+/// Missing metadata files are left empty; any other read error is returned.
+pub fn try_parse_package_dir<P: AsRef<Path>>(category: &str, dir_name: &str, path: P) -> error::Result<VarDbPkg> {
+    try_parse_package_dir_with(category, dir_name, path, Fields::all())
+}
 
-    packages
+/// Parses a single package directory, reading only the selected fields.
+/// The other fields can be read later with `VarDbPkg::load`.
+pub fn try_parse_package_dir_with<P: AsRef<Path>>(
+    category: &str,
+    dir_name: &str,
+    path: P,
+    fields: Fields,
+) -> error::Result<VarDbPkg> {
+    let (pkg, errors) = load_package(category, dir_name, path.as_ref(), fields)?;
+    match errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(pkg),
+    }
 }
 
-fn parse_package_dir(category: &str, dir_name: &str, path: &Path) -> Option<VarDbPkg> {
+/// Reads a package directory.
+/// Fails only if the directory cannot be listed; errors of single files are returned alongside the package.
+pub(crate) fn load_package(category: &str, dir_name: &str, path: &Path, fields: Fields) -> error::Result<(VarDbPkg, Vec<Error>)> {
+    fs::read_dir(path).map_err(|e| Error::from_read_dir(path, e))?;
+
+    let mut errors = Vec::new();
     let (package_name, version) = split_package_version(dir_name);
+    if version.is_empty() {
+        errors.push(Error::InvalidPackageDir { path: path.to_path_buf() });
+    }
 
     let mut pkg = VarDbPkg {
         category: category.to_string(),
-        package: package_name.clone(),
-        version: version.clone(),
+        package: package_name,
+        version,
+        path: path.to_path_buf(),
         ..Default::default()
     };
+    pkg.read_fields(fields, &mut errors);
 
-    pkg.buildtime = read_first_line(path.join("BUILD_TIME")).unwrap_or_default();
-    pkg.description = read_first_line(path.join("DESCRIPTION")).unwrap_or_default();
-    pkg.homepage = read_first_line(path.join("HOMEPAGE")).unwrap_or_default();
-    pkg.iuse = read_first_line(path.join("IUSE")).unwrap_or_default();
-    pkg.keywords = read_first_line(path.join("KEYWORDS")).unwrap_or_default();
-    pkg.license = read_first_line(path.join("LICENSE")).unwrap_or_default();
-    pkg.rdepend = read_first_line(path.join("RDEPEND")).unwrap_or_default();
-    pkg.repository = read_first_line(path.join("repository")).unwrap_or_default();
-    pkg.slot = read_first_line(path.join("SLOT")).unwrap_or_default();
-    pkg.usepkg = read_first_line(path.join("USE")).unwrap_or_default();
-    pkg.eapi = read_first_line(path.join("EAPI")).unwrap_or_default();
-    pkg.binpkgmd5 = read_first_line(path.join("BINPKGMD5")).unwrap_or_default();
-
-    // Read ebuild file
-    let ebuild_filename = format!("{}-{}.ebuild", package_name, version);
-    let ebuild_path = path.join(ebuild_filename);
-    if let Ok(ebuild_data) = EbuildData::scan(ebuild_path) {
-        pkg.ebuild_data = ebuild_data;
-    }
-
-    Some(pkg)
+    Ok((pkg, errors))
 }
 
 /// Splits a directory name into package name and version.
 /// Gentoo package directories are named as `package-version`.
-fn split_package_version(dir_name: &str) -> (String, String) {
+pub(crate) fn split_package_version(dir_name: &str) -> (String, String) {
     let parts: Vec<&str> = dir_name.split('-').collect();
 
+    // Prefer the first split whose remainder is a valid PMS version
     for i in 1..parts.len() {
-        if let Some(first_char) = parts[i].chars().next() {
-            if first_char.is_ascii_digit() {
-                let package_name = parts[..i].join("-");
-                let version = parts[i..].join("-");
-                return (package_name, version);
-            }
+        let version = parts[i..].join("-");
+        if Version::parse(&version).is_ok() {
+            return (parts[..i].join("-"), version);
+        }
+    }
+
+    // Otherwise fall back to the first segment starting with a digit
+    for i in 1..parts.len() {
+        if let Some(first_char) = parts[i].chars().next()
+            && first_char.is_ascii_digit()
+        {
+            let package_name = parts[..i].join("-");
+            let version = parts[i..].join("-");
+            return (package_name, version);
         }
     }
 
     (dir_name.to_string(), String::new())
 }
 
+/// Reads a whole file.
+/// Returns `Ok(None)` if the file does not exist.
+pub(crate) fn read_file<P: AsRef<Path>>(path: P) -> error::Result<Option<String>> {
+    let path = path.as_ref();
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::from_io(path, e)),
+    }
+}
+
+/// Reads a vardb file and normalizes it according to its kind.
+/// Returns `Ok(None)` if the file does not exist.
+fn read_value<P: AsRef<Path>>(path: P, kind: FileKind) -> error::Result<Option<String>> {
+    Ok(read_file(path)?.map(|content| kind.normalize(&content)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::atom::PackageAtom;
     use std::io::Write;
     use tempfile::tempdir;
 
@@ -128,6 +711,14 @@ mod tests {
             split_package_version("my-pkg-name-1.2.3-r1"),
             ("my-pkg-name".to_string(), "1.2.3-r1".to_string())
         );
+        assert_eq!(
+            split_package_version("font-adobe-100dpi-1.0.4"),
+            ("font-adobe-100dpi".to_string(), "1.0.4".to_string())
+        );
+        assert_eq!(
+            split_package_version("pkg-1.0_weird"),
+            ("pkg".to_string(), "1.0_weird".to_string())
+        );
         assert_eq!(
             split_package_version("noversion"),
             ("noversion".to_string(), "".to_string())
@@ -135,15 +726,127 @@ mod tests {
     }
 
     #[test]
-    fn test_read_first_line() {
+    fn test_cmp_cpv() {
+        let mut packages: Vec<VarDbPkg> = ["b/x-1.2", "a/y-1.10", "a/y-1.2_rc1", "a/y-1.2", "a/y-1.2-r1"]
+            .iter()
+            .map(|cpv| {
+                let (category, rest) = cpv.split_once('/').unwrap();
+                let (package, version) = split_package_version(rest);
+                VarDbPkg {
+                    category: category.to_string(),
+                    package,
+                    version,
+                    ..Default::default()
+                }
+            })
+            .collect();
+        packages.sort_by(VarDbPkg::cmp_cpv);
+        let versions: Vec<&str> = packages.iter().map(|p| p.version.as_str()).collect();
+        assert_eq!(versions, ["1.2_rc1", "1.2", "1.2-r1", "1.10", "1.2"]);
+    }
+
+    #[test]
+    fn test_required_use_violations() {
+        let pkg = VarDbPkg {
+            required_use: "^^ ( python_targets_python3_12 python_targets_python3_13 ) test? ( debug )".to_string(),
+            iuse_effective: "debug python_targets_python3_12 python_targets_python3_13 test".to_string(),
+            usepkg: "python_targets_python3_12 python_targets_python3_13 test".to_string(),
+            ..Default::default()
+        };
+        let violations: Vec<String> = pkg.required_use_violations().unwrap().iter().map(|v| v.to_string()).collect();
+        assert_eq!(
+            violations,
+            [
+                "^^ ( python_targets_python3_12 python_targets_python3_13 ): exactly one flag must be enabled, \
+                 enabled: python_targets_python3_12, python_targets_python3_13",
+                "test? ( debug ): debug must be enabled",
+            ]
+        );
+
+        let pkg = VarDbPkg { required_use: "^^ ( a".to_string(), ..Default::default() };
+        assert!(matches!(pkg.required_use_violations(), Err(Error::InvalidSpec { .. })));
+    }
+
+    #[test]
+    fn test_build_time_and_install_order() {
+        let pkg = |package: &str, buildtime: &str, counter: Option<u64>| VarDbPkg {
+            category: "app-misc".to_string(),
+            package: package.to_string(),
+            version: "1".to_string(),
+            buildtime: buildtime.to_string(),
+            counter,
+            ..Default::default()
+        };
+        let mut packages = [
+            pkg("gcc", "1735689600", Some(30)),
+            pkg("binutils", "1704067200", Some(10)),
+            pkg("broken", "yesterday", None),
+            pkg("glibc", "1719792000", Some(20)),
+        ];
+
+        assert_eq!(packages[0].build_time().unwrap(), Some(UNIX_EPOCH + Duration::from_secs(1735689600)));
+        assert!(matches!(packages[2].build_time(), Err(Error::InvalidNumber { .. })));
+        // Everything built before the toolchain update on 2024-07-01
+        let toolchain_update = UNIX_EPOCH + Duration::from_secs(1719792000);
+        let stale: Vec<&str> = packages
+            .iter()
+            .filter(|p| p.built_before(toolchain_update).unwrap_or(false))
+            .map(|p| p.package.as_str())
+            .collect();
+        assert_eq!(stale, ["binutils"]);
+
+        packages.sort_by(VarDbPkg::cmp_install_order);
+        let order: Vec<&str> = packages.iter().map(|p| p.package.as_str()).collect();
+        assert_eq!(order, ["broken", "binutils", "glibc", "gcc"]);
+
+        packages[1].buildtime = "1740000000".to_string();
+        packages.sort_by(VarDbPkg::cmp_build_time);
+        let order: Vec<&str> = packages.iter().map(|p| p.package.as_str()).collect();
+        assert_eq!(order, ["broken", "glibc", "gcc", "binutils"]);
+    }
+
+    #[test]
+    fn test_build_time_and_counter_on_demand() {
+        let dir = tempdir().unwrap();
+        for (cpv, buildtime, counter) in [("app-misc/a-1", "1735689600\n", "20\n"), ("app-misc/b-1", "1704067200\n", "10\n")] {
+            let pkg_path = dir.path().join(cpv);
+            fs::create_dir_all(&pkg_path).unwrap();
+            fs::write(pkg_path.join("BUILD_TIME"), buildtime).unwrap();
+            fs::write(pkg_path.join("COUNTER"), counter).unwrap();
+        }
+
+        let mut packages = try_parse_vardb_with(dir.path(), Fields::SLOT).unwrap().packages;
+        assert_eq!(packages[0].counter, None);
+        assert_eq!(packages[0].counter().unwrap(), Some(20));
+        assert_eq!(packages[1].build_time().unwrap(), Some(UNIX_EPOCH + Duration::from_secs(1704067200)));
+        assert!(packages[1].built_before(UNIX_EPOCH + Duration::from_secs(1719792000)).unwrap());
+
+        packages.sort_by(VarDbPkg::cmp_install_order);
+        assert_eq!(packages.iter().map(|p| p.package.as_str()).collect::<Vec<_>>(), ["b", "a"]);
+        packages.reverse();
+        packages.sort_by(VarDbPkg::cmp_build_time);
+        assert_eq!(packages.iter().map(|p| p.package.as_str()).collect::<Vec<_>>(), ["b", "a"]);
+
+        fs::write(dir.path().join("app-misc/a-1/COUNTER"), "x\n").unwrap();
+        let pkg = try_parse_package_dir_with("app-misc", "a-1", dir.path().join("app-misc/a-1"), Fields::empty()).unwrap();
+        assert!(matches!(pkg.counter(), Err(Error::InvalidNumber { path }) if path.ends_with("COUNTER")));
+    }
+
+    #[test]
+    fn test_read_value() {
         let dir = tempdir().unwrap();
         let file_path = dir.path().join("test.txt");
         let mut file = fs::File::create(&file_path).unwrap();
         writeln!(file, "  first line  ").unwrap();
         writeln!(file, "second line").unwrap();
 
-        assert_eq!(read_first_line(&file_path), Some("first line".to_string()));
-        assert_eq!(read_first_line(dir.path().join("nonexistent")), None);
+        assert_eq!(read_value(&file_path, FileKind::Token).unwrap(), Some("first line".to_string()));
+        assert_eq!(read_value(&file_path, FileKind::List).unwrap(), Some("first line second line".to_string()));
+        assert_eq!(read_value(&file_path, FileKind::Lines).unwrap(), Some("  first line  \nsecond line".to_string()));
+        assert_eq!(read_value(dir.path().join("nonexistent"), FileKind::Token).unwrap(), None);
+
+        fs::write(&file_path, b"\xff\xfe\n").unwrap();
+        assert!(matches!(read_value(&file_path, FileKind::Token), Err(Error::InvalidUtf8 { .. })));
     }
 
     #[test]
@@ -160,7 +863,7 @@ mod tests {
         let ebuild_content = "MY_VAR=\"hello world\"\nEAPI=8\n";
         fs::write(pkg_path.join("pkg-1.2.3.ebuild"), ebuild_content).unwrap();
 
-        let pkg = parse_package_dir("cat", "pkg-1.2.3", &pkg_path).unwrap();
+        let pkg = try_parse_package_dir("cat", "pkg-1.2.3", &pkg_path).unwrap();
 
         assert_eq!(pkg.package, "pkg");
         assert_eq!(pkg.version, "1.2.3");
@@ -171,11 +874,384 @@ mod tests {
         assert_eq!(pkg.ebuild_data["my_var"], "hello world");
         assert_eq!(pkg.ebuild_data["eapi"], "8");
     }
-}
 
-/// Reads the first line of a file and trims it.
-fn read_first_line<P: AsRef<Path>>(path: P) -> Option<String> {
-    fs::read_to_string(path)
-        .ok()
-        .and_then(|content| content.lines().next().map(|s| s.trim().to_string()))
+    #[test]
+    fn test_effective_dependencies() {
+        let dir = tempdir().unwrap();
+        let pkg_path = dir.path().join("net-misc").join("curl-8.11.1");
+        fs::create_dir_all(&pkg_path).unwrap();
+        fs::write(pkg_path.join("USE"), "ssl amd64\n").unwrap();
+        fs::write(
+            pkg_path.join("RDEPEND"),
+            "sys-libs/zlib ssl? ( dev-libs/openssl:0= ) http2? ( net-libs/nghttp2:= )\n",
+        )
+        .unwrap();
+        fs::write(pkg_path.join("BDEPEND"), "virtual/pkgconfig\n").unwrap();
+
+        let pkg = try_parse_package_dir("net-misc", "curl-8.11.1", &pkg_path).unwrap();
+        assert_eq!(pkg.dependencies(DepKind::Rdepend).unwrap().leaves().len(), 3);
+        assert_eq!(
+            pkg.effective_dependencies(DepKind::Rdepend).unwrap().to_string(),
+            "sys-libs/zlib dev-libs/openssl:0="
+        );
+        assert_eq!(pkg.effective_dependencies(DepKind::Bdepend).unwrap().to_string(), "virtual/pkgconfig");
+        assert!(pkg.dependencies(DepKind::Pdepend).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_try_parse_vardb_errors() {
+        let dir = tempdir().unwrap();
+        assert!(matches!(try_parse_vardb(dir.path().join("missing")), Err(Error::NotFound { .. })));
+
+        let scan = try_parse_vardb(dir.path()).unwrap();
+        assert!(scan.packages.is_empty());
+        assert!(scan.diagnostics.is_empty());
+
+        let good = dir.path().join("app-misc").join("good-1.0");
+        let bad = dir.path().join("app-misc").join("bad-2.0");
+        let unversioned = dir.path().join("app-misc").join("unversioned");
+        for p in [&good, &bad, &unversioned] {
+            fs::create_dir_all(p).unwrap();
+        }
+        fs::write(good.join("SLOT"), "0\n").unwrap();
+        fs::write(bad.join("SLOT"), "0\n").unwrap();
+        fs::write(bad.join("DESCRIPTION"), b"caf\xe9\n").unwrap();
+        fs::write(dir.path().join("stray-file"), "").unwrap();
+
+        let scan = try_parse_vardb(dir.path()).unwrap();
+        assert_eq!(scan.packages.len(), 3);
+        assert_eq!(scan.diagnostics.len(), 2);
+        let bad_diag = scan.diagnostics.iter().find(|d| d.cpv.as_deref() == Some("app-misc/bad-2.0")).unwrap();
+        assert!(matches!(bad_diag.error, Error::InvalidUtf8 { .. }));
+        assert!(bad_diag.to_string().contains("invalid UTF-8"));
+        let unversioned_diag = scan.diagnostics.iter().find(|d| d.cpv.as_deref() == Some("app-misc/unversioned")).unwrap();
+        assert!(matches!(unversioned_diag.error, Error::InvalidPackageDir { .. }));
+
+        assert!(try_parse_package_dir("app-misc", "good-1.0", &good).is_ok());
+        assert!(matches!(try_parse_package_dir("app-misc", "bad-2.0", &bad), Err(Error::InvalidUtf8 { .. })));
+        assert!(matches!(
+            try_parse_package_dir("app-misc", "gone-1.0", dir.path().join("app-misc").join("gone-1.0")),
+            Err(Error::NotFound { .. })
+        ));
+        assert_eq!(parse_vardb(dir.path()).len(), 3);
+    }
+
+    #[test]
+    fn test_parse_vardb_sorted() {
+        let dir = tempdir().unwrap();
+        for cpv in ["sys-libs/zlib-1.3", "app-misc/b-1", "app-misc/a-2", "dev-libs/openssl-3.3.2"] {
+            fs::create_dir_all(dir.path().join(cpv)).unwrap();
+        }
+        let names: Vec<String> = parse_vardb(dir.path())
+            .iter()
+            .map(|p| format!("{}/{}-{}", p.category, p.package, p.version))
+            .collect();
+        assert_eq!(names, ["app-misc/a-2", "app-misc/b-1", "dev-libs/openssl-3.3.2", "sys-libs/zlib-1.3"]);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parse_vardb_parallel_matches_sequential() {
+        let dir = tempdir().unwrap();
+        for i in 0..50 {
+            let pkg_path = dir.path().join(format!("cat-{}", i % 7)).join(format!("pkg{}-1.{}", i, i));
+            fs::create_dir_all(&pkg_path).unwrap();
+            fs::write(pkg_path.join("SLOT"), format!("{}\n", i)).unwrap();
+        }
+        fs::write(dir.path().join("cat-0").join("pkg0-1.0").join("USE"), b"\xff\n").unwrap();
+
+        let sequential = try_parse_vardb(dir.path()).unwrap();
+        let parallel = try_parse_vardb_parallel(dir.path()).unwrap();
+        assert_eq!(
+            serde_json::to_string(&sequential.packages).unwrap(),
+            serde_json::to_string(&parallel.packages).unwrap()
+        );
+        assert_eq!(sequential.diagnostics.len(), 1);
+        assert_eq!(parallel.diagnostics.len(), 1);
+        assert_eq!(parse_vardb_parallel(dir.path()).len(), 50);
+    }
+
+    #[test]
+    fn test_field_selection_and_lazy_loading() {
+        let dir = tempdir().unwrap();
+        let pkg_path = dir.path().join("cat").join("pkg-1.0");
+        fs::create_dir_all(&pkg_path).unwrap();
+        fs::write(pkg_path.join("SLOT"), "0\n").unwrap();
+        fs::write(pkg_path.join("DESCRIPTION"), "Lazy\n").unwrap();
+        fs::write(pkg_path.join("pkg-1.0.ebuild"), "EAPI=8\n").unwrap();
+
+        let mut pkg = try_parse_package_dir_with("cat", "pkg-1.0", &pkg_path, Fields::SLOT).unwrap();
+        assert_eq!(pkg.slot, "0");
+        assert_eq!(pkg.description, "");
+        assert!(pkg.ebuild_data.variables().is_empty());
+        assert_eq!(pkg.loaded_fields, Fields::SLOT);
+
+        assert_eq!(pkg.field(Fields::DESCRIPTION).unwrap(), "Lazy");
+        assert_eq!(pkg.ebuild().unwrap()["eapi"], "8");
+        assert_eq!(pkg.loaded_fields, Fields::SLOT | Fields::DESCRIPTION | Fields::EBUILD);
+        assert!(matches!(pkg.field(Fields::EBUILD), Err(Error::InvalidSpec { .. })));
+        assert!(matches!(pkg.field(Fields::DEPENDENCIES), Err(Error::InvalidSpec { .. })));
+        assert!(matches!(pkg.field(Fields::COUNTER), Err(Error::InvalidSpec { .. })));
+
+        // Already loaded fields are not read again
+        fs::write(pkg_path.join("SLOT"), "1\n").unwrap();
+        pkg.load(Fields::all()).unwrap();
+        assert_eq!(pkg.slot, "0");
+        assert_eq!(pkg.loaded_fields, Fields::all());
+
+        let scan = try_parse_vardb_with(dir.path(), Fields::SLOT | Fields::DESCRIPTION).unwrap();
+        assert_eq!(scan.packages[0].description, "Lazy");
+        assert_eq!(scan.packages[0].eapi, "");
+
+        // Fields that fail to read stay unloaded
+        fs::create_dir(pkg_path.join("HOMEPAGE")).unwrap();
+        fs::create_dir(pkg_path.join("IUSE")).unwrap();
+        let mut pkg = try_parse_package_dir_with("cat", "pkg-1.0", &pkg_path, Fields::empty()).unwrap();
+        assert!(pkg.load(Fields::SLOT | Fields::HOMEPAGE | Fields::IUSE).is_err());
+        assert_eq!(pkg.loaded_fields, Fields::SLOT);
+    }
+
+    #[test]
+    fn test_accessors_with_partial_fields() {
+        let dir = tempdir().unwrap();
+        let pkg_path = dir.path().join("app-misc").join("foo-1.0");
+        fs::create_dir_all(&pkg_path).unwrap();
+        for (name, content) in [
+            ("SLOT", "0\n"),
+            ("IUSE", "ssl\n"),
+            ("USE", "ssl\n"),
+            ("RDEPEND", "ssl? ( dev-libs/openssl ) sys-libs/zlib\n"),
+            ("repository", "gentoo\n"),
+        ] {
+            fs::write(pkg_path.join(name), content).unwrap();
+        }
+
+        let pkg = try_parse_package_dir_with("app-misc", "foo-1.0", &pkg_path, Fields::empty()).unwrap();
+        assert_eq!(pkg.rdepend, "");
+        assert_eq!(pkg.dependency_string(DepKind::Rdepend).unwrap(), "ssl? ( dev-libs/openssl ) sys-libs/zlib");
+        assert_eq!(
+            pkg.effective_dependencies(DepKind::Rdepend).unwrap().to_string(),
+            "dev-libs/openssl sys-libs/zlib"
+        );
+        assert!(PackageAtom::parse("app-misc/foo:0::gentoo[ssl]").unwrap().matches(&pkg));
+        // The values are cached, not stored in the fields
+        fs::write(pkg_path.join("RDEPEND"), "sys-libs/zlib\n").unwrap();
+        assert_eq!(pkg.dependency_string(DepKind::Rdepend).unwrap(), "ssl? ( dev-libs/openssl ) sys-libs/zlib");
+        assert_eq!(pkg.loaded_fields, Fields::empty());
+
+        // Unreadable and unparsable files are reported
+        fs::write(pkg_path.join("DEPEND"), b"\xff").unwrap();
+        assert!(matches!(pkg.dependencies(DepKind::Depend), Err(Error::InvalidUtf8 { .. })));
+        fs::write(pkg_path.join("BDEPEND"), "|| foo\n").unwrap();
+        assert!(matches!(pkg.dependencies(DepKind::Bdepend), Err(Error::InvalidSpec { .. })));
+
+        // Values set by hand win over the files
+        let mut pkg = pkg;
+        pkg.slot = "1".to_string();
+        assert!(!PackageAtom::parse("app-misc/foo:0").unwrap().matches(&pkg));
+    }
+
+    #[test]
+    fn test_parse_full_metadata() {
+        let dir = tempdir().unwrap();
+        let pkg_path = dir.path().join("app-shells").join("bash-5.2_p37");
+        fs::create_dir_all(&pkg_path).unwrap();
+        for (name, value) in [
+            ("SRC_URI", "mirror://gnu/bash/bash-5.2.tar.gz"),
+            ("RESTRICT", "!test? ( test )"),
+            ("PROPERTIES", "live"),
+            ("REQUIRED_USE", "^^ ( a b )"),
+            ("INHERITED", "flag-o-matic toolchain-funcs"),
+            ("DEFINED_PHASES", "compile configure install"),
+            ("IUSE_EFFECTIVE", "abi_x86_64 amd64 nls"),
+            ("PKGUSE", "-nls"),
+            ("CHOST", "x86_64-pc-linux-gnu"),
+            ("CBUILD", "x86_64-pc-linux-gnu"),
+            ("CTARGET", "aarch64-unknown-linux-gnu"),
+            ("CFLAGS", "-O2 -pipe"),
+            ("CXXFLAGS", "-O2 -pipe"),
+            ("LDFLAGS", "-Wl,-O1"),
+            ("FEATURES", "sandbox userpriv"),
+            ("COUNTER", "4711"),
+            ("SIZE", "9876543"),
+            ("PF", "bash-5.2_p37"),
+            ("CATEGORY", "app-shells"),
+            ("PROVIDES", "x86_64: libreadline.so.8"),
+            ("REQUIRES", "x86_64: libc.so.6"),
+            ("ASFLAGS", "--64"),
+        ] {
+            fs::write(pkg_path.join(name), format!("{}\n", value)).unwrap();
+        }
+        fs::write(pkg_path.join("BUILD_ID"), "not-a-number\n").unwrap();
+        fs::write(pkg_path.join("BUILD_TIME"), "1735689600\n").unwrap();
+        fs::write(pkg_path.join("environment.bz2"), b"BZh").unwrap();
+        fs::write(pkg_path.join("CONTENTS"), "dir /bin\n").unwrap();
+
+        let (pkg, errors) = load_package("app-shells", "bash-5.2_p37", &pkg_path, Fields::all()).unwrap();
+        assert_eq!(pkg.src_uri, "mirror://gnu/bash/bash-5.2.tar.gz");
+        assert_eq!(pkg.restrict, "!test? ( test )");
+        assert_eq!(pkg.properties, "live");
+        assert_eq!(pkg.required_use, "^^ ( a b )");
+        assert_eq!(pkg.inherited, "flag-o-matic toolchain-funcs");
+        assert_eq!(pkg.defined_phases, "compile configure install");
+        assert_eq!(pkg.iuse_effective, "abi_x86_64 amd64 nls");
+        assert_eq!(pkg.pkguse, "-nls");
+        assert_eq!(pkg.chost, "x86_64-pc-linux-gnu");
+        assert_eq!(pkg.cbuild, "x86_64-pc-linux-gnu");
+        assert_eq!(pkg.ctarget, "aarch64-unknown-linux-gnu");
+        assert_eq!(pkg.cflags, "-O2 -pipe");
+        assert_eq!(pkg.cxxflags, "-O2 -pipe");
+        assert_eq!(pkg.ldflags, "-Wl,-O1");
+        assert_eq!(pkg.features, "sandbox userpriv");
+        assert_eq!(pkg.counter, Some(4711));
+        assert_eq!(pkg.size, Some(9876543));
+        assert_eq!(pkg.build_id, None);
+        assert_eq!(pkg.build_time().unwrap(), Some(UNIX_EPOCH + Duration::from_secs(1735689600)));
+        assert_eq!(pkg.pf, "bash-5.2_p37");
+        assert_eq!(pkg.category, "app-shells");
+        assert_eq!(pkg.provides, "x86_64: libreadline.so.8");
+        assert_eq!(pkg.requires, "x86_64: libc.so.6");
+        assert!(pkg.has_environment);
+        assert_eq!(pkg.extra.len(), 1);
+        assert_eq!(pkg.extra["ASFLAGS"], "--64");
+
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], Error::InvalidNumber { path } if path.ends_with("BUILD_ID")));
+    }
+
+    #[test]
+    fn test_package_contents() {
+        let dir = tempdir().unwrap();
+        let pkg_path = dir.path().join("cat").join("pkg-1.0");
+        fs::create_dir_all(&pkg_path).unwrap();
+        fs::write(
+            pkg_path.join("CONTENTS"),
+            "dir /usr/bin\nobj /usr/bin/pkg 0123456789abcdef0123456789abcdef 1700000000\n",
+        )
+        .unwrap();
+
+        let pkg = try_parse_package_dir("cat", "pkg-1.0", &pkg_path).unwrap();
+        assert_eq!(pkg.path, pkg_path);
+        let contents = pkg.contents().unwrap();
+        assert_eq!(contents.len(), 2);
+        assert!(contents.get("/usr/bin/pkg").is_some());
+
+        fs::remove_file(pkg_path.join("CONTENTS")).unwrap();
+        assert!(matches!(pkg.contents(), Err(Error::NotFound { path }) if path == pkg_path.join("CONTENTS")));
+    }
+
+    #[test]
+    fn test_parse_testdata_vardb() {
+        let scan = try_parse_vardb(Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/vardb")).unwrap();
+        assert!(scan.diagnostics.is_empty(), "{:?}", scan.diagnostics);
+        let cpvs: Vec<String> = scan.packages.iter().map(|p| format!("{}/{}-{}", p.category, p.package, p.version)).collect();
+        assert_eq!(cpvs, ["app-shells/bash-5.2_p37", "sys-libs/glibc-2.40-r8", "sys-libs/readline-8.2_p13"]);
+
+        let bash = &scan.packages[0];
+        // Token
+        assert_eq!(bash.slot, "0");
+        assert_eq!(bash.counter, Some(1402));
+        // List, wrapped over several lines
+        assert_eq!(
+            bash.rdepend,
+            ">=sys-libs/ncurses-5.2-r2:0= readline? ( >=sys-libs/readline-8.2_p13:0= ) nls? ( virtual/libintl )"
+        );
+        assert_eq!(bash.cflags, "-O2 -pipe -march=x86-64-v3");
+        // Text
+        assert_eq!(bash.description, "The standard GNU Bourne again shell");
+        assert!(bash.extra.is_empty());
+        let flags = bash.use_flags().unwrap();
+        assert_eq!(flags.enabled_plain().collect::<Vec<_>>(), ["amd64", "net", "nls", "readline"]);
+        assert_eq!(flags.default("readline"), Some(true));
+        let contents = bash.contents().unwrap();
+        assert_eq!(contents.len(), 9);
+        assert!(contents.get("/usr/share/doc/bash-5.2_p37/NEWS with spaces.bz2").is_some());
+
+        let readline = &scan.packages[2];
+        // Lines
+        assert_eq!(readline.provides, "x86_32: libhistory.so.8 libreadline.so.8\nx86_64: libhistory.so.8 libreadline.so.8");
+        assert_eq!(readline.slot, "0/8");
+        assert_eq!(readline.dependencies(DepKind::Rdepend).unwrap().leaves().len(), 1);
+    }
+
+    #[test]
+    fn test_purl_and_cpe() {
+        let mut pkg = VarDbPkg {
+            category: "dev-libs".to_string(),
+            package: "openssl".to_string(),
+            version: "3.0.13-r2".to_string(),
+            repository: "gentoo".to_string(),
+            ..Default::default()
+        };
+        assert!(pkg.purl().ends_with("?repository_url=https%3A%2F%2Fgitweb.gentoo.org%2Frepo%2Fgentoo.git"));
+        assert_eq!(pkg.cpe().unwrap(), "cpe:2.3:a:openssl:openssl:3.0.13:*:*:*:*:*:*:*");
+        pkg.repository = "local".to_string();
+        assert_eq!(pkg.purl(), "pkg:ebuild/dev-libs/openssl@3.0.13-r2");
+
+        // An unreadable repository file leaves out the qualifier
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("repository")).unwrap();
+        let pkg = VarDbPkg { path: dir.path().to_path_buf(), repository: String::new(), ..pkg };
+        assert!(pkg.value(Fields::REPOSITORY).is_err());
+        assert_eq!(pkg.purl(), "pkg:ebuild/dev-libs/openssl@3.0.13-r2");
+    }
+
+    #[test]
+    fn test_testdata_license_report() {
+        let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        let packages = parse_vardb(testdata.join("vardb"));
+        let groups = license::LicenseGroups::scan(testdata.join("license_groups")).unwrap();
+        let policy = license::AcceptLicense::parse("-* @FREE", &groups);
+        let report = policy.report(&packages);
+        assert!(report.errors.is_empty());
+        assert_eq!(report.violations.len(), 1);
+        assert_eq!(packages[report.violations[0].package].package, "glibc");
+        assert_eq!(report.violations[0].missing, ["inner-net", "rc"]);
+        assert!(license::AcceptLicense::parse("@FREE inner-net rc", &groups).report(&packages).violations.is_empty());
+    }
+
+    #[cfg(feature = "environment")]
+    #[test]
+    fn test_package_environment() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/vardb/app-shells/bash-5.2_p37");
+        let pkg = try_parse_package_dir("app-shells", "bash-5.2_p37", &path).unwrap();
+        assert!(pkg.has_environment);
+        let env = pkg.environment().unwrap();
+        assert_eq!(env.get("SLOT"), Some(pkg.slot.as_str()));
+        assert_eq!(FileKind::List.normalize(env.get("RDEPEND").unwrap()), pkg.rdepend);
+        assert_eq!(env.variable("PATCHES").unwrap().as_array().unwrap().len(), 2);
+        assert!(env.function("src_install").unwrap().contains("dosym bash /bin/sh"));
+
+        let dir = tempdir().unwrap();
+        let pkg = VarDbPkg { path: dir.path().to_path_buf(), ..pkg };
+        let path = dir.path().join("environment.bz2");
+        assert!(matches!(pkg.environment(), Err(Error::NotFound { path: p }) if p == path));
+        fs::write(&path, b"BZh").unwrap();
+        assert!(matches!(pkg.environment(), Err(Error::InvalidFile { path: p, .. }) if p == path));
+    }
+
+    #[test]
+    fn test_testdata_broken_linkage() {
+        let packages = parse_vardb(Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/vardb"));
+        let solver = linkage::LinkageSolver::new(&packages);
+        // sys-libs/ncurses is not installed
+        let broken: Vec<(&str, String)> = solver
+            .broken()
+            .into_iter()
+            .map(|b| (packages[b.package].package.as_str(), format!("{}: {}", b.category, b.soname)))
+            .collect();
+        assert_eq!(
+            broken,
+            [
+                ("bash", "x86_64: libtinfo.so.6".to_string()),
+                ("readline", "x86_32: libtinfo.so.6".to_string()),
+                ("readline", "x86_64: libtinfo.so.6".to_string()),
+            ]
+        );
+
+        let needed = packages[2].needed().unwrap();
+        let objects: Vec<&Path> = needed.objects_needing("x86_64", "libtinfo.so.6").map(|e| e.object.as_path()).collect();
+        assert_eq!(objects, [Path::new("/usr/lib64/libreadline.so.8.2")]);
+        assert_eq!(needed.provides(), packages[2].provided_sonames().unwrap());
+    }
 }
@@ -0,0 +1,299 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use crate::depend::{DepSpec, ParseDepError};
+use crate::error::{self, Error};
+use crate::VarDbPkg;
+
+/// A LICENSE expression: license names with `|| ( )` groups and USE conditionals.
+pub type LicenseSpec = DepSpec<String>;
+
+/// Parses a LICENSE expression such as `|| ( MIT Apache-2.0 ) BSD ssl? ( openssl )`.
+pub fn parse_license(s: &str) -> Result<LicenseSpec, ParseDepError> {
+    DepSpec::parse_with(s, |token| {
+        let valid = token.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+            && token.chars().all(|c| c.is_ascii_alphanumeric() || "+_.-".contains(c));
+        if valid {
+            Ok(token.to_string())
+        } else {
+            Err(ParseDepError::new(format!("invalid license name {:?}", token)))
+        }
+    })
+}
+
+/// License groups as defined in `profiles/license_groups` of a repository,
+/// e.g. `FREE-SOFTWARE`, `OSI-APPROVED` or `EULA`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LicenseGroups {
+    groups: BTreeMap<String, Vec<String>>,
+}
+
+impl LicenseGroups {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads and parses a license_groups file.
+    pub fn scan<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let content = fs::read(path)?;
+        Ok(Self::parse(&String::from_utf8_lossy(&content)))
+    }
+
+    /// Parses the content of a license_groups file: one `GROUP license @OTHER-GROUP ...` line per group.
+    /// Comments and empty lines are skipped.
+    pub fn parse(content: &str) -> Self {
+        let mut groups = LicenseGroups::new();
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            if let Some(name) = words.next() {
+                groups.insert(name, words);
+            }
+        }
+        groups
+    }
+
+    /// Adds members to a group; members starting with `@` refer to other groups.
+    pub fn insert<I, S>(&mut self, group: &str, members: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let entry = self.groups.entry(group.to_string()).or_default();
+        entry.extend(members.into_iter().map(|m| m.as_ref().to_string()));
+    }
+
+    /// Returns the group names, sorted.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.groups.keys().map(String::as_str)
+    }
+
+    /// Returns the licenses of a group with nested groups resolved.
+    /// Unknown groups are empty; cyclic references are resolved once.
+    pub fn expand(&self, group: &str) -> BTreeSet<String> {
+        let mut licenses = BTreeSet::new();
+        let mut seen = BTreeSet::new();
+        let mut todo = vec![group];
+        while let Some(group) = todo.pop() {
+            if !seen.insert(group) {
+                continue;
+            }
+            for member in self.groups.get(group).into_iter().flatten() {
+                match member.strip_prefix('@') {
+                    Some(nested) => todo.push(nested),
+                    None => {
+                        licenses.insert(member.clone());
+                    }
+                }
+            }
+        }
+        licenses
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    All,
+    License(String),
+    Group(BTreeSet<String>),
+}
+
+/// A license acceptance policy in ACCEPT_LICENSE syntax, e.g. `-* @FREE MIT -GPL-3`.
+///
+/// Tokens are evaluated in order and the last one matching a license decides,
+/// like the incremental ACCEPT_LICENSE variable of Portage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcceptLicense {
+    tokens: Vec<(bool, Token)>,
+}
+
+impl AcceptLicense {
+    /// Parses an ACCEPT_LICENSE value. `@GROUP` names are resolved with `groups`.
+    pub fn parse(accept_license: &str, groups: &LicenseGroups) -> Self {
+        let tokens = accept_license
+            .split_whitespace()
+            .map(|token| {
+                let (accept, name) = match token.strip_prefix('-') {
+                    Some(name) => (false, name),
+                    None => (true, token),
+                };
+                let token = if name == "*" {
+                    Token::All
+                } else if let Some(group) = name.strip_prefix('@') {
+                    Token::Group(groups.expand(group))
+                } else {
+                    Token::License(name.to_string())
+                };
+                (accept, token)
+            })
+            .collect();
+        AcceptLicense { tokens }
+    }
+
+    /// Returns true if the policy accepts a single license.
+    pub fn accepts(&self, license: &str) -> bool {
+        let mut accepted = false;
+        for (accept, token) in &self.tokens {
+            let matches = match token {
+                Token::All => true,
+                Token::License(name) => name == license,
+                Token::Group(members) => members.contains(license),
+            };
+            if matches {
+                accepted = *accept;
+            }
+        }
+        accepted
+    }
+
+    /// Returns the licenses of a USE-reduced expression that keep it from being accepted,
+    /// empty if it is accepted. For an `|| ( )` group without an accepted alternative
+    /// all of its licenses are returned, like Portage reports masked licenses.
+    pub fn missing(&self, spec: &LicenseSpec) -> Vec<String> {
+        let mut missing = Vec::new();
+        self.collect_missing(spec, &mut missing);
+        missing
+    }
+
+    fn is_accepted(&self, spec: &LicenseSpec) -> bool {
+        match spec {
+            DepSpec::Leaf(license) => self.accepts(license),
+            DepSpec::AllOf(children) | DepSpec::UseConditional { children, .. } => {
+                children.iter().all(|c| self.is_accepted(c))
+            }
+            DepSpec::AnyOf(children) => children.is_empty() || children.iter().any(|c| self.is_accepted(c)),
+        }
+    }
+
+    fn collect_missing(&self, spec: &LicenseSpec, out: &mut Vec<String>) {
+        match spec {
+            DepSpec::Leaf(license) => {
+                if !self.accepts(license) && !out.contains(license) {
+                    out.push(license.clone());
+                }
+            }
+            DepSpec::AllOf(children) | DepSpec::UseConditional { children, .. } => {
+                for child in children {
+                    self.collect_missing(child, out);
+                }
+            }
+            DepSpec::AnyOf(_) if self.is_accepted(spec) => {}
+            DepSpec::AnyOf(_) => {
+                for license in spec.leaves() {
+                    if !out.contains(license) {
+                        out.push(license.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the licenses of an installed package not accepted by the policy,
+    /// with USE conditionals resolved against the package's USE flags.
+    pub fn check(&self, pkg: &VarDbPkg) -> error::Result<Vec<String>> {
+        Ok(self.missing(&pkg.effective_licenses()?))
+    }
+
+    /// Checks all packages, e.g. for a per-host compliance report.
+    pub fn report(&self, packages: &[VarDbPkg]) -> LicenseReport {
+        let mut report = LicenseReport::default();
+        for (idx, pkg) in packages.iter().enumerate() {
+            match self.check(pkg) {
+                Ok(missing) if missing.is_empty() => {}
+                Ok(missing) => report.violations.push(LicenseViolation { package: idx, missing }),
+                Err(e) => report.errors.push((idx, e)),
+            }
+        }
+        report
+    }
+}
+
+/// A package whose LICENSE is not accepted by the policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LicenseViolation {
+    /// Index of the package in the slice passed to `AcceptLicense::report`.
+    pub package: usize,
+    /// The licenses that are not accepted.
+    pub missing: Vec<String>,
+}
+
+/// The result of `AcceptLicense::report`.
+#[derive(Debug, Default)]
+pub struct LicenseReport {
+    pub violations: Vec<LicenseViolation>,
+    /// Packages whose LICENSE could not be read or parsed.
+    pub errors: Vec<(usize, Error)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LICENSE_GROUPS: &str = "\
+# Licenses approved by the FSF
+FSF-APPROVED Apache-2.0 BSD GPL-2 GPL-3 MIT
+OSI-APPROVED Apache-2.0 BSD BSD-1 MIT
+FREE-SOFTWARE @FSF-APPROVED @OSI-APPROVED
+FREE @FREE-SOFTWARE @FREE-DOCUMENTS
+FREE-DOCUMENTS CC-BY-4.0 FDL-1.3
+
+EULA NVIDIA-r2 @BINARY-REDISTRIBUTABLE
+BINARY-REDISTRIBUTABLE linux-fw-redistributable @EULA
+";
+
+    #[test]
+    fn test_parse_license() {
+        let spec = parse_license("|| ( MIT Apache-2.0 ) BSD BSD-1 ssl? ( openssl )").unwrap();
+        assert_eq!(spec.leaves().len(), 5);
+        assert_eq!(spec.evaluate(|_| false).to_string(), "|| ( MIT Apache-2.0 ) BSD BSD-1");
+        assert!(parse_license("MIT || GPL-2").is_err());
+        assert!(parse_license("GPL-2+ LGPL-2.1+").is_ok());
+        assert!(parse_license("-MIT").is_err());
+    }
+
+    #[test]
+    fn test_license_groups() {
+        let groups = LicenseGroups::parse(LICENSE_GROUPS);
+        assert_eq!(groups.names().count(), 7);
+        assert_eq!(groups.expand("FREE-SOFTWARE").len(), 6);
+        assert!(groups.expand("FREE").contains("FDL-1.3"));
+        // Cyclic groups terminate
+        assert_eq!(groups.expand("EULA").into_iter().collect::<Vec<_>>(), ["NVIDIA-r2", "linux-fw-redistributable"]);
+        assert!(groups.expand("UNKNOWN").is_empty());
+    }
+
+    #[test]
+    fn test_accept_license() {
+        let groups = LicenseGroups::parse(LICENSE_GROUPS);
+        let policy = AcceptLicense::parse("-* @FREE -GPL-3 linux-fw-redistributable", &groups);
+        assert!(policy.accepts("MIT"));
+        assert!(policy.accepts("linux-fw-redistributable"));
+        assert!(!policy.accepts("GPL-3"));
+        assert!(!policy.accepts("NVIDIA-r2"));
+        assert!(AcceptLicense::parse("* -@EULA", &groups).accepts("WTFPL"));
+        assert!(!AcceptLicense::parse("", &groups).accepts("MIT"));
+
+        let spec = parse_license("|| ( GPL-3 MIT ) || ( GPL-3 NVIDIA-r2 ) BSD GPL-3").unwrap();
+        assert_eq!(policy.missing(&spec), ["GPL-3", "NVIDIA-r2"]);
+        assert!(policy.missing(&parse_license("|| ( GPL-3 MIT ) BSD").unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_report() {
+        let pkg = |license: &str, usepkg: &str| VarDbPkg {
+            license: license.to_string(),
+            usepkg: usepkg.to_string(),
+            ..Default::default()
+        };
+        let packages = [
+            pkg("MIT", ""),
+            pkg("GPL-2 bindist? ( NVIDIA-r2 )", "bindist"),
+            pkg("GPL-2 bindist? ( NVIDIA-r2 )", ""),
+            pkg("|| ( MIT", ""),
+        ];
+        let policy = AcceptLicense::parse("@FREE", &LicenseGroups::parse(LICENSE_GROUPS));
+        let report = policy.report(&packages);
+        assert_eq!(report.violations, [LicenseViolation { package: 1, missing: vec!["NVIDIA-r2".to_string()] }]);
+        assert!(matches!(report.errors[..], [(3, Error::InvalidSpec { .. })]));
+    }
+}
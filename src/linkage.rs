@@ -0,0 +1,579 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::error::{self, Error};
+use crate::VarDbPkg;
+
+/// Sonames grouped by multilib category, the format of PROVIDES and REQUIRES:
+/// one `x86_64: libc.so.6 libm.so.6` line per category.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sonames {
+    categories: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Sonames {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the content of a PROVIDES or REQUIRES file.
+    /// Lines without a `category:` prefix are skipped.
+    pub fn parse(content: &str) -> Self {
+        let mut sonames = Sonames::new();
+        for line in content.lines() {
+            if let Some((category, list)) = line.split_once(':') {
+                for soname in list.split_whitespace() {
+                    sonames.insert(category.trim(), soname);
+                }
+            }
+        }
+        sonames
+    }
+
+    pub fn insert(&mut self, category: &str, soname: &str) {
+        self.categories.entry(category.to_string()).or_default().insert(soname.to_string());
+    }
+
+    pub fn contains(&self, category: &str, soname: &str) -> bool {
+        self.categories.get(category).is_some_and(|s| s.contains(soname))
+    }
+
+    /// Returns the multilib categories, e.g. `x86_64`.
+    pub fn categories(&self) -> impl Iterator<Item = &str> {
+        self.categories.keys().map(String::as_str)
+    }
+
+    /// Iterates over all `(category, soname)` pairs.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.categories
+            .iter()
+            .flat_map(|(category, sonames)| sonames.iter().map(move |s| (category.as_str(), s.as_str())))
+    }
+
+    pub fn len(&self) -> usize {
+        self.categories.values().map(BTreeSet::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Display for Sonames {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (category, sonames)) in self.categories.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}:", category)?;
+            for soname in sonames {
+                write!(f, " {}", soname)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A line of NEEDED.ELF.2: `arch;object;soname;rpath;needed;multilib_category`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NeededEntry {
+    /// The ELF machine, e.g. `X86_64` or `386`.
+    pub arch: String,
+    /// The installed path of the ELF object.
+    pub object: PathBuf,
+    /// The DT_SONAME of the object, empty for executables.
+    pub soname: String,
+    /// The DT_RUNPATH or DT_RPATH entries.
+    pub rpath: Vec<String>,
+    /// The DT_NEEDED entries.
+    pub needed: Vec<String>,
+    /// The multilib category, e.g. `x86_64`. Missing in files written by old Portage versions.
+    pub multilib_category: Option<String>,
+}
+
+impl NeededEntry {
+    /// Parses a single NEEDED.ELF.2 line.
+    /// Returns `None` if the line has less than five fields.
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let line = line.trim_end_matches(['\n', '\r']);
+        let mut fields = line.splitn(6, ';');
+        let arch = fields.next()?;
+        let object = fields.next()?;
+        let soname = fields.next()?;
+        let rpath = fields.next()?;
+        let needed = fields.next()?;
+        if object.is_empty() {
+            return None;
+        }
+        let list = |s: &str| s.split(',').filter(|e| !e.is_empty()).map(str::to_string).collect();
+        Some(NeededEntry {
+            arch: arch.to_string(),
+            object: PathBuf::from(object),
+            soname: soname.to_string(),
+            rpath: rpath.split(':').filter(|e| !e.is_empty()).map(str::to_string).collect(),
+            needed: list(needed),
+            multilib_category: fields.next().filter(|c| !c.is_empty()).map(str::to_string),
+        })
+    }
+
+    /// Returns the multilib category, falling back to the ELF machine for old entries.
+    pub fn category(&self) -> &str {
+        self.multilib_category.as_deref().unwrap_or(&self.arch)
+    }
+}
+
+/// The parsed NEEDED.ELF.2 file of an installed package.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NeededElf {
+    entries: Vec<NeededEntry>,
+}
+
+impl NeededElf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads and parses a NEEDED.ELF.2 file.
+    pub fn scan<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let content = fs::read(path)?;
+        Ok(Self::parse(&String::from_utf8_lossy(&content)))
+    }
+
+    /// Parses the content of a NEEDED.ELF.2 file.
+    /// Malformed lines are skipped.
+    pub fn parse(content: &str) -> Self {
+        let entries = content.lines().filter_map(NeededEntry::parse_line).collect();
+        Self { entries }
+    }
+
+    /// Returns all entries in file order.
+    pub fn entries(&self) -> &[NeededEntry] {
+        &self.entries
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, NeededEntry> {
+        self.entries.iter()
+    }
+
+    /// Returns the sonames of the objects, i.e. what the package provides.
+    pub fn provides(&self) -> Sonames {
+        let mut sonames = Sonames::new();
+        for entry in self.entries.iter().filter(|e| !e.soname.is_empty()) {
+            sonames.insert(entry.category(), &entry.soname);
+        }
+        sonames
+    }
+
+    /// Returns the sonames needed by the objects that the package does not provide itself.
+    pub fn requires(&self) -> Sonames {
+        let provides = self.provides();
+        let mut sonames = Sonames::new();
+        for entry in &self.entries {
+            for soname in entry.needed.iter().filter(|s| !provides.contains(entry.category(), s)) {
+                sonames.insert(entry.category(), soname);
+            }
+        }
+        sonames
+    }
+
+    /// Returns the objects of a multilib category linking against a soname.
+    pub fn objects_needing<'a>(&'a self, category: &'a str, soname: &'a str) -> impl Iterator<Item = &'a NeededEntry> {
+        self.entries
+            .iter()
+            .filter(move |e| e.category() == category && e.needed.iter().any(|n| n == soname))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<'a> IntoIterator for &'a NeededElf {
+    type Item = &'a NeededEntry;
+    type IntoIter = std::slice::Iter<'a, NeededEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+/// A soname required by a package that no installed package provides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenLinkage {
+    /// Index of the affected package in the slice the solver was built from.
+    pub package: usize,
+    pub category: String,
+    pub soname: String,
+}
+
+/// Finds packages with unresolved shared library dependencies, like
+/// `revdep-rebuild` or `emerge @preserved-rebuild` would.
+///
+/// The solver works on the PROVIDES and REQUIRES files, which are read on demand
+/// if the packages were loaded without them. A file that cannot be read is reported
+/// by `errors` and treated as empty. Use `VarDbPkg::needed` to find the objects
+/// affected by a broken soname.
+#[derive(Debug)]
+pub struct LinkageSolver<'a> {
+    packages: &'a [VarDbPkg],
+    provides: Vec<Sonames>,
+    requires: Vec<Sonames>,
+    errors: Vec<(usize, Error)>,
+}
+
+impl<'a> LinkageSolver<'a> {
+    pub fn new(packages: &'a [VarDbPkg]) -> Self {
+        let mut errors = Vec::new();
+        let mut sonames = |idx: usize, result: error::Result<Sonames>| {
+            result.unwrap_or_else(|e| {
+                errors.push((idx, e));
+                Sonames::default()
+            })
+        };
+        let provides = packages.iter().enumerate().map(|(i, p)| sonames(i, p.provided_sonames())).collect();
+        let requires = packages.iter().enumerate().map(|(i, p)| sonames(i, p.required_sonames())).collect();
+        LinkageSolver { packages, provides, requires, errors }
+    }
+
+    /// Returns the PROVIDES and REQUIRES files that could not be read, by package index.
+    pub fn errors(&self) -> &[(usize, Error)] {
+        &self.errors
+    }
+
+    /// Returns the packages the solver was built from.
+    pub fn packages(&self) -> &'a [VarDbPkg] {
+        self.packages
+    }
+
+    /// Returns the sonames required by installed packages but not provided by any of them.
+    pub fn broken(&self) -> Vec<BrokenLinkage> {
+        self.broken_without(&[])
+    }
+
+    /// Returns the linkage that would break if the given packages were uninstalled.
+    /// The removed packages themselves are not reported.
+    pub fn broken_without(&self, removed: &[usize]) -> Vec<BrokenLinkage> {
+        let removed: HashSet<usize> = removed.iter().copied().collect();
+        let mut provided: HashSet<(&str, &str)> = HashSet::new();
+        for (idx, sonames) in self.provides.iter().enumerate() {
+            if !removed.contains(&idx) {
+                provided.extend(sonames.iter());
+            }
+        }
+
+        let mut broken = Vec::new();
+        for (idx, sonames) in self.requires.iter().enumerate() {
+            if removed.contains(&idx) {
+                continue;
+            }
+            for (category, soname) in sonames.iter().filter(|pair| !provided.contains(pair)) {
+                broken.push(BrokenLinkage {
+                    package: idx,
+                    category: category.to_string(),
+                    soname: soname.to_string(),
+                });
+            }
+        }
+        broken
+    }
+
+    /// Returns the indices of the packages with broken linkage, i.e. the packages to rebuild.
+    pub fn packages_to_rebuild(&self) -> Vec<usize> {
+        let broken: BTreeSet<usize> = self.broken().into_iter().map(|b| b.package).collect();
+        broken.into_iter().collect()
+    }
+}
+
+#[derive(Debug, Default)]
+struct SonameUsers {
+    providers: BTreeSet<usize>,
+    consumers: BTreeSet<usize>,
+    objects: Vec<(usize, PathBuf)>,
+}
+
+/// Index of the sonames provided and used by the installed packages.
+///
+/// Answers which package provides a soname and which packages and objects link
+/// against it, e.g. to estimate the impact of an ABI change before bumping a library.
+/// Like `DepGraph`, packages are referred to by their index in the slice the index was built from.
+#[derive(Debug)]
+pub struct SonameIndex<'a> {
+    packages: &'a [VarDbPkg],
+    sonames: BTreeMap<String, BTreeMap<String, SonameUsers>>,
+    errors: Vec<(usize, Error)>,
+}
+
+impl<'a> SonameIndex<'a> {
+    /// Builds the index from PROVIDES, REQUIRES and the NEEDED.ELF.2 file of every package.
+    /// Packages without NEEDED.ELF.2 own no ELF objects; other read errors, including those of
+    /// PROVIDES and REQUIRES files read on demand, are kept in `errors`.
+    pub fn build(packages: &'a [VarDbPkg]) -> Self {
+        Self::with_needed(packages, |pkg| match pkg.needed() {
+            Err(Error::NotFound { .. }) => Ok(NeededElf::new()),
+            needed => needed,
+        })
+    }
+
+    /// Builds the index with the NEEDED.ELF.2 file of each package supplied by `needed`,
+    /// e.g. from a cache. `needed` is called once per package, in slice order.
+    pub fn with_needed<F>(packages: &'a [VarDbPkg], mut needed: F) -> Self
+    where
+        F: FnMut(&VarDbPkg) -> error::Result<NeededElf>,
+    {
+        let mut index = SonameIndex {
+            packages,
+            sonames: BTreeMap::new(),
+            errors: Vec::new(),
+        };
+
+        for (idx, pkg) in packages.iter().enumerate() {
+            match pkg.provided_sonames() {
+                Ok(sonames) => {
+                    for (category, soname) in sonames.iter() {
+                        index.users(category, soname).providers.insert(idx);
+                    }
+                }
+                Err(e) => index.errors.push((idx, e)),
+            }
+            match pkg.required_sonames() {
+                Ok(sonames) => {
+                    for (category, soname) in sonames.iter() {
+                        index.users(category, soname).consumers.insert(idx);
+                    }
+                }
+                Err(e) => index.errors.push((idx, e)),
+            }
+            let needed = match needed(pkg) {
+                Ok(needed) => needed,
+                Err(e) => {
+                    index.errors.push((idx, e));
+                    continue;
+                }
+            };
+            for entry in needed.entries() {
+                if !entry.soname.is_empty() {
+                    index.users(entry.category(), &entry.soname).providers.insert(idx);
+                }
+                for soname in &entry.needed {
+                    let users = index.users(entry.category(), soname);
+                    users.consumers.insert(idx);
+                    users.objects.push((idx, entry.object.clone()));
+                }
+            }
+        }
+
+        index
+    }
+
+    fn users(&mut self, category: &str, soname: &str) -> &mut SonameUsers {
+        self.sonames
+            .entry(category.to_string())
+            .or_default()
+            .entry(soname.to_string())
+            .or_default()
+    }
+
+    fn get(&self, category: &str, soname: &str) -> Option<&SonameUsers> {
+        self.sonames.get(category).and_then(|sonames| sonames.get(soname))
+    }
+
+    /// Returns the packages the index was built from.
+    pub fn packages(&self) -> &'a [VarDbPkg] {
+        self.packages
+    }
+
+    /// Returns the package for an index.
+    pub fn package(&self, idx: usize) -> &'a VarDbPkg {
+        &self.packages[idx]
+    }
+
+    /// Returns the PROVIDES, REQUIRES and NEEDED.ELF.2 files that could not be read.
+    pub fn errors(&self) -> &[(usize, Error)] {
+        &self.errors
+    }
+
+    /// Returns the packages providing a soname for a multilib category,
+    /// e.g. `providers("x86_64", "libssl.so.3")`.
+    pub fn providers(&self, category: &str, soname: &str) -> impl Iterator<Item = usize> + '_ {
+        self.get(category, soname).into_iter().flat_map(|u| u.providers.iter().copied())
+    }
+
+    /// Returns the packages linking against a soname.
+    pub fn consumers(&self, category: &str, soname: &str) -> impl Iterator<Item = usize> + '_ {
+        self.get(category, soname).into_iter().flat_map(|u| u.consumers.iter().copied())
+    }
+
+    /// Returns the ELF objects linking against a soname, with the package owning them.
+    pub fn objects(&self, category: &str, soname: &str) -> &[(usize, PathBuf)] {
+        self.get(category, soname).map(|u| u.objects.as_slice()).unwrap_or_default()
+    }
+
+    /// Returns the packages linking against any soname provided by a package,
+    /// i.e. the packages affected by an ABI change of it. The package itself is excluded.
+    pub fn reverse_linkage(&self, idx: usize) -> BTreeSet<usize> {
+        self.iter()
+            .filter(|(_, _, u)| u.providers.contains(&idx))
+            .flat_map(|(_, _, u)| u.consumers.iter().copied())
+            .filter(|&consumer| consumer != idx)
+            .collect()
+    }
+
+    /// Returns the multilib categories a soname is known in.
+    pub fn categories_of<'s>(&'s self, soname: &'s str) -> impl Iterator<Item = &'s str> {
+        self.sonames
+            .iter()
+            .filter(move |(_, sonames)| sonames.contains_key(soname))
+            .map(|(category, _)| category.as_str())
+    }
+
+    /// Iterates over all known `(category, soname)` pairs, sorted.
+    pub fn sonames(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.iter().map(|(category, soname, _)| (category, soname))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&str, &str, &SonameUsers)> {
+        self.sonames.iter().flat_map(|(category, sonames)| {
+            sonames.iter().map(move |(soname, users)| (category.as_str(), soname.as_str(), users))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::Fields;
+    use crate::split_package_version;
+
+    fn pkg(cpv: &str, provides: &str, requires: &str) -> VarDbPkg {
+        let (category, rest) = cpv.split_once('/').unwrap();
+        let (package, version) = split_package_version(rest);
+        VarDbPkg {
+            category: category.to_string(),
+            package,
+            version,
+            provides: provides.to_string(),
+            requires: requires.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_sonames() {
+        let sonames = Sonames::parse("x86_32: libhistory.so.8 libreadline.so.8\nx86_64: libreadline.so.8\n");
+        assert_eq!(sonames.len(), 3);
+        assert!(sonames.contains("x86_32", "libhistory.so.8"));
+        assert!(!sonames.contains("x86_64", "libhistory.so.8"));
+        assert_eq!(sonames.categories().collect::<Vec<_>>(), ["x86_32", "x86_64"]);
+        assert_eq!(sonames.to_string(), "x86_32: libhistory.so.8 libreadline.so.8\nx86_64: libreadline.so.8");
+        assert!(Sonames::parse("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_needed() {
+        let needed = NeededElf::parse(
+            "X86_64;/usr/lib64/libreadline.so.8.2;libreadline.so.8;$ORIGIN:/opt/lib;libtinfo.so.6,libc.so.6;x86_64\n\
+             X86_64;/usr/bin/rlfe;;;libreadline.so.8,libc.so.6;x86_64\n\
+             386;/usr/lib/libold.so.1;libold.so.1;;libc.so.6\n\
+             garbage\n",
+        );
+        assert_eq!(needed.len(), 3);
+        let lib = &needed.entries()[0];
+        assert_eq!(lib.object, Path::new("/usr/lib64/libreadline.so.8.2"));
+        assert_eq!(lib.rpath, ["$ORIGIN", "/opt/lib"]);
+        assert_eq!(lib.needed, ["libtinfo.so.6", "libc.so.6"]);
+        assert_eq!(lib.category(), "x86_64");
+        assert_eq!(needed.entries()[2].category(), "386");
+
+        assert_eq!(needed.provides().to_string(), "386: libold.so.1\nx86_64: libreadline.so.8");
+        // libreadline.so.8 is provided by the package itself
+        assert_eq!(needed.requires().to_string(), "386: libc.so.6\nx86_64: libc.so.6 libtinfo.so.6");
+        let users: Vec<_> = needed.objects_needing("x86_64", "libreadline.so.8").map(|e| &e.object).collect();
+        assert_eq!(users, [Path::new("/usr/bin/rlfe")]);
+    }
+
+    #[test]
+    fn test_broken_linkage() {
+        let packages = vec![
+            /* 0 */ pkg("sys-libs/glibc-2.40-r8", "x86_64: libc.so.6", ""),
+            /* 1 */ pkg("sys-libs/readline-8.2_p13", "x86_64: libreadline.so.8", "x86_64: libc.so.6 libtinfo.so.6"),
+            /* 2 */ pkg("app-shells/bash-5.2_p37", "", "x86_64: libc.so.6 libreadline.so.8"),
+            /* 3 */ pkg("app-emulation/wine-9.0", "", "x86_32: libc.so.6"),
+        ];
+        let solver = LinkageSolver::new(&packages);
+        let broken = solver.broken();
+        assert_eq!(
+            broken,
+            [
+                BrokenLinkage { package: 1, category: "x86_64".to_string(), soname: "libtinfo.so.6".to_string() },
+                BrokenLinkage { package: 3, category: "x86_32".to_string(), soname: "libc.so.6".to_string() },
+            ]
+        );
+        assert_eq!(solver.packages_to_rebuild(), [1, 3]);
+
+        let after_removal: Vec<(usize, String)> =
+            solver.broken_without(&[1]).into_iter().map(|b| (b.package, b.soname)).collect();
+        assert_eq!(after_removal, [(2, "libreadline.so.8".to_string()), (3, "libc.so.6".to_string())]);
+    }
+
+    #[test]
+    fn test_solver_reads_unloaded_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        for (cpv, provides, requires) in [
+            ("sys-libs/glibc-2.40-r8", "x86_64: libc.so.6\n", ""),
+            ("app-shells/bash-5.2_p37", "", "x86_64: libc.so.6 libreadline.so.8\n"),
+        ] {
+            let pkg_path = dir.path().join(cpv);
+            fs::create_dir_all(&pkg_path).unwrap();
+            fs::write(pkg_path.join("PROVIDES"), provides).unwrap();
+            fs::write(pkg_path.join("REQUIRES"), requires).unwrap();
+        }
+        fs::write(dir.path().join("sys-libs/glibc-2.40-r8/REQUIRES"), b"\xff").unwrap();
+
+        let packages = crate::try_parse_vardb_with(dir.path(), Fields::SLOT).unwrap().packages;
+        let solver = LinkageSolver::new(&packages);
+        let broken: Vec<(usize, String)> = solver.broken().into_iter().map(|b| (b.package, b.soname)).collect();
+        assert_eq!(broken, [(0, "libreadline.so.8".to_string())]);
+        assert_eq!(solver.errors().len(), 1);
+        assert_eq!(solver.errors()[0].0, 1);
+    }
+
+    #[test]
+    fn test_soname_index() {
+        let packages = crate::parse_vardb(Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/vardb"));
+        let index = SonameIndex::build(&packages);
+        assert!(index.errors().is_empty());
+        let name = |idx: usize| index.package(idx).package.as_str();
+
+        assert_eq!(index.providers("x86_64", "libreadline.so.8").map(name).collect::<Vec<_>>(), ["readline"]);
+        assert_eq!(index.providers("x86_64", "libtinfo.so.6").count(), 0);
+        assert_eq!(index.consumers("x86_64", "libc.so.6").map(name).collect::<Vec<_>>(), ["bash", "glibc", "readline"]);
+        assert_eq!(index.consumers("x86_32", "libreadline.so.8").count(), 0);
+        assert_eq!(
+            index.objects("x86_64", "libreadline.so.8"),
+            [(0, PathBuf::from("/bin/bash"))]
+        );
+        assert_eq!(index.categories_of("libhistory.so.8").collect::<Vec<_>>(), ["x86_32", "x86_64"]);
+        assert_eq!(index.reverse_linkage(1).into_iter().map(name).collect::<Vec<_>>(), ["bash", "readline"]);
+        assert!(index.sonames().any(|s| s == ("x86_32", "ld-linux.so.2")));
+    }
+
+    #[test]
+    fn test_soname_index_errors() {
+        let packages = vec![pkg("sys-libs/zlib-1.3.1", "x86_64: libz.so.1", ""), pkg("app-misc/a-1", "", "x86_64: libz.so.1")];
+        let index = SonameIndex::with_needed(&packages, |pkg| match pkg.package.as_str() {
+            "zlib" => Ok(NeededElf::new()),
+            _ => Err(Error::InvalidUtf8 { path: PathBuf::from("NEEDED.ELF.2") }),
+        });
+        assert_eq!(index.errors().len(), 1);
+        assert_eq!(index.errors()[0].0, 1);
+        // PROVIDES and REQUIRES are indexed even without NEEDED.ELF.2
+        assert_eq!(index.consumers("x86_64", "libz.so.1").collect::<Vec<_>>(), [1]);
+        assert_eq!(index.reverse_linkage(0), BTreeSet::from([1]));
+    }
+}
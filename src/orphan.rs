@@ -0,0 +1,241 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::contents::EntryType;
+use crate::error::Error;
+use crate::owner::{glob_match, resolve_in_root, OwnerIndex};
+use crate::verify::entry_type_of;
+
+/// A path not owned by any installed package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Orphan {
+    /// The absolute path, without the finder's root.
+    pub path: PathBuf,
+    /// The file type, `None` for sockets and other types CONTENTS cannot record.
+    pub entry_type: Option<EntryType>,
+}
+
+/// The result of `OrphanFinder::find`.
+#[derive(Debug, Default)]
+pub struct OrphanScan {
+    /// Orphaned paths, sorted. An orphaned directory is reported once, without its content.
+    pub orphans: Vec<Orphan>,
+    /// Directories that could not be listed.
+    pub errors: Vec<Error>,
+}
+
+/// Finds files not owned by any installed package, like `qfile -o` on a whole directory tree.
+///
+/// ```no_run
+/// use vardbpkg::orphan::OrphanFinder;
+/// use vardbpkg::owner::OwnerIndex;
+///
+/// let packages = vardbpkg::parse_vardb("/var/db/pkg");
+/// let mut index = OwnerIndex::build(&packages);
+/// index.detect_dir_symlinks("/");
+/// let scan = OrphanFinder::new(&index).ignore("/usr/**/__pycache__").find("/usr");
+/// for orphan in scan.orphans {
+///     println!("{}", orphan.path.display());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct OrphanFinder<'a> {
+    index: &'a OwnerIndex,
+    /// Directories containing owned paths, even if no package lists the directory itself.
+    parents: HashSet<PathBuf>,
+    root: PathBuf,
+    ignore: Vec<String>,
+}
+
+impl<'a> OrphanFinder<'a> {
+    pub fn new(index: &'a OwnerIndex) -> Self {
+        let mut parents = HashSet::new();
+        for owner in index.entries() {
+            let path = index.canonical(&owner.path);
+            for parent in path.ancestors().skip(1) {
+                if !parents.insert(parent.to_path_buf()) {
+                    break;
+                }
+            }
+        }
+        OrphanFinder {
+            index,
+            parents,
+            root: PathBuf::from("/"),
+            ignore: Vec::new(),
+        }
+    }
+
+    /// Walks the files below another root, e.g. a mounted image.
+    pub fn with_root<P: AsRef<Path>>(mut self, root: P) -> Self {
+        self.root = root.as_ref().to_path_buf();
+        self
+    }
+
+    /// Skips paths matching a glob pattern, see `OwnerIndex::find_glob` for the syntax.
+    /// Patterns are matched against the absolute path; a matching directory is not entered.
+    pub fn ignore(mut self, pattern: &str) -> Self {
+        self.ignore.push(pattern.to_string());
+        self
+    }
+
+    /// Adds several ignore patterns.
+    pub fn with_ignore_patterns<I, S>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.ignore.extend(patterns.into_iter().map(|p| p.as_ref().to_string()));
+        self
+    }
+
+    /// Returns true if the path matches an ignore pattern.
+    pub fn is_ignored<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = path.as_ref().to_string_lossy();
+        self.ignore.iter().any(|pattern| glob_match(pattern, &path))
+    }
+
+    /// Walks an absolute directory, e.g. `/usr`, and returns the paths no package owns.
+    /// Symlinks are reported or skipped like files and never followed.
+    pub fn find<P: AsRef<Path>>(&self, dir: P) -> OrphanScan {
+        let mut scan = OrphanScan::default();
+        let mut todo = vec![dir.as_ref().to_path_buf()];
+
+        while let Some(dir) = todo.pop() {
+            let on_disk = self.on_disk(&dir);
+            let entries = match resolve_in_root(&self.root, &dir, true).and_then(fs::read_dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    scan.errors.push(Error::from_read_dir(&on_disk, e));
+                    continue;
+                }
+            };
+            let mut children: Vec<(PathBuf, Option<EntryType>)> = Vec::new();
+            for entry in entries {
+                match entry {
+                    Ok(entry) => {
+                        let entry_type = entry.file_type().ok().and_then(|t| entry_type_of(&t));
+                        children.push((dir.join(entry.file_name()), entry_type));
+                    }
+                    Err(e) => scan.errors.push(Error::from_read_dir(&on_disk, e)),
+                }
+            }
+
+            for (path, entry_type) in children {
+                if self.is_ignored(&path) {
+                    continue;
+                }
+                if entry_type == Some(EntryType::Dir) && self.parents.contains(&self.index.canonical(&path)) {
+                    todo.push(path);
+                } else if !self.index.contains(&path) {
+                    scan.orphans.push(Orphan { path, entry_type });
+                } else if entry_type == Some(EntryType::Dir) {
+                    todo.push(path);
+                }
+            }
+        }
+
+        scan.orphans.sort_by(|a, b| a.path.cmp(&b.path));
+        scan
+    }
+
+    /// Returns the path below the root, for error messages.
+    fn on_disk(&self, path: &Path) -> PathBuf {
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contents::Contents;
+    use crate::VarDbPkg;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_find_orphans() {
+        let root = tempdir().unwrap();
+        for dir in ["usr/bin", "usr/lib64/python3.12/__pycache__", "usr/local/share", "usr/share/cruft/deep"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+        }
+        for file in [
+            "usr/bin/bash",
+            "usr/bin/stray",
+            "usr/lib64/libfoo.so.1",
+            "usr/lib64/python3.12/os.py",
+            "usr/lib64/python3.12/__pycache__/os.pyc",
+            "usr/local/share/mine",
+            "usr/share/cruft/deep/file",
+        ] {
+            fs::write(root.path().join(file), "").unwrap();
+        }
+        std::os::unix::fs::symlink("bash", root.path().join("usr/bin/sh")).unwrap();
+
+        let mut index = OwnerIndex::new();
+        let pkg = VarDbPkg {
+            category: "app-shells".to_string(),
+            package: "bash".to_string(),
+            version: "5.2_p37".to_string(),
+            ..Default::default()
+        };
+        // /usr/lib64 is not listed as directory, but contains an owned file
+        index.add_package(
+            &pkg,
+            &Contents::parse(
+                "dir /usr\ndir /usr/bin\nobj /usr/bin/bash 0 1\nobj /usr/lib64/libfoo.so.1 0 1\n\
+                 obj /usr/lib64/python3.12/os.py 0 1\ndir /usr/share\n",
+            ),
+        );
+
+        let finder = OrphanFinder::new(&index)
+            .with_root(root.path())
+            .ignore("/usr/local")
+            .with_ignore_patterns(["**/__pycache__"]);
+        let scan = finder.find("/usr");
+        assert!(scan.errors.is_empty());
+        let orphans: Vec<(&str, Option<EntryType>)> =
+            scan.orphans.iter().map(|o| (o.path.to_str().unwrap(), o.entry_type)).collect();
+        assert_eq!(
+            orphans,
+            [
+                ("/usr/bin/sh", Some(EntryType::Sym)),
+                ("/usr/bin/stray", Some(EntryType::Obj)),
+                ("/usr/share/cruft", Some(EntryType::Dir)),
+            ]
+        );
+
+        let scan = finder.find("/missing");
+        assert!(scan.orphans.is_empty());
+        assert!(matches!(scan.errors[..], [Error::NotFound { .. }]));
+    }
+
+    #[test]
+    fn test_merged_usr() {
+        let root = tempdir().unwrap();
+        fs::create_dir_all(root.path().join("usr/lib")).unwrap();
+        std::os::unix::fs::symlink("usr/lib", root.path().join("lib")).unwrap();
+        fs::write(root.path().join("usr/lib/libc.so.6"), "").unwrap();
+        fs::write(root.path().join("usr/lib/orphan.so"), "").unwrap();
+
+        let mut index = OwnerIndex::new();
+        index.add_package(&VarDbPkg::default(), &Contents::parse("dir /lib\nobj /lib/libc.so.6 0 1\n"));
+        index.add_dir_alias("/lib", "usr/lib");
+
+        let scan = OrphanFinder::new(&index).with_root(root.path()).find("/usr/lib");
+        assert_eq!(scan.orphans.iter().map(|o| o.path.as_path()).collect::<Vec<_>>(), [Path::new("/usr/lib/orphan.so")]);
+    }
+
+    #[test]
+    fn test_absolute_symlink_stays_in_root() {
+        let root = tempdir().unwrap();
+        fs::create_dir_all(root.path().join("usr/lib64")).unwrap();
+        fs::write(root.path().join("usr/lib64/orphan.so"), "").unwrap();
+        // Resolved inside the image; on the host /usr/lib64 would be listed instead
+        std::os::unix::fs::symlink("/usr/lib64", root.path().join("lib64")).unwrap();
+
+        let index = OwnerIndex::new();
+        let scan = OrphanFinder::new(&index).with_root(root.path()).find("/lib64");
+        assert!(scan.errors.is_empty());
+        assert_eq!(scan.orphans.iter().map(|o| o.path.as_path()).collect::<Vec<_>>(), [Path::new("/lib64/orphan.so")]);
+    }
+}
@@ -0,0 +1,521 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use crate::error::Error;
+use crate::version::Version;
+use crate::VarDbPkg;
+
+/// The package of an OSV `affected` entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OsvPackage {
+    /// e.g. `crates.io`, `OSS-Fuzz` or `Debian:12`.
+    pub ecosystem: String,
+    pub name: String,
+    pub purl: Option<String>,
+}
+
+/// An event of a version range.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    /// The first vulnerable version, `0` for all versions before the next event.
+    Introduced(String),
+    /// The first version that is no longer vulnerable.
+    Fixed(String),
+    /// The last vulnerable version.
+    LastAffected(String),
+    /// Versions from here on are not covered by the range.
+    Limit(String),
+}
+
+/// A version range of an OSV `affected` entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OsvRange {
+    /// `ECOSYSTEM`, `SEMVER` or `GIT`. `GIT` ranges hold commit hashes and are never matched.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub events: Vec<Event>,
+}
+
+impl OsvRange {
+    /// Returns true if an upstream version is inside the range, comparing versions by PMS rules.
+    /// Ranges that cannot be evaluated are treated as not containing the version, see `evaluate`.
+    pub fn contains(&self, version: &Version) -> bool {
+        self.evaluate(version) == Some(true)
+    }
+
+    /// Returns whether an upstream version is inside the range, comparing versions by PMS rules.
+    /// Returns `None` if an event holds something that is not a PMS version, e.g. the Debian
+    /// version `2.36-9+deb12u4` or the SemVer version `1.2.0-rc.1`, as ignoring the event
+    /// would widen the range.
+    pub fn evaluate(&self, version: &Version) -> Option<bool> {
+        if self.kind == "GIT" {
+            return Some(false);
+        }
+        let mut events: Vec<(Option<Version>, &Event)> = self
+            .events
+            .iter()
+            .map(|event| match event {
+                Event::Introduced(v) if v == "0" => Some((None, event)),
+                Event::Introduced(v) | Event::Fixed(v) | Event::LastAffected(v) | Event::Limit(v) => {
+                    Version::parse(v).ok().map(|v| (Some(v), event))
+                }
+            })
+            .collect::<Option<_>>()?;
+        events.sort_by(|(a, _), (b, _)| match (a, b) {
+            (None, None) => Ordering::Equal,
+            (None, _) => Ordering::Less,
+            (_, None) => Ordering::Greater,
+            (Some(a), Some(b)) => a.cmp(b),
+        });
+
+        // The evaluation of the OSV schema, see https://ossf.github.io/osv-schema/#evaluation.
+        // Limits cap the whole range: a version at or above every limit is never affected.
+        let mut limits = events.iter().filter(|(_, event)| matches!(event, Event::Limit(_))).peekable();
+        if limits.peek().is_some() && limits.all(|(limit, _)| limit.as_ref().is_some_and(|l| version >= l)) {
+            return Some(false);
+        }
+        let mut affected = false;
+        for (event_version, event) in events {
+            let ord = event_version.as_ref().map_or(Ordering::Greater, |v| version.cmp(v));
+            match event {
+                Event::Introduced(_) if ord != Ordering::Less => affected = true,
+                Event::Fixed(_) if ord != Ordering::Less => affected = false,
+                Event::LastAffected(_) if ord == Ordering::Greater => affected = false,
+                _ => {}
+            }
+        }
+        Some(affected)
+    }
+}
+
+/// An `affected` entry of a vulnerability.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OsvAffected {
+    pub package: OsvPackage,
+    pub ranges: Vec<OsvRange>,
+    /// Explicitly listed vulnerable versions.
+    pub versions: Vec<String>,
+}
+
+impl OsvAffected {
+    /// Returns true if an upstream version is listed in `versions` or inside one of the ranges.
+    /// Ranges that cannot be evaluated are treated as not containing the version, see `evaluate`.
+    pub fn contains(&self, version: &str) -> bool {
+        self.evaluate(version) == Some(true)
+    }
+
+    /// Returns whether an upstream version is listed in `versions` or inside one of the ranges.
+    /// Returns `None` if it is neither listed nor inside a range, but a range could not be
+    /// evaluated, e.g. because it holds versions of another ecosystem.
+    pub fn evaluate(&self, version: &str) -> Option<bool> {
+        let parsed = Version::parse(version).ok();
+        let listed = self.versions.iter().any(|v| match (&parsed, Version::parse(v)) {
+            (Some(installed), Ok(listed)) => *installed == listed,
+            _ => v == version,
+        });
+        if listed {
+            return Some(true);
+        }
+        let mut evaluable = true;
+        for range in &self.ranges {
+            match parsed.as_ref().and_then(|v| range.evaluate(v)) {
+                Some(true) => return Some(true),
+                Some(false) => {}
+                None => evaluable = false,
+            }
+        }
+        evaluable.then_some(false)
+    }
+}
+
+/// A reference of a vulnerability.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OsvReference {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub url: String,
+}
+
+/// A vulnerability in the OSV format, as published by osv.dev and many advisory databases.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Vulnerability {
+    pub id: String,
+    pub modified: String,
+    pub published: String,
+    /// Other ids of the same vulnerability, e.g. CVE identifiers.
+    pub aliases: Vec<String>,
+    pub summary: String,
+    pub details: String,
+    pub affected: Vec<OsvAffected>,
+    pub references: Vec<OsvReference>,
+}
+
+/// Maps OSV packages to Gentoo `category/package` names.
+///
+/// Packages with an `ebuild` package URL are mapped by it; all others need an entry,
+/// as OSV names follow the conventions of their ecosystem.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageMap {
+    entries: BTreeMap<(String, String), Vec<String>>,
+}
+
+impl PackageMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps a package of an ecosystem to a Gentoo package. A package may map to several,
+    /// e.g. `dev-lang/rust` and `dev-lang/rust-bin`. Use `*` as ecosystem to match any.
+    pub fn insert(&mut self, ecosystem: &str, name: &str, cp: &str) {
+        let cps = self.entries.entry((ecosystem.to_string(), name.to_string())).or_default();
+        if !cps.iter().any(|c| c == cp) {
+            cps.push(cp.to_string());
+        }
+    }
+
+    /// Adds the entries of a mapping file, one `ecosystem name category/package` line per mapping.
+    /// Comments and malformed lines are skipped.
+    pub fn with_mappings(mut self, content: &str) -> Self {
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            if let [ecosystem, name, cp] = line.split_whitespace().collect::<Vec<_>>()[..] {
+                self.insert(ecosystem, name, cp);
+            }
+        }
+        self
+    }
+
+    /// Returns the Gentoo packages an OSV package refers to.
+    pub fn map(&self, package: &OsvPackage) -> Vec<String> {
+        let mut cps: Vec<String> = Vec::new();
+        for ecosystem in [package.ecosystem.as_str(), "*"] {
+            let entries = self.entries.get(&(ecosystem.to_string(), package.name.clone()));
+            cps.extend(entries.into_iter().flatten().cloned());
+        }
+        cps.extend(package.purl.as_deref().and_then(ebuild_purl_cp));
+        let mut seen = Vec::new();
+        cps.retain(|cp| {
+            let new = !seen.contains(cp);
+            seen.push(cp.clone());
+            new
+        });
+        cps
+    }
+}
+
+/// Returns `category/package` of a `pkg:ebuild/` package URL.
+fn ebuild_purl_cp(purl: &str) -> Option<String> {
+    let rest = purl.strip_prefix("pkg:ebuild/")?;
+    let end = rest.find(['@', '?', '#']).unwrap_or(rest.len());
+    let cp = rest[..end].replace("%2B", "+").replace("%2b", "+");
+    cp.contains('/').then_some(cp)
+}
+
+/// An installed package affected by a vulnerability.
+#[derive(Debug, Clone, Copy)]
+pub struct OsvMatch<'a> {
+    /// Index of the package in the slice passed to `OsvDb::check`.
+    pub package: usize,
+    pub vulnerability: &'a Vulnerability,
+}
+
+/// The result of `OsvDb::check`.
+#[derive(Debug, Default)]
+pub struct OsvReport<'a> {
+    /// The installed packages affected by a vulnerability.
+    pub matches: Vec<OsvMatch<'a>>,
+    /// Vulnerabilities of installed packages whose ranges could not be evaluated, e.g. because
+    /// they hold Debian or SemVer versions. These need to be checked by other means.
+    pub skipped: Vec<OsvMatch<'a>>,
+}
+
+/// A set of OSV vulnerabilities loaded from disk.
+///
+/// ```no_run
+/// use vardbpkg::osv::{OsvDb, PackageMap};
+///
+/// let packages = vardbpkg::parse_vardb("/var/db/pkg");
+/// let db = OsvDb::scan("/var/cache/osv/crates.io").unwrap();
+/// let map = PackageMap::new().with_mappings("crates.io openssl dev-libs/openssl\n");
+/// for m in db.check(&packages, &map).matches {
+///     println!("{}: {}", m.vulnerability.id, packages[m.package].package);
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct OsvDb {
+    vulnerabilities: Vec<Vulnerability>,
+    /// Files that could not be read or parsed.
+    pub errors: Vec<Error>,
+}
+
+impl OsvDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a JSON file or all `*.json` files of a directory, e.g. an extracted osv.dev dump.
+    /// A file holds a single vulnerability or an array of them.
+    /// Fails only if the path cannot be read; invalid files are collected in `errors`.
+    pub fn scan<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut db = OsvDb::new();
+        if !path.is_dir() {
+            db.load_file(path)?;
+            return Ok(db);
+        }
+        let mut files = Vec::new();
+        for entry in fs::read_dir(path).map_err(|e| Error::from_read_dir(path, e))? {
+            match entry {
+                Ok(entry) if entry.path().extension().is_some_and(|e| e == "json") => files.push(entry.path()),
+                Ok(_) => {}
+                Err(e) => db.errors.push(Error::from_read_dir(path, e)),
+            }
+        }
+        files.sort();
+        for file in files {
+            if let Err(e) = db.load_file(&file) {
+                db.errors.push(e);
+            }
+        }
+        Ok(db)
+    }
+
+    fn load_file(&mut self, path: &Path) -> Result<(), Error> {
+        let content = fs::read_to_string(path).map_err(|e| Error::from_io(path, e))?;
+        let vulnerabilities = Self::parse(&content).map_err(|e| Error::InvalidFile {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        self.vulnerabilities.extend(vulnerabilities);
+        Ok(())
+    }
+
+    /// Parses a JSON document holding a single vulnerability or an array of them.
+    pub fn parse(json: &str) -> Result<Vec<Vulnerability>, serde_json::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Document {
+            Many(Vec<Vulnerability>),
+            One(Box<Vulnerability>),
+        }
+        Ok(match serde_json::from_str(json)? {
+            Document::Many(vulnerabilities) => vulnerabilities,
+            Document::One(vulnerability) => vec![*vulnerability],
+        })
+    }
+
+    pub fn insert(&mut self, vulnerability: Vulnerability) {
+        self.vulnerabilities.push(vulnerability);
+    }
+
+    pub fn vulnerabilities(&self) -> &[Vulnerability] {
+        &self.vulnerabilities
+    }
+
+    /// Returns the vulnerability with the given id or alias, e.g. a CVE identifier.
+    pub fn get(&self, id: &str) -> Option<&Vulnerability> {
+        self.vulnerabilities.iter().find(|v| v.id == id || v.aliases.iter().any(|a| a == id))
+    }
+
+    pub fn len(&self) -> usize {
+        self.vulnerabilities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vulnerabilities.is_empty()
+    }
+
+    /// Checks installed packages, mapping OSV packages with a `PackageMap`.
+    /// The result is ordered by package, then by the order the vulnerabilities were loaded.
+    pub fn check<'a>(&'a self, packages: &[VarDbPkg], map: &PackageMap) -> OsvReport<'a> {
+        self.check_with(packages, |package| map.map(package))
+    }
+
+    /// Checks installed packages with a custom mapping from OSV packages to Gentoo
+    /// `category/package` names. Versions are compared without the Gentoo revision.
+    /// A vulnerability is reported as skipped for a package only if none of its entries match.
+    pub fn check_with<'a, F>(&'a self, packages: &[VarDbPkg], mut map: F) -> OsvReport<'a>
+    where
+        F: FnMut(&OsvPackage) -> Vec<String>,
+    {
+        // category/package -> (vulnerability, affected entry)
+        let mut index: BTreeMap<String, Vec<(&Vulnerability, &OsvAffected)>> = BTreeMap::new();
+        for vulnerability in &self.vulnerabilities {
+            for affected in &vulnerability.affected {
+                for cp in map(&affected.package) {
+                    index.entry(cp).or_default().push((vulnerability, affected));
+                }
+            }
+        }
+
+        let mut report = OsvReport::default();
+        for (idx, pkg) in packages.iter().enumerate() {
+            let Some(entries) = index.get(&format!("{}/{}", pkg.category, pkg.package)) else {
+                continue;
+            };
+            let version = match pkg.parsed_version() {
+                Ok(version) => version.without_revision().to_string(),
+                Err(_) => pkg.version.clone(),
+            };
+            let mut seen: Vec<&str> = Vec::new();
+            let mut skipped: Vec<&Vulnerability> = Vec::new();
+            for (vulnerability, affected) in entries {
+                if seen.contains(&vulnerability.id.as_str()) {
+                    continue;
+                }
+                match affected.evaluate(&version) {
+                    Some(true) => {
+                        seen.push(&vulnerability.id);
+                        report.matches.push(OsvMatch { package: idx, vulnerability });
+                    }
+                    Some(false) => {}
+                    None if skipped.iter().any(|v| v.id == vulnerability.id) => {}
+                    None => skipped.push(vulnerability),
+                }
+            }
+            for vulnerability in skipped.into_iter().filter(|v| !seen.contains(&v.id.as_str())) {
+                report.skipped.push(OsvMatch { package: idx, vulnerability });
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn testdata() -> &'static Path {
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata"))
+    }
+
+    fn range(events: &str) -> OsvRange {
+        serde_json::from_str(&format!("{{\"type\": \"ECOSYSTEM\", \"events\": {}}}", events)).unwrap()
+    }
+
+    #[test]
+    fn test_range_evaluation() {
+        let v = |s| Version::parse(s).unwrap();
+        let r = range(r#"[{"introduced": "0"}, {"fixed": "1.25.4"}]"#);
+        assert!(r.contains(&v("1.0")));
+        assert!(r.contains(&v("1.25.4_rc1")));
+        assert!(!r.contains(&v("1.25.4")));
+
+        // Two ranges in one, unordered
+        let r = range(r#"[{"fixed": "1.2.5"}, {"introduced": "1.2"}, {"introduced": "2.0"}, {"last_affected": "2.1"}]"#);
+        assert!(!r.contains(&v("1.1")));
+        assert!(r.contains(&v("1.2.4")));
+        assert!(!r.contains(&v("1.3")));
+        assert!(r.contains(&v("2.1")));
+        assert!(!r.contains(&v("2.1.1")));
+
+        let r = range(r#"[{"introduced": "1.0"}, {"limit": "2.0"}]"#);
+        assert!(r.contains(&v("1.9")));
+        assert!(!r.contains(&v("2.0")));
+        // A limit also applies to events after it
+        let r = range(r#"[{"introduced": "1.0"}, {"limit": "2.0"}, {"introduced": "3.0"}]"#);
+        assert!(r.contains(&v("1.5")));
+        assert!(!r.contains(&v("2.5")));
+        assert!(!r.contains(&v("3.1")));
+
+        let mut git = range(r#"[{"introduced": "0"}]"#);
+        git.kind = "GIT".to_string();
+        assert!(!git.contains(&v("1.0")));
+
+        // Versions of other ecosystems make the range unusable instead of open-ended
+        let debian = range(r#"[{"introduced": "0"}, {"fixed": "2.36-9+deb12u4"}]"#);
+        assert_eq!(debian.evaluate(&v("2.40")), None);
+        assert!(!debian.contains(&v("2.40")));
+        let semver = range(r#"[{"introduced": "1.2.0-rc.1"}, {"fixed": "1.2.3"}]"#);
+        assert_eq!(semver.evaluate(&v("1.0")), None);
+    }
+
+    #[test]
+    fn test_affected_evaluation() {
+        let affected: OsvAffected = serde_json::from_str(
+            r#"{"versions": ["2.36-9+deb12u4"], "ranges": [
+                {"type": "ECOSYSTEM", "events": [{"introduced": "0"}, {"fixed": "2.36-9+deb12u4"}]},
+                {"type": "ECOSYSTEM", "events": [{"introduced": "2.40"}, {"fixed": "2.41"}]}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(affected.evaluate("2.40.1"), Some(true));
+        assert_eq!(affected.evaluate("2.36-9+deb12u4"), Some(true));
+        assert_eq!(affected.evaluate("2.39"), None);
+        assert!(!affected.contains("2.39"));
+    }
+
+    #[test]
+    fn test_package_map() {
+        let map = PackageMap::new().with_mappings(
+            "# Rust toolchain\ncrates.io std dev-lang/rust\n* rust dev-lang/rust\n* rust dev-lang/rust-bin\nmalformed line\n",
+        );
+        let package = |ecosystem: &str, name: &str, purl: Option<&str>| OsvPackage {
+            ecosystem: ecosystem.to_string(),
+            name: name.to_string(),
+            purl: purl.map(str::to_string),
+        };
+        assert_eq!(map.map(&package("crates.io", "std", None)), ["dev-lang/rust"]);
+        assert_eq!(map.map(&package("GIT", "rust", None)), ["dev-lang/rust", "dev-lang/rust-bin"]);
+        assert!(map.map(&package("PyPI", "std", None)).is_empty());
+        assert_eq!(map.map(&package("Gentoo", "gtk", Some("pkg:ebuild/x11-libs/gtk%2B@2.24"))), ["x11-libs/gtk+"]);
+    }
+
+    #[test]
+    fn test_check_testdata() {
+        let packages = crate::parse_vardb(testdata().join("vardb"));
+        let db = OsvDb::scan(testdata().join("osv")).unwrap();
+        assert!(db.errors.is_empty(), "{:?}", db.errors);
+        assert_eq!(db.len(), 6);
+        assert_eq!(db.get("CVE-2025-1001").unwrap().id, "OSV-2025-1001");
+
+        let map = PackageMap::new().with_mappings("OSS-Fuzz readline sys-libs/readline\n");
+        let ids = |found: &[OsvMatch]| -> Vec<(String, String)> {
+            found
+                .iter()
+                .map(|m| (packages[m.package].package.clone(), m.vulnerability.id.clone()))
+                .collect()
+        };
+        let report = db.check(&packages, &map);
+        assert_eq!(
+            ids(&report.matches),
+            [("bash".into(), "OSV-2025-3001".into()), ("readline".into(), "OSV-2025-1001".into())]
+        );
+        assert!(report.skipped.is_empty());
+
+        // The hook decides alone, without the ebuild purl fallback.
+        // Debian and SemVer versions cannot be compared to Gentoo ones, so their ranges are skipped.
+        let report = db.check_with(&packages, |p| match p.name.as_str() {
+            "glibc" => vec!["sys-libs/glibc".to_string()],
+            "readline-sys" => vec!["sys-libs/readline".to_string()],
+            _ => Vec::new(),
+        });
+        assert_eq!(ids(&report.matches), [("glibc".into(), "OSV-2025-2002".into())]);
+        assert_eq!(
+            ids(&report.skipped),
+            [("glibc".into(), "DEBIAN-CVE-2025-4802".into()), ("readline".into(), "GHSA-7q4h-5v2m-9xw3".into())]
+        );
+    }
+
+    #[test]
+    fn test_scan_errors() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.json"), r#"{"id": "OSV-1", "affected": []}"#).unwrap();
+        fs::write(dir.path().join("b.json"), "{").unwrap();
+        fs::write(dir.path().join("c.txt"), "{").unwrap();
+        let db = OsvDb::scan(dir.path()).unwrap();
+        assert_eq!(db.len(), 1);
+        assert!(matches!(&db.errors[..], [Error::InvalidFile { .. }]));
+        assert_eq!(OsvDb::scan(dir.path().join("a.json")).unwrap().len(), 1);
+        assert!(matches!(OsvDb::scan(dir.path().join("missing.json")), Err(Error::NotFound { .. })));
+    }
+}
@@ -0,0 +1,546 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use crate::contents::{Contents, EntryType};
+use crate::error::Error;
+use crate::fields::Fields;
+use crate::VarDbPkg;
+
+/// The installed package an `Owner` entry belongs to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct OwnerPackage {
+    pub category: String,
+    pub package: String,
+    pub version: String,
+    pub slot: String,
+}
+
+/// A package claiming a path in its CONTENTS.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Owner {
+    /// The package, shared by all entries of its CONTENTS.
+    pub pkg: Arc<OwnerPackage>,
+    /// The path exactly as recorded in CONTENTS.
+    pub path: PathBuf,
+    pub entry_type: EntryType,
+}
+
+impl Owner {
+    /// Returns `category/package`.
+    pub fn cp(&self) -> String {
+        format!("{}/{}", self.pkg.category, self.pkg.package)
+    }
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}-{}", self.pkg.category, self.pkg.package, self.pkg.version)
+    }
+}
+
+/// A path claimed by more than one package.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Collision<'a> {
+    pub path: PathBuf,
+    pub owners: Vec<&'a Owner>,
+}
+
+/// Reverse file-owner lookup over the CONTENTS of all installed packages.
+/// Equivalent to `equery belongs` / `qfile`.
+#[derive(Debug, Default)]
+pub struct OwnerIndex {
+    /// Errors reading CONTENTS files in `build`. The files of these packages are not indexed,
+    /// so a path without owners is only known to be unowned if this is empty.
+    pub errors: Vec<Error>,
+    owners: Vec<Owner>,
+    by_path: HashMap<PathBuf, Vec<usize>>,
+    /// Symlinked directories (link, target), e.g. `/lib` -> `/usr/lib` on merged-usr systems.
+    dir_aliases: Vec<(PathBuf, PathBuf)>,
+}
+
+impl OwnerIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the index from the CONTENTS files of the given packages.
+    /// Packages without a readable CONTENTS file are skipped and reported in `errors`.
+    pub fn build(packages: &[VarDbPkg]) -> Self {
+        let mut index = Self::new();
+        for pkg in packages {
+            match pkg.contents() {
+                Ok(contents) => index.add_package(pkg, &contents),
+                Err(e) => index.errors.push(e),
+            }
+        }
+        index
+    }
+
+    /// Adds all entries of a package's CONTENTS to the index.
+    pub fn add_package(&mut self, pkg: &VarDbPkg, contents: &Contents) {
+        let owner = Arc::new(OwnerPackage {
+            category: pkg.category.clone(),
+            package: pkg.package.clone(),
+            version: pkg.version.clone(),
+            slot: pkg.value(Fields::SLOT).unwrap_or_default().to_string(),
+        });
+        for entry in contents {
+            let idx = self.owners.len();
+            self.owners.push(Owner {
+                pkg: Arc::clone(&owner),
+                path: entry.path().to_path_buf(),
+                entry_type: entry.entry_type(),
+            });
+            let key = self.canonical(entry.path());
+            self.by_path.entry(key).or_default().push(idx);
+        }
+    }
+
+    /// Registers a symlinked directory, so that paths below `link` and below `target`
+    /// are treated as the same file. A relative `target` is resolved against the parent of `link`.
+    pub fn add_dir_alias<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, link: P, target: Q) {
+        let link = normalize(link.as_ref());
+        let target = link.parent().unwrap_or(Path::new("/")).join(target.as_ref());
+        let target = normalize(&target);
+        if link == target || self.dir_aliases.iter().any(|(l, _)| *l == link) {
+            return;
+        }
+        self.dir_aliases.push((link, target));
+        self.rebuild();
+    }
+
+    /// Detects symlinked directories below `root` (usually `/`) for all directories
+    /// in the first two levels of the indexed paths, e.g. `/lib`, `/bin` or `/usr/sbin`.
+    pub fn detect_dir_symlinks<P: AsRef<Path>>(&mut self, root: P) {
+        let root = root.as_ref();
+        let mut candidates = BTreeSet::new();
+        for owner in &self.owners {
+            let mut prefix = PathBuf::from("/");
+            for component in owner.path.components().filter(|c| matches!(c, Component::Normal(_))).take(2) {
+                prefix.push(component);
+                if prefix != owner.path {
+                    candidates.insert(prefix.clone());
+                }
+            }
+        }
+
+        for dir in candidates {
+            let on_disk = root.join(dir.strip_prefix("/").unwrap_or(&dir));
+            let is_symlink = fs::symlink_metadata(&on_disk).is_ok_and(|m| m.file_type().is_symlink());
+            if is_symlink
+                && resolve_in_root(root, &dir, true).is_ok_and(|resolved| resolved.is_dir())
+                && let Ok(target) = fs::read_link(&on_disk)
+            {
+                self.add_dir_alias(&dir, target);
+            }
+        }
+    }
+
+    /// Returns the symlinked directories known to the index.
+    pub fn dir_aliases(&self) -> &[(PathBuf, PathBuf)] {
+        &self.dir_aliases
+    }
+
+    /// Returns all packages owning the given absolute path.
+    pub fn owners<P: AsRef<Path>>(&self, path: P) -> Vec<&Owner> {
+        let key = self.canonical(path.as_ref());
+        self.by_path
+            .get(&key)
+            .map(|ids| ids.iter().map(|&i| &self.owners[i]).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns all entries whose file name equals `name`.
+    pub fn find_basename(&self, name: &str) -> Vec<&Owner> {
+        self.owners
+            .iter()
+            .filter(|o| o.path.file_name().is_some_and(|n| n == name))
+            .collect()
+    }
+
+    /// Returns all entries matching a glob pattern (`*`, `?`, `[...]`).
+    /// A pattern without `/` is matched against the file name only, otherwise against
+    /// the full path; `*` does not cross `/` but `**` does.
+    pub fn find_glob(&self, pattern: &str) -> Vec<&Owner> {
+        let full_path = pattern.contains('/');
+        self.owners
+            .iter()
+            .filter(|o| {
+                if full_path {
+                    glob_match(pattern, &o.path.to_string_lossy())
+                        || glob_match(pattern, &self.canonical(&o.path).to_string_lossy())
+                } else {
+                    o.path.file_name().is_some_and(|n| glob_match(pattern, &n.to_string_lossy()))
+                }
+            })
+            .collect()
+    }
+
+    /// Looks up a query like `qfile` does: absolute paths exactly, patterns
+    /// containing wildcards as glob and everything else as a file name.
+    pub fn find(&self, query: &str) -> Vec<&Owner> {
+        if query.contains(['*', '?', '[']) {
+            self.find_glob(query)
+        } else if query.starts_with('/') {
+            self.owners(query)
+        } else {
+            self.find_basename(query)
+        }
+    }
+
+    /// Returns all non-directory paths claimed by more than one package, sorted by path.
+    pub fn collisions(&self) -> Vec<Collision<'_>> {
+        let mut collisions: Vec<Collision<'_>> = self
+            .by_path
+            .iter()
+            .filter_map(|(path, ids)| {
+                let owners: Vec<&Owner> = ids
+                    .iter()
+                    .map(|&i| &self.owners[i])
+                    .filter(|o| o.entry_type != EntryType::Dir)
+                    .collect();
+                let distinct: BTreeSet<(&str, &str, &str)> = owners
+                    .iter()
+                    .map(|o| (o.pkg.category.as_str(), o.pkg.package.as_str(), o.pkg.version.as_str()))
+                    .collect();
+                (distinct.len() > 1).then(|| Collision { path: path.clone(), owners })
+            })
+            .collect();
+        collisions.sort_by(|a, b| a.path.cmp(&b.path));
+        collisions
+    }
+
+    /// Returns all indexed entries.
+    pub fn entries(&self) -> &[Owner] {
+        &self.owners
+    }
+
+    /// Returns true if any package owns the given path.
+    pub fn contains<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.by_path.contains_key(&self.canonical(path.as_ref()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.owners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.owners.is_empty()
+    }
+
+    /// Normalizes a path and resolves known symlinked directories.
+    pub(crate) fn canonical(&self, path: &Path) -> PathBuf {
+        let mut path = normalize(path);
+        // Bounded, so that alias cycles cannot loop forever.
+        for _ in 0..=self.dir_aliases.len() {
+            let replaced = self.dir_aliases.iter().find_map(|(link, target)| {
+                path.strip_prefix(link).ok().map(|rest| target.join(rest))
+            });
+            match replaced {
+                Some(p) => path = normalize(&p),
+                None => break,
+            }
+        }
+        path
+    }
+
+    fn rebuild(&mut self) {
+        let mut by_path: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+        for (idx, owner) in self.owners.iter().enumerate() {
+            by_path.entry(self.canonical(&owner.path)).or_default().push(idx);
+        }
+        self.by_path = by_path;
+    }
+}
+
+/// Lexically normalizes an absolute path (removes `.`, `..` and duplicate separators).
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => out.push(c),
+            Component::ParentDir => {
+                out.pop();
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    out
+}
+
+/// Matches `text` against a shell-like glob pattern.
+/// Supports `*` (not crossing `/`), `**` (crossing `/`), `?` and `[...]` / `[!...]` classes.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_match_at(&pattern, &text)
+}
+
+fn glob_match_at(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') => {
+            let double = pattern.get(1) == Some(&'*');
+            let rest = if double { &pattern[2..] } else { &pattern[1..] };
+            for i in 0..=text.len() {
+                if glob_match_at(rest, &text[i..]) {
+                    return true;
+                }
+                if i < text.len() && !double && text[i] == '/' {
+                    return false;
+                }
+            }
+            false
+        }
+        Some('?') => !text.is_empty() && text[0] != '/' && glob_match_at(&pattern[1..], &text[1..]),
+        Some('[') => {
+            let Some(close) = pattern.iter().skip(2).position(|&c| c == ']').map(|p| p + 2) else {
+                return text.first() == Some(&'[') && glob_match_at(&pattern[1..], &text[1..]);
+            };
+            let Some(&c) = text.first() else {
+                return false;
+            };
+            let mut class = &pattern[1..close];
+            let negated = matches!(class.first(), Some('!') | Some('^'));
+            if negated {
+                class = &class[1..];
+            }
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == '-' {
+                    matched |= class[i] <= c && c <= class[i + 2];
+                    i += 3;
+                } else {
+                    matched |= class[i] == c;
+                    i += 1;
+                }
+            }
+            matched != negated && glob_match_at(&pattern[close + 1..], &text[1..])
+        }
+        Some(&p) => text.first() == Some(&p) && glob_match_at(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Maximum number of symlinks followed while resolving a path, like Linux' MAXSYMLINKS.
+const MAX_SYMLINKS: usize = 40;
+
+/// Resolves an installed path below `root` the way a chroot into `root` would see it:
+/// symlinks are followed with absolute targets taken relative to `root`, and `..`
+/// never leaves `root`. The last component is only followed if `follow_last` is set.
+/// Components that do not exist are kept as they are.
+pub(crate) fn resolve_in_root(root: &Path, path: &Path, follow_last: bool) -> io::Result<PathBuf> {
+    let mut pending: VecDeque<OsString> = VecDeque::new();
+    let mut resolved: Vec<OsString> = Vec::new();
+    let mut links = 0;
+    push_components(&mut pending, &mut resolved, path);
+
+    while let Some(name) = pending.pop_front() {
+        if name == ".." {
+            resolved.pop();
+            continue;
+        }
+        let candidate = join(root, &resolved).join(&name);
+        let is_link = (follow_last || !pending.is_empty())
+            && fs::symlink_metadata(&candidate).is_ok_and(|m| m.file_type().is_symlink());
+        if !is_link {
+            resolved.push(name);
+            continue;
+        }
+        links += 1;
+        if links > MAX_SYMLINKS {
+            return Err(io::Error::other("too many levels of symbolic links"));
+        }
+        let target = fs::read_link(&candidate)?;
+        let mut rest = std::mem::take(&mut pending);
+        push_components(&mut pending, &mut resolved, &target);
+        pending.append(&mut rest);
+    }
+
+    Ok(join(root, &resolved))
+}
+
+fn join(root: &Path, components: &[OsString]) -> PathBuf {
+    let mut path = root.to_path_buf();
+    path.extend(components);
+    path
+}
+
+/// Queues the components of `path`, restarting at the root for an absolute path.
+fn push_components(pending: &mut VecDeque<OsString>, resolved: &mut Vec<OsString>, path: &Path) {
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => resolved.clear(),
+            Component::CurDir => {}
+            Component::ParentDir => pending.push_back(OsString::from("..")),
+            Component::Normal(name) => pending.push_back(name.to_os_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn pkg(category: &str, package: &str, version: &str) -> VarDbPkg {
+        VarDbPkg {
+            category: category.to_string(),
+            package: package.to_string(),
+            version: version.to_string(),
+            slot: "0".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn sample_index() -> OwnerIndex {
+        let mut index = OwnerIndex::new();
+        index.add_package(
+            &pkg("sys-libs", "glibc", "2.40-r8"),
+            &Contents::parse(
+                "dir /lib64\n\
+                 obj /lib64/libc.so.6 abc 1\n\
+                 sym /lib64/ld-linux-x86-64.so.2 -> ld-2.40.so 1\n\
+                 dir /usr/bin\n\
+                 obj /usr/bin/ldd def 1\n",
+            ),
+        );
+        index.add_package(
+            &pkg("app-shells", "bash", "5.2_p37"),
+            &Contents::parse("dir /usr/bin\nobj /usr/bin/bash 123 1\nobj /usr/share/man/man1/bash.1.bz2 456 1\n"),
+        );
+        index
+    }
+
+    #[test]
+    fn test_exact_lookup() {
+        let index = sample_index();
+        let owners = index.owners("/usr/bin/bash");
+        assert_eq!(owners.len(), 1);
+        assert_eq!(owners[0].to_string(), "app-shells/bash-5.2_p37");
+        assert_eq!(owners[0].entry_type, EntryType::Obj);
+        assert_eq!(index.owners("/lib64/ld-linux-x86-64.so.2")[0].entry_type, EntryType::Sym);
+        assert_eq!(index.owners("/usr/bin").len(), 2);
+        assert_eq!(index.owners("/usr/./bin//bash").len(), 1);
+        assert!(index.owners("/usr/bin/zsh").is_empty());
+    }
+
+    #[test]
+    fn test_basename_and_glob_lookup() {
+        let index = sample_index();
+        assert_eq!(index.find("bash").len(), 1);
+        assert_eq!(index.find("libc.so*").len(), 1);
+        assert_eq!(index.find("/usr/bin/*").len(), 2);
+        assert_eq!(index.find("/usr/**/*.bz2").len(), 1);
+        assert_eq!(index.find("/usr/*.bz2").len(), 0);
+        assert_eq!(index.find("l[a-d]d").len(), 1);
+    }
+
+    #[test]
+    fn test_merged_usr_alias() {
+        let mut index = sample_index();
+        index.add_package(
+            &pkg("sys-apps", "coreutils", "9.5"),
+            &Contents::parse("obj /usr/lib64/libfoo.so.1 abc 1\n"),
+        );
+        assert!(index.owners("/usr/lib64/libc.so.6").is_empty());
+
+        index.add_dir_alias("/lib64", "usr/lib64");
+        assert_eq!(index.owners("/usr/lib64/libc.so.6")[0].pkg.package, "glibc");
+        assert_eq!(index.owners("/lib64/libc.so.6")[0].pkg.package, "glibc");
+        assert_eq!(index.owners("/lib64/libfoo.so.1")[0].pkg.package, "coreutils");
+        // Recorded path stays as written in CONTENTS.
+        assert_eq!(index.owners("/usr/lib64/libc.so.6")[0].path, Path::new("/lib64/libc.so.6"));
+    }
+
+    #[test]
+    fn test_detect_dir_symlinks() {
+        let root = tempdir().unwrap();
+        fs::create_dir_all(root.path().join("usr/lib64")).unwrap();
+        std::os::unix::fs::symlink("usr/lib64", root.path().join("lib64")).unwrap();
+
+        let mut index = sample_index();
+        index.detect_dir_symlinks(root.path());
+        assert_eq!(index.dir_aliases(), &[(PathBuf::from("/lib64"), PathBuf::from("/usr/lib64"))]);
+        assert_eq!(index.owners("/usr/lib64/libc.so.6").len(), 1);
+    }
+
+    #[test]
+    fn test_detect_absolute_dir_symlinks_inside_root() {
+        // /lib64 -> /usr/lib64 must be resolved below root, not on the host
+        let root = tempdir().unwrap();
+        fs::create_dir_all(root.path().join("vardbpkg-only-in-root/lib64")).unwrap();
+        fs::create_dir_all(root.path().join("usr")).unwrap();
+        std::os::unix::fs::symlink("/vardbpkg-only-in-root/lib64", root.path().join("lib64")).unwrap();
+        // Exists on the host but not below root
+        std::os::unix::fs::symlink("/proc", root.path().join("usr/bin")).unwrap();
+
+        let mut index = sample_index();
+        index.detect_dir_symlinks(root.path());
+        assert_eq!(
+            index.dir_aliases(),
+            &[(PathBuf::from("/lib64"), PathBuf::from("/vardbpkg-only-in-root/lib64"))]
+        );
+
+        assert_eq!(
+            resolve_in_root(root.path(), Path::new("/lib64/../lib64/libc.so.6"), true).unwrap(),
+            root.path().join("vardbpkg-only-in-root/lib64/libc.so.6")
+        );
+        assert_eq!(resolve_in_root(root.path(), Path::new("/lib64"), false).unwrap(), root.path().join("lib64"));
+    }
+
+    #[test]
+    fn test_build_shares_package_and_keeps_errors() {
+        let vardb = tempdir().unwrap();
+        let mut packages = Vec::new();
+        for (dir, contents) in [("app-shells/bash-5.2_p37", Some("dir /bin\nobj /bin/bash 123 1\n")), ("sys-libs/glibc-2.40-r8", None)] {
+            let path = vardb.path().join(dir);
+            fs::create_dir_all(&path).unwrap();
+            if let Some(contents) = contents {
+                fs::write(path.join("CONTENTS"), contents).unwrap();
+            }
+            let (category, pf) = dir.split_once('/').unwrap();
+            let (package, version) = pf.split_once('-').unwrap();
+            packages.push(VarDbPkg { path, ..pkg(category, package, version) });
+        }
+
+        let index = OwnerIndex::build(&packages);
+        assert_eq!(index.len(), 2);
+        assert!(Arc::ptr_eq(&index.entries()[0].pkg, &index.entries()[1].pkg));
+        assert_eq!(index.owners("/bin/bash")[0].to_string(), "app-shells/bash-5.2_p37");
+        assert_eq!(index.errors.len(), 1);
+        assert!(matches!(&index.errors[0], Error::NotFound { path } if path.ends_with("glibc-2.40-r8/CONTENTS")));
+    }
+
+    #[test]
+    fn test_collisions() {
+        let mut index = sample_index();
+        index.add_package(
+            &pkg("app-shells", "bash-completion", "2.14"),
+            &Contents::parse("dir /usr/bin\nobj /usr/lib64/libc.so.6 abc 1\n"),
+        );
+        assert!(index.collisions().is_empty());
+
+        index.add_dir_alias("/lib64", "/usr/lib64");
+        let collisions = index.collisions();
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].path, Path::new("/usr/lib64/libc.so.6"));
+        assert_eq!(collisions[0].owners.len(), 2);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.so", "libc.so"));
+        assert!(!glob_match("*.so", "libc.so.6"));
+        assert!(glob_match("lib?.so", "libc.so"));
+        assert!(glob_match("[!a]*", "bash"));
+        assert!(!glob_match("[!b]*", "bash"));
+        assert!(glob_match("/usr/**", "/usr/share/doc/foo"));
+        assert!(!glob_match("/usr/*", "/usr/share/doc"));
+    }
+}
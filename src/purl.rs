@@ -0,0 +1,78 @@
+use crate::VarDbPkg;
+
+/// Repository URLs used in package URLs, by repository name.
+pub const REPOSITORY_URLS: &[(&str, &str)] = &[
+    ("gentoo", "https://gitweb.gentoo.org/repo/gentoo.git"),
+    ("guru", "https://gitweb.gentoo.org/repo/proj/guru.git"),
+];
+
+/// Returns the URL of a repository listed in `REPOSITORY_URLS`.
+pub fn repository_url(repository: &str) -> Option<&'static str> {
+    REPOSITORY_URLS.iter().find(|(name, _)| *name == repository).map(|(_, url)| *url)
+}
+
+/// Returns the package URL of a package, with the repository URL as qualifier if given.
+pub fn purl(pkg: &VarDbPkg, repository_url: Option<&str>) -> String {
+    let mut purl = format!(
+        "pkg:ebuild/{}/{}@{}",
+        percent_encode(&pkg.category),
+        percent_encode(&pkg.package),
+        percent_encode(&pkg.version)
+    );
+    if let Some(url) = repository_url {
+        purl.push_str("?repository_url=");
+        purl.push_str(&percent_encode(url));
+    }
+    purl
+}
+
+/// Percent-encodes everything but unreserved URI characters.
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pkg(category: &str, package: &str, version: &str) -> VarDbPkg {
+        VarDbPkg {
+            category: category.to_string(),
+            package: package.to_string(),
+            version: version.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_percent_encode() {
+        assert_eq!(percent_encode("gtk+"), "gtk%2B");
+        assert_eq!(percent_encode("user@host"), "user%40host");
+        assert_eq!(percent_encode("grüß"), "gr%C3%BC%C3%9F");
+        assert_eq!(percent_encode("a-b.c_d~1"), "a-b.c_d~1");
+    }
+
+    #[test]
+    fn test_purl() {
+        let openssl = pkg("dev-libs", "openssl", "3.0.13-r2");
+        assert_eq!(
+            purl(&openssl, repository_url("gentoo")),
+            "pkg:ebuild/dev-libs/openssl@3.0.13-r2?repository_url=https%3A%2F%2Fgitweb.gentoo.org%2Frepo%2Fgentoo.git"
+        );
+        assert_eq!(repository_url("local"), None);
+        let gtk = pkg("x11-libs", "gtk+", "3.24.43");
+        assert_eq!(purl(&gtk, None), "pkg:ebuild/x11-libs/gtk%2B@3.24.43");
+        assert_eq!(
+            purl(&gtk, Some("https://example.org/overlay.git")),
+            "pkg:ebuild/x11-libs/gtk%2B@3.24.43?repository_url=https%3A%2F%2Fexample.org%2Foverlay.git"
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use crate::depend::{parse_tree, Group, Node, ParseDepError};
+use crate::useflags::is_valid_flag;
+
+/// A node of a REQUIRED_USE expression.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequiredUse {
+    /// The flag must be enabled (`foo`) or disabled (`!foo`).
+    Flag { name: String, negated: bool },
+    /// All children must be satisfied: `( a b )` or the top level.
+    AllOf(Vec<RequiredUse>),
+    /// At least one child must be satisfied: `|| ( a b )`.
+    AnyOf(Vec<RequiredUse>),
+    /// Exactly one child must be satisfied: `^^ ( a b )`.
+    ExactlyOneOf(Vec<RequiredUse>),
+    /// At most one child may be satisfied: `?? ( a b )`.
+    AtMostOneOf(Vec<RequiredUse>),
+    /// Children apply only if the flag is enabled (`flag? ( )`) or disabled (`!flag? ( )`).
+    Conditional {
+        flag: String,
+        negated: bool,
+        children: Vec<RequiredUse>,
+    },
+}
+
+/// A REQUIRED_USE constraint not met by the USE flags of a package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The enclosing conditionals, e.g. `["ssl", "!minimal"]`.
+    pub conditions: Vec<String>,
+    /// The violated constraint.
+    pub constraint: RequiredUse,
+    /// The flags of the constraint that are enabled.
+    pub enabled: Vec<String>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for condition in &self.conditions {
+            write!(f, "{}? ( ", condition)?;
+        }
+        write!(f, "{:#}", self.constraint)?;
+        for _ in &self.conditions {
+            f.write_str(" )")?;
+        }
+        let enabled = if self.enabled.is_empty() {
+            "none".to_string()
+        } else {
+            self.enabled.join(", ")
+        };
+        match &self.constraint {
+            RequiredUse::Flag { name, negated: false } => write!(f, ": {} must be enabled", name),
+            RequiredUse::Flag { name, negated: true } => write!(f, ": {} must be disabled", name),
+            RequiredUse::AnyOf(_) => write!(f, ": at least one flag must be enabled, enabled: {}", enabled),
+            RequiredUse::ExactlyOneOf(_) => write!(f, ": exactly one flag must be enabled, enabled: {}", enabled),
+            RequiredUse::AtMostOneOf(_) => write!(f, ": at most one flag may be enabled, enabled: {}", enabled),
+            _ => write!(f, ": not satisfied, enabled: {}", enabled),
+        }
+    }
+}
+
+impl RequiredUse {
+    /// Parses a REQUIRED_USE expression.
+    /// The result is always an `AllOf` node holding the top-level items.
+    pub fn parse(s: &str) -> Result<Self, ParseDepError> {
+        let children = parse_tree(s, &mut |node| match node {
+            Node::Leaf(token) => {
+                let (name, negated) = match token.strip_prefix('!') {
+                    Some(name) => (name, true),
+                    None => (token, false),
+                };
+                if !is_valid_flag(name) {
+                    return Err(ParseDepError::new(format!("invalid USE flag {:?}", token)));
+                }
+                Ok(RequiredUse::Flag { name: name.to_string(), negated })
+            }
+            Node::Group(Group::All, children) => Ok(RequiredUse::AllOf(children)),
+            Node::Group(Group::Any, children) => Ok(RequiredUse::AnyOf(children)),
+            Node::Group(Group::ExactlyOne, children) => Ok(RequiredUse::ExactlyOneOf(children)),
+            Node::Group(Group::AtMostOne, children) => Ok(RequiredUse::AtMostOneOf(children)),
+            Node::Conditional { flag, negated, children } => Ok(RequiredUse::Conditional {
+                flag: flag.to_string(),
+                negated,
+                children,
+            }),
+        })?;
+        Ok(RequiredUse::AllOf(children))
+    }
+
+    /// Returns true if the constraint holds for the given flag state.
+    /// Empty groups are satisfied, as specified by PMS. Conditionals that do not apply
+    /// are left out of `||`, `^^` and `??` groups, so a group of only those is empty.
+    pub fn is_satisfied<F: Fn(&str) -> bool>(&self, is_enabled: &F) -> bool {
+        let active = |children: &[RequiredUse]| children.iter().filter(|c| c.applies(is_enabled)).count();
+        let count = |children: &[RequiredUse]| {
+            children.iter().filter(|c| c.applies(is_enabled) && c.is_satisfied(is_enabled)).count()
+        };
+        match self {
+            RequiredUse::Flag { name, negated } => is_enabled(name) != *negated,
+            RequiredUse::AllOf(children) => children.iter().all(|c| c.is_satisfied(is_enabled)),
+            RequiredUse::AnyOf(children) => active(children) == 0 || count(children) > 0,
+            RequiredUse::ExactlyOneOf(children) => active(children) == 0 || count(children) == 1,
+            RequiredUse::AtMostOneOf(children) => count(children) <= 1,
+            RequiredUse::Conditional { children, .. } => {
+                !self.applies(is_enabled) || children.iter().all(|c| c.is_satisfied(is_enabled))
+            }
+        }
+    }
+
+    /// Returns false for a conditional whose flag is not in the required state.
+    fn applies<F: Fn(&str) -> bool>(&self, is_enabled: &F) -> bool {
+        match self {
+            RequiredUse::Conditional { flag, negated, .. } => is_enabled(flag) != *negated,
+            _ => true,
+        }
+    }
+
+    /// Returns the violated constraints, innermost first where possible:
+    /// for `AllOf` groups and applying conditionals the failing children are reported.
+    pub fn violations<F: Fn(&str) -> bool>(&self, is_enabled: F) -> Vec<Violation> {
+        let mut out = Vec::new();
+        self.collect_violations(&is_enabled, &mut Vec::new(), &mut out);
+        out
+    }
+
+    fn collect_violations<F: Fn(&str) -> bool>(
+        &self,
+        is_enabled: &F,
+        conditions: &mut Vec<String>,
+        out: &mut Vec<Violation>,
+    ) {
+        match self {
+            RequiredUse::AllOf(children) => {
+                for child in children {
+                    child.collect_violations(is_enabled, conditions, out);
+                }
+            }
+            RequiredUse::Conditional { flag, negated, children } => {
+                if self.applies(is_enabled) {
+                    conditions.push(format!("{}{}", if *negated { "!" } else { "" }, flag));
+                    for child in children {
+                        child.collect_violations(is_enabled, conditions, out);
+                    }
+                    conditions.pop();
+                }
+            }
+            _ if self.is_satisfied(is_enabled) => {}
+            _ => {
+                let mut enabled = Vec::new();
+                self.collect_enabled(is_enabled, &mut enabled);
+                out.push(Violation {
+                    conditions: conditions.clone(),
+                    constraint: self.clone(),
+                    enabled,
+                });
+            }
+        }
+    }
+
+    /// Collects the enabled flags without duplicates, skipping conditionals that do not apply.
+    fn collect_enabled<F: Fn(&str) -> bool>(&self, is_enabled: &F, out: &mut Vec<String>) {
+        if !self.applies(is_enabled) {
+            return;
+        }
+        let mut push = |flag: &str| {
+            if is_enabled(flag) && !out.iter().any(|e| e == flag) {
+                out.push(flag.to_string());
+            }
+        };
+        match self {
+            RequiredUse::Flag { name, .. } => push(name),
+            RequiredUse::AllOf(children)
+            | RequiredUse::AnyOf(children)
+            | RequiredUse::ExactlyOneOf(children)
+            | RequiredUse::AtMostOneOf(children) => {
+                for child in children {
+                    child.collect_enabled(is_enabled, out);
+                }
+            }
+            RequiredUse::Conditional { flag, children, .. } => {
+                push(flag);
+                for child in children {
+                    child.collect_enabled(is_enabled, out);
+                }
+            }
+        }
+    }
+
+    /// Returns all flags mentioned, including those of conditionals, in order of appearance.
+    pub fn flags(&self) -> Vec<&str> {
+        let mut out = Vec::new();
+        self.collect_flags(&mut out);
+        out
+    }
+
+    fn collect_flags<'a>(&'a self, out: &mut Vec<&'a str>) {
+        match self {
+            RequiredUse::Flag { name, .. } => out.push(name),
+            RequiredUse::AllOf(children)
+            | RequiredUse::AnyOf(children)
+            | RequiredUse::ExactlyOneOf(children)
+            | RequiredUse::AtMostOneOf(children) => {
+                for child in children {
+                    child.collect_flags(out);
+                }
+            }
+            RequiredUse::Conditional { flag, children, .. } => {
+                out.push(flag);
+                for child in children {
+                    child.collect_flags(out);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for RequiredUse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn group(f: &mut fmt::Formatter<'_>, prefix: &str, children: &[RequiredUse]) -> fmt::Result {
+            write!(f, "{}( ", prefix)?;
+            for child in children {
+                write!(f, "{:#} ", child)?;
+            }
+            f.write_str(")")
+        }
+
+        match self {
+            RequiredUse::Flag { name, negated } => write!(f, "{}{}", if *negated { "!" } else { "" }, name),
+            RequiredUse::AllOf(children) => {
+                // The root is written without parentheses
+                if f.alternate() {
+                    group(f, "", children)
+                } else {
+                    let items: Vec<String> = children.iter().map(|c| format!("{:#}", c)).collect();
+                    f.write_str(&items.join(" "))
+                }
+            }
+            RequiredUse::AnyOf(children) => group(f, "|| ", children),
+            RequiredUse::ExactlyOneOf(children) => group(f, "^^ ", children),
+            RequiredUse::AtMostOneOf(children) => group(f, "?? ", children),
+            RequiredUse::Conditional { flag, negated, children } => {
+                group(f, &format!("{}{}? ", if *negated { "!" } else { "" }, flag), children)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUIRED_USE: &str = "^^ ( openssl gnutls ) ?? ( X wayland ) \
+        ssl? ( || ( openssl gnutls ) ) !minimal? ( ( nls readline ) ) test? ( !static )";
+
+    #[test]
+    fn test_parse_and_display() {
+        let spec = RequiredUse::parse(REQUIRED_USE).unwrap();
+        let RequiredUse::AllOf(children) = &spec else {
+            panic!("root must be AllOf");
+        };
+        assert_eq!(children.len(), 5);
+        assert!(matches!(&children[0], RequiredUse::ExactlyOneOf(c) if c.len() == 2));
+        assert!(matches!(&children[3], RequiredUse::Conditional { flag, negated: true, .. } if flag == "minimal"));
+        assert_eq!(spec.to_string(), REQUIRED_USE.split_whitespace().collect::<Vec<_>>().join(" "));
+        assert_eq!(spec.flags().len(), 12);
+
+        for s in ["^^ openssl", "( a", "a )", "ssl? a", "!", "a? ( b", "-a", "@a? ( b )"] {
+            assert!(RequiredUse::parse(s).is_err(), "{} should fail", s);
+        }
+        assert_eq!(RequiredUse::parse("").unwrap(), RequiredUse::AllOf(Vec::new()));
+    }
+
+    #[test]
+    fn test_satisfied() {
+        let spec = RequiredUse::parse(REQUIRED_USE).unwrap();
+        let enabled = ["openssl", "ssl", "nls", "readline"];
+        assert!(spec.is_satisfied(&|f: &str| enabled.contains(&f)));
+        assert!(spec.violations(|f| enabled.contains(&f)).is_empty());
+        assert!(RequiredUse::parse("^^ ( ) || ( ) ?? ( )").unwrap().is_satisfied(&|_: &str| false));
+    }
+
+    #[test]
+    fn test_violations() {
+        let spec = RequiredUse::parse(REQUIRED_USE).unwrap();
+        let enabled = ["openssl", "gnutls", "X", "wayland", "nls", "test", "static"];
+        let violations: Vec<String> = spec.violations(|f| enabled.contains(&f)).iter().map(|v| v.to_string()).collect();
+        assert_eq!(
+            violations,
+            [
+                "^^ ( openssl gnutls ): exactly one flag must be enabled, enabled: openssl, gnutls",
+                "?? ( X wayland ): at most one flag may be enabled, enabled: X, wayland",
+                "!minimal? ( readline ): readline must be enabled",
+                "test? ( !static ): static must be disabled",
+            ]
+        );
+
+        let violations = spec.violations(|f| f == "ssl");
+        assert_eq!(violations.len(), 4);
+        assert_eq!(violations[1].conditions, ["ssl"]);
+        assert_eq!(
+            violations[1].to_string(),
+            "ssl? ( || ( openssl gnutls ) ): at least one flag must be enabled, enabled: none"
+        );
+    }
+
+    #[test]
+    fn test_conditionals_in_groups() {
+        let check = |spec: &str, enabled: &[&str]| {
+            let spec = RequiredUse::parse(spec).unwrap();
+            let satisfied = spec.is_satisfied(&|f: &str| enabled.contains(&f));
+            let violations: Vec<String> = spec.violations(|f| enabled.contains(&f)).iter().map(|v| v.to_string()).collect();
+            assert_eq!(satisfied, violations.is_empty());
+            violations
+        };
+        // A conditional that does not apply is not an alternative
+        assert!(check("^^ ( foo? ( a ) b )", &["b"]).is_empty());
+        assert!(check("^^ ( foo? ( a ) b )", &["a", "b"]).is_empty());
+        assert_eq!(
+            check("^^ ( foo? ( a ) b )", &["foo", "a", "b"]),
+            ["^^ ( foo? ( a ) b ): exactly one flag must be enabled, enabled: foo, a, b"]
+        );
+        assert_eq!(
+            check("|| ( foo? ( a ) b )", &[]),
+            ["|| ( foo? ( a ) b ): at least one flag must be enabled, enabled: none"]
+        );
+        assert_eq!(
+            check("|| ( !foo? ( a ) b )", &["foo", "a"]),
+            ["|| ( !foo? ( a ) b ): at least one flag must be enabled, enabled: none"]
+        );
+        assert!(check("|| ( foo? ( a ) b )", &["foo", "a"]).is_empty());
+        assert!(check("?? ( foo? ( a ) b )", &["a", "b"]).is_empty());
+        assert_eq!(check("?? ( foo? ( a ) b )", &["foo", "a", "b"]).len(), 1);
+        // Groups of only inapplicable conditionals are empty
+        assert!(check("|| ( foo? ( a ) ) ^^ ( !bar? ( b ) )", &["bar"]).is_empty());
+    }
+}
@@ -0,0 +1,567 @@
+use serde_json::{json, Map, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::contents::ContentsEntry;
+use crate::cpe::CpeMap;
+use crate::depend::DepSpec;
+use crate::fields::Fields;
+use crate::license::LicenseSpec;
+use crate::VarDbPkg;
+
+/// Gentoo license names that differ from or match an SPDX license identifier.
+/// Other licenses are written as `LicenseRef-<name>`.
+pub const SPDX_LICENSE_IDS: &[(&str, &str)] = &[
+    ("AGPL-3", "AGPL-3.0-only"),
+    ("AGPL-3+", "AGPL-3.0-or-later"),
+    ("Apache-1.1", "Apache-1.1"),
+    ("Apache-2.0", "Apache-2.0"),
+    ("Artistic", "Artistic-1.0-Perl"),
+    ("Artistic-2", "Artistic-2.0"),
+    ("BSD", "BSD-3-Clause"),
+    ("BSD-2", "BSD-2-Clause"),
+    ("BSD-4", "BSD-4-Clause"),
+    ("Boost-1.0", "BSL-1.0"),
+    ("CC-BY-4.0", "CC-BY-4.0"),
+    ("CC-BY-SA-4.0", "CC-BY-SA-4.0"),
+    ("CC0-1.0", "CC0-1.0"),
+    ("FDL-1.3", "GFDL-1.3-only"),
+    ("FDL-1.3+", "GFDL-1.3-or-later"),
+    ("GPL-1", "GPL-1.0-only"),
+    ("GPL-1+", "GPL-1.0-or-later"),
+    ("GPL-2", "GPL-2.0-only"),
+    ("GPL-2+", "GPL-2.0-or-later"),
+    ("GPL-3", "GPL-3.0-only"),
+    ("GPL-3+", "GPL-3.0-or-later"),
+    ("HPND", "HPND"),
+    ("ISC", "ISC"),
+    ("LGPL-2", "LGPL-2.0-only"),
+    ("LGPL-2+", "LGPL-2.0-or-later"),
+    ("LGPL-2.1", "LGPL-2.1-only"),
+    ("LGPL-2.1+", "LGPL-2.1-or-later"),
+    ("LGPL-3", "LGPL-3.0-only"),
+    ("LGPL-3+", "LGPL-3.0-or-later"),
+    ("MIT", "MIT"),
+    ("MPL-2.0", "MPL-2.0"),
+    ("OpenSSL", "OpenSSL"),
+    ("PSF-2", "PSF-2.0"),
+    ("UoI-NCSA", "NCSA"),
+    ("Unlicense", "Unlicense"),
+    ("Vim", "Vim"),
+    ("WTFPL-2", "WTFPL"),
+    ("ZLIB", "Zlib"),
+    ("curl", "curl"),
+];
+
+/// Returns the SPDX identifier of a Gentoo license name, `LicenseRef-<name>` if it has none.
+pub fn spdx_license_id(license: &str) -> String {
+    match SPDX_LICENSE_IDS.iter().find(|(gentoo, _)| *gentoo == license) {
+        Some((_, spdx)) => spdx.to_string(),
+        None => format!("LicenseRef-{}", spdx_idstring(license)),
+    }
+}
+
+/// Converts a USE-reduced LICENSE expression to an SPDX license expression,
+/// `None` if it is empty. `|| ( )` groups become `OR`, everything else `AND`.
+pub fn spdx_license_expression(spec: &LicenseSpec) -> Option<String> {
+    spdx_license_expression_with(spec, &mut |license| spdx_license_id(license))
+}
+
+/// Like `spdx_license_expression`, with license identifiers chosen by `license_id`.
+fn spdx_license_expression_with(spec: &LicenseSpec, license_id: &mut dyn FnMut(&str) -> String) -> Option<String> {
+    // Returns the expression and whether it is compound, i.e. needs parentheses when nested
+    fn write(spec: &LicenseSpec, license_id: &mut dyn FnMut(&str) -> String) -> Option<(String, bool)> {
+        let (children, op) = match spec {
+            DepSpec::Leaf(license) => return Some((license_id(license), false)),
+            DepSpec::AnyOf(children) => (children, " OR "),
+            DepSpec::AllOf(children) | DepSpec::UseConditional { children, .. } => (children, " AND "),
+        };
+        let mut items: Vec<(String, bool)> = children.iter().filter_map(|child| write(child, license_id)).collect();
+        if items.len() <= 1 {
+            return items.pop();
+        }
+        let items: Vec<String> = items
+            .into_iter()
+            .map(|(item, compound)| if compound { format!("({})", item) } else { item })
+            .collect();
+        Some((items.join(op), true))
+    }
+    write(spec, license_id).map(|(expression, _)| expression)
+}
+
+/// The `LicenseRef-` identifiers of an SPDX document with the Gentoo license names they stand for.
+/// Names that sanitize to the same identifier get a numeric suffix, e.g. `LicenseRef-foo-2`.
+#[derive(Debug, Default)]
+struct LicenseRefs {
+    refs: Vec<(String, String)>,
+}
+
+impl LicenseRefs {
+    /// Returns the SPDX identifier of a Gentoo license name, registering a `LicenseRef-` if needed.
+    fn id(&mut self, license: &str) -> String {
+        let id = spdx_license_id(license);
+        if !id.starts_with("LicenseRef-") {
+            return id;
+        }
+        if let Some((id, _)) = self.refs.iter().find(|(_, name)| name == license) {
+            return id.clone();
+        }
+        let mut unique = id.clone();
+        let mut n = 1;
+        while self.refs.iter().any(|(other, _)| *other == unique) {
+            n += 1;
+            unique = format!("{}-{}", id, n);
+        }
+        self.refs.push((unique.clone(), license.to_string()));
+        unique
+    }
+}
+
+/// Exports installed packages as Software Bill of Materials, in SPDX 2.3 or CycloneDX 1.5 JSON.
+///
+/// ```no_run
+/// use vardbpkg::sbom::Sbom;
+///
+/// let packages = vardbpkg::parse_vardb("/var/db/pkg");
+/// let spdx = Sbom::new(&packages).with_name("host.example.org").to_spdx();
+/// println!("{}", serde_json::to_string_pretty(&spdx).unwrap());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sbom<'a> {
+    packages: &'a [VarDbPkg],
+    name: String,
+    namespace: Option<String>,
+    serial_number: Option<String>,
+    created: SystemTime,
+    files: bool,
+    cpe_map: CpeMap,
+}
+
+impl<'a> Sbom<'a> {
+    pub fn new(packages: &'a [VarDbPkg]) -> Self {
+        Sbom {
+            packages,
+            name: "vardb".to_string(),
+            namespace: None,
+            serial_number: None,
+            created: SystemTime::now(),
+            files: false,
+            cpe_map: CpeMap::new(),
+        }
+    }
+
+    /// Sets the document name, e.g. the host name.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Sets the unique SPDX document namespace. Defaults to a URI made of the name and creation time.
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.to_string());
+        self
+    }
+
+    /// Sets the CycloneDX serial number, a `urn:uuid:` URN. It is omitted by default.
+    pub fn with_serial_number(mut self, serial_number: &str) -> Self {
+        self.serial_number = Some(serial_number.to_string());
+        self
+    }
+
+    /// Sets the mapping used for the CPE names of the packages, `CpeMap::new()` by default.
+    pub fn with_cpe_map(mut self, cpe_map: CpeMap) -> Self {
+        self.cpe_map = cpe_map;
+        self
+    }
+
+    /// Sets the creation time, the current time by default.
+    pub fn with_created(mut self, created: SystemTime) -> Self {
+        self.created = created;
+        self
+    }
+
+    /// Adds the files of each package from CONTENTS, with their MD5 checksums.
+    /// Packages whose CONTENTS cannot be read are exported without files.
+    pub fn with_files(mut self) -> Self {
+        self.files = true;
+        self
+    }
+
+    /// Returns an SPDX 2.3 JSON document.
+    ///
+    /// CONTENTS records only MD5 checksums, so files lack the SHA1 checksum
+    /// SPDX 2.3 asks for; strict validators may report this.
+    pub fn to_spdx(&self) -> Value {
+        let created = format_timestamp(self.created);
+        let namespace = self.namespace.clone().unwrap_or_else(|| {
+            format!("https://spdx.org/spdxdocs/{}-{}", spdx_idstring(&self.name), created)
+        });
+        let mut packages = Vec::new();
+        let mut files = Vec::new();
+        let mut relationships = Vec::new();
+        let mut license_refs = LicenseRefs::default();
+
+        for (idx, pkg) in self.packages.iter().enumerate() {
+            // The index keeps ids unique since spdx_idstring maps e.g. foo_bar and foo-bar alike
+            let id = format!("SPDXRef-Package-{}-{}", idx, spdx_idstring(&cpv(pkg)));
+            let license = pkg
+                .effective_licenses()
+                .ok()
+                .and_then(|spec| spdx_license_expression_with(&spec, &mut |name| license_refs.id(name)));
+
+            let mut package = json!({
+                "SPDXID": id,
+                "name": pkg.package,
+                "versionInfo": pkg.version,
+                "downloadLocation": "NOASSERTION",
+                "licenseConcluded": "NOASSERTION",
+                "licenseDeclared": license.as_deref().unwrap_or("NOASSERTION"),
+                "copyrightText": "NOASSERTION",
+                "filesAnalyzed": false,
+                "externalRefs": [{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": pkg.purl(),
+                }],
+            });
+            let fields = package.as_object_mut().unwrap();
+            if let Some(cpe) = self.cpe_map.cpe(pkg) {
+                let refs = fields["externalRefs"].as_array_mut().unwrap();
+                refs.push(json!({
+                    "referenceCategory": "SECURITY",
+                    "referenceType": "cpe23Type",
+                    "referenceLocator": cpe,
+                }));
+            }
+            if let Some(homepage) = field(pkg, Fields::HOMEPAGE).split_whitespace().next() {
+                fields.insert("homepage".to_string(), json!(homepage));
+            }
+            let description = field(pkg, Fields::DESCRIPTION);
+            if !description.is_empty() {
+                fields.insert("summary".to_string(), json!(description));
+            }
+            let repository = field(pkg, Fields::REPOSITORY);
+            if !repository.is_empty() {
+                fields.insert("sourceInfo".to_string(), json!(format!("built from the {} repository", repository)));
+            }
+            packages.push(package);
+            relationships.push(json!({
+                "spdxElementId": "SPDXRef-DOCUMENT",
+                "relationshipType": "DESCRIBES",
+                "relatedSpdxElement": id,
+            }));
+
+            for (file_idx, (path, md5)) in self.package_files(pkg).into_iter().enumerate() {
+                let file_id = format!("SPDXRef-File-{}-{}", idx, file_idx);
+                files.push(json!({
+                    "SPDXID": file_id,
+                    "fileName": path,
+                    "checksums": [{"algorithm": "MD5", "checksumValue": md5}],
+                }));
+                relationships.push(json!({
+                    "spdxElementId": id,
+                    "relationshipType": "CONTAINS",
+                    "relatedSpdxElement": file_id,
+                }));
+            }
+        }
+
+        let mut document = json!({
+            "spdxVersion": "SPDX-2.3",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": self.name,
+            "documentNamespace": namespace,
+            "creationInfo": {
+                "created": created,
+                "creators": [format!("Tool: vardbpkg-{}", env!("CARGO_PKG_VERSION"))],
+            },
+            "packages": packages,
+            "relationships": relationships,
+        });
+        let fields = document.as_object_mut().unwrap();
+        if !files.is_empty() {
+            fields.insert("files".to_string(), Value::Array(files));
+        }
+        if !license_refs.refs.is_empty() {
+            let infos: Vec<Value> = license_refs
+                .refs
+                .iter()
+                .map(|(id, name)| {
+                    json!({
+                        "licenseId": id,
+                        "name": name,
+                        "extractedText": format!("The {} license of the Gentoo repository", name),
+                    })
+                })
+                .collect();
+            fields.insert("hasExtractedLicensingInfos".to_string(), Value::Array(infos));
+        }
+        document
+    }
+
+    /// Returns a CycloneDX 1.5 JSON BOM.
+    pub fn to_cyclonedx(&self) -> Value {
+        let components: Vec<Value> = self
+            .packages
+            .iter()
+            .map(|pkg| {
+                let purl = pkg.purl();
+                let mut component = Map::new();
+                component.insert("type".to_string(), json!("library"));
+                component.insert("bom-ref".to_string(), json!(purl));
+                component.insert("group".to_string(), json!(pkg.category));
+                component.insert("name".to_string(), json!(pkg.package));
+                component.insert("version".to_string(), json!(pkg.version));
+                let description = field(pkg, Fields::DESCRIPTION);
+                if !description.is_empty() {
+                    component.insert("description".to_string(), json!(description));
+                }
+                if let Some(license) = license_expression(pkg) {
+                    component.insert("licenses".to_string(), json!([{"expression": license}]));
+                }
+                if let Some(cpe) = self.cpe_map.cpe(pkg) {
+                    component.insert("cpe".to_string(), json!(cpe));
+                }
+                component.insert("purl".to_string(), json!(purl));
+                let references: Vec<Value> = field(pkg, Fields::HOMEPAGE)
+                    .split_whitespace()
+                    .map(|url| json!({"type": "website", "url": url}))
+                    .collect();
+                if !references.is_empty() {
+                    component.insert("externalReferences".to_string(), Value::Array(references));
+                }
+                let properties: Vec<Value> = [
+                    ("gentoo:repository", field(pkg, Fields::REPOSITORY)),
+                    ("gentoo:slot", field(pkg, Fields::SLOT)),
+                ]
+                .into_iter()
+                .filter(|(_, value)| !value.is_empty())
+                .map(|(name, value)| json!({"name": name, "value": value}))
+                .collect();
+                if !properties.is_empty() {
+                    component.insert("properties".to_string(), Value::Array(properties));
+                }
+                let files: Vec<Value> = self
+                    .package_files(pkg)
+                    .into_iter()
+                    .map(|(path, md5)| json!({"type": "file", "name": path, "hashes": [{"alg": "MD5", "content": md5}]}))
+                    .collect();
+                if !files.is_empty() {
+                    component.insert("components".to_string(), Value::Array(files));
+                }
+                Value::Object(component)
+            })
+            .collect();
+
+        let mut bom = json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "version": 1,
+            "metadata": {
+                "timestamp": format_timestamp(self.created),
+                "tools": {
+                    "components": [{
+                        "type": "application",
+                        "name": "vardbpkg",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    }],
+                },
+                "component": {"type": "operating-system", "name": self.name},
+            },
+            "components": components,
+        });
+        if let Some(serial_number) = &self.serial_number {
+            bom.as_object_mut().unwrap().insert("serialNumber".to_string(), json!(serial_number));
+        }
+        bom
+    }
+
+    /// Returns the regular files of a package with their MD5 checksums if files are exported.
+    fn package_files(&self, pkg: &VarDbPkg) -> Vec<(String, String)> {
+        if !self.files {
+            return Vec::new();
+        }
+        let Ok(contents) = pkg.contents() else {
+            return Vec::new();
+        };
+        contents
+            .iter()
+            .filter_map(|entry| match entry {
+                ContentsEntry::Obj { path, md5, .. } => Some((path.to_string_lossy().into_owned(), md5.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn cpv(pkg: &VarDbPkg) -> String {
+    format!("{}/{}-{}", pkg.category, pkg.package, pkg.version)
+}
+
+/// Returns a field, read on demand. The SBOM is best-effort, unreadable fields are left out.
+fn field(pkg: &VarDbPkg, field: Fields) -> &str {
+    pkg.value(field).unwrap_or_default()
+}
+
+fn license_expression(pkg: &VarDbPkg) -> Option<String> {
+    pkg.effective_licenses().ok().as_ref().and_then(spdx_license_expression)
+}
+
+/// Replaces characters not allowed in SPDX identifiers, which may contain only letters, digits, `.` and `-`.
+fn spdx_idstring(s: &str) -> String {
+    s.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '-' }).collect()
+}
+
+/// Formats a time as UTC timestamp, e.g. `2024-05-01T12:00:00Z`.
+fn format_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::license::parse_license;
+    use std::path::Path;
+    use std::time::Duration;
+
+    fn testdata() -> Vec<VarDbPkg> {
+        crate::parse_vardb(Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/vardb"))
+    }
+
+    #[test]
+    fn test_spdx_license_expression() {
+        let expr = |s: &str| spdx_license_expression(&parse_license(s).unwrap().evaluate(|f| f == "doc"));
+        assert_eq!(expr("GPL-2+ || ( MIT Apache-2.0 )").unwrap(), "GPL-2.0-or-later AND (MIT OR Apache-2.0)");
+        assert_eq!(expr("|| ( MIT ( BSD ZLIB ) )").unwrap(), "MIT OR (BSD-3-Clause AND Zlib)");
+        assert_eq!(expr("inner-net doc? ( FDL-1.3 )").unwrap(), "LicenseRef-inner-net AND GFDL-1.3-only");
+        assert_eq!(expr("test? ( MIT )"), None);
+        assert_eq!(expr("|| ( doc? ( MIT ZLIB ) BSD )").unwrap(), "(MIT AND Zlib) OR BSD-3-Clause");
+        assert_eq!(spdx_license_id("free_noncomm+"), "LicenseRef-free-noncomm-");
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(UNIX_EPOCH + Duration::from_secs(1709251199)), "2024-02-29T23:59:59Z");
+    }
+
+    #[test]
+    fn test_spdx() {
+        let packages = testdata();
+        let created = UNIX_EPOCH + Duration::from_secs(1714564800);
+        let doc = Sbom::new(&packages).with_name("host").with_created(created).with_files().to_spdx();
+        assert_eq!(doc["spdxVersion"], "SPDX-2.3");
+        assert_eq!(doc["creationInfo"]["created"], "2024-05-01T12:00:00Z");
+        assert_eq!(doc["documentNamespace"], "https://spdx.org/spdxdocs/host-2024-05-01T12:00:00Z");
+        let bash = &doc["packages"][0];
+        assert_eq!(bash["SPDXID"], "SPDXRef-Package-0-app-shells-bash-5.2-p37");
+        assert_eq!(bash["licenseDeclared"], "GPL-3.0-or-later");
+        assert_eq!(
+            bash["externalRefs"][0]["referenceLocator"],
+            "pkg:ebuild/app-shells/bash@5.2_p37?repository_url=https%3A%2F%2Fgitweb.gentoo.org%2Frepo%2Fgentoo.git"
+        );
+        assert_eq!(bash["externalRefs"][1]["referenceLocator"], "cpe:2.3:a:gnu:bash:5.2_p37:*:*:*:*:*:*:*");
+        assert_eq!(
+            doc["packages"][1]["licenseDeclared"],
+            "LGPL-2.1-or-later AND BSD-3-Clause AND HPND AND ISC AND LicenseRef-inner-net AND LicenseRef-rc AND LicenseRef-PCRE"
+        );
+        let refs = doc["hasExtractedLicensingInfos"].as_array().unwrap();
+        assert_eq!(refs.len(), 3);
+
+        let files = doc["files"].as_array().unwrap();
+        assert!(files.iter().any(|f| f["fileName"] == "/bin/bash"));
+        let contains = doc["relationships"].as_array().unwrap().iter().filter(|r| r["relationshipType"] == "CONTAINS");
+        assert_eq!(contains.count(), files.len());
+
+        let doc = Sbom::new(&packages).with_namespace("urn:test").to_spdx();
+        assert_eq!(doc["documentNamespace"], "urn:test");
+        assert!(doc.get("files").is_none());
+    }
+
+    #[test]
+    fn test_spdx_unique_package_ids() {
+        let pkg = |category: &str, package: &str| VarDbPkg {
+            category: category.to_string(),
+            package: package.to_string(),
+            version: "1".to_string(),
+            ..Default::default()
+        };
+        let packages = [pkg("dev-python", "foo_bar"), pkg("dev-python", "foo-bar"), pkg("x11-libs", "gtk+"), pkg("x11-libs", "gtk-")];
+        let doc = Sbom::new(&packages).to_spdx();
+        let ids: Vec<&str> = doc["packages"].as_array().unwrap().iter().map(|p| p["SPDXID"].as_str().unwrap()).collect();
+        assert_eq!(
+            ids,
+            [
+                "SPDXRef-Package-0-dev-python-foo-bar-1",
+                "SPDXRef-Package-1-dev-python-foo-bar-1",
+                "SPDXRef-Package-2-x11-libs-gtk--1",
+                "SPDXRef-Package-3-x11-libs-gtk--1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spdx_license_refs() {
+        let pkg = |package: &str, license: &str| VarDbPkg {
+            category: "app-misc".to_string(),
+            package: package.to_string(),
+            version: "1".to_string(),
+            license: license.to_string(),
+            ..Default::default()
+        };
+        let packages = [pkg("a", "free_noncomm+ MIT"), pkg("b", "free-noncomm+"), pkg("c", "free_noncomm+")];
+        let doc = Sbom::new(&packages).to_spdx();
+        let declared: Vec<&str> =
+            doc["packages"].as_array().unwrap().iter().map(|p| p["licenseDeclared"].as_str().unwrap()).collect();
+        assert_eq!(declared, ["LicenseRef-free-noncomm- AND MIT", "LicenseRef-free-noncomm--2", "LicenseRef-free-noncomm-"]);
+        let refs: Vec<(&str, &str)> = doc["hasExtractedLicensingInfos"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| (r["licenseId"].as_str().unwrap(), r["name"].as_str().unwrap()))
+            .collect();
+        assert_eq!(refs, [("LicenseRef-free-noncomm-", "free_noncomm+"), ("LicenseRef-free-noncomm--2", "free-noncomm+")]);
+    }
+
+    #[test]
+    fn test_cyclonedx() {
+        let packages = testdata();
+        let bom = Sbom::new(&packages).with_serial_number("urn:uuid:3e671687-395b-41f5-a30f-a58921a69b79").to_cyclonedx();
+        assert_eq!(bom["bomFormat"], "CycloneDX");
+        assert_eq!(bom["serialNumber"], "urn:uuid:3e671687-395b-41f5-a30f-a58921a69b79");
+        let components = bom["components"].as_array().unwrap();
+        assert_eq!(components.len(), 3);
+        let readline = &components[2];
+        assert_eq!(readline["group"], "sys-libs");
+        assert!(readline["purl"].as_str().unwrap().starts_with("pkg:ebuild/sys-libs/readline@8.2_p13?"));
+        assert_eq!(readline["cpe"], "cpe:2.3:a:gnu:readline:8.2_p13:*:*:*:*:*:*:*");
+        assert_eq!(readline["licenses"][0]["expression"], "GPL-3.0-or-later");
+        assert!(readline["properties"].as_array().unwrap().iter().any(|p| p["value"] == "0/8"));
+        assert!(readline.get("components").is_none());
+
+        let bom = Sbom::new(&packages).with_files().with_cpe_map(CpeMap::new().with_overrides("sys-libs/readline -")).to_cyclonedx();
+        assert!(bom["components"][2].get("cpe").is_none());
+        let files = bom["components"][2]["components"].as_array().unwrap();
+        assert_eq!(files.len(), 4);
+        assert_eq!(files[0]["hashes"][0]["alg"], "MD5");
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+/// USE_EXPAND and USE_EXPAND_HIDDEN variables of the Gentoo base and arch profiles, lowercased
+/// as they appear in flag names. Used to tell `python_targets_python3_12` apart from an ordinary
+/// flag; use `UseFlags::with_use_expand` for variables added by other profiles.
+pub const DEFAULT_USE_EXPAND: &[&str] = &[
+    "abi_mips", "abi_ppc", "abi_riscv", "abi_s390", "abi_x86", "ada_target", "alsa_cards", "amdgpu_targets",
+    "apache2_modules", "apache2_mpms", "calligra_features", "cameras", "collectd_plugins", "cpu_flags_arm",
+    "cpu_flags_ppc", "cpu_flags_x86", "curl_ssl", "elibc", "fftools", "gpsd_protocols", "grub_platforms",
+    "guile_single_target", "guile_targets", "input_devices", "kernel", "l10n", "lcd_devices",
+    "libreoffice_extensions", "llvm_slot", "llvm_targets", "lua_single_target", "lua_targets",
+    "monkeyd_plugins", "nginx_modules_http", "nginx_modules_mail", "nginx_modules_stream",
+    "office_implementation", "openmpi_fabrics", "openmpi_ofed_features", "openmpi_rm", "php_targets",
+    "postgres_targets", "python_single_target", "python_targets", "qemu_softmmu_targets", "qemu_user_targets",
+    "ros_messages", "ruby_targets", "sane_backends", "userland", "uwsgi_plugins", "video_cards",
+    "voicemail_storage", "xtables_addons",
+];
+
+/// Returns true if `flag` is a valid USE flag name as specified by PMS.
+pub(crate) fn is_valid_flag(flag: &str) -> bool {
+    flag.chars().next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && flag.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '_' | '@' | '-'))
+}
+
+/// The state of a flag for an installed package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagState {
+    Enabled,
+    Disabled,
+    /// The package does not know the flag.
+    NotInIuse,
+}
+
+/// The USE flags of an installed package, combined from IUSE, IUSE_EFFECTIVE and USE.
+///
+/// USE as recorded in the vardb also contains profile flags the package does not use,
+/// so only flags in IUSE_EFFECTIVE are considered enabled. Packages installed by old
+/// Portage versions have no IUSE_EFFECTIVE; for them USE is taken as is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UseFlags {
+    /// IUSE flags with their default, `+foo` is `Some(true)` and `-foo` is `Some(false)`.
+    iuse: BTreeMap<String, Option<bool>>,
+    /// IUSE_EFFECTIVE, or IUSE and USE if it is missing.
+    effective: BTreeSet<String>,
+    enabled: BTreeSet<String>,
+    use_expand: Vec<String>,
+}
+
+impl UseFlags {
+    /// Combines the content of IUSE, IUSE_EFFECTIVE and USE.
+    pub fn new(iuse: &str, iuse_effective: &str, usepkg: &str) -> Self {
+        let iuse: BTreeMap<String, Option<bool>> = iuse
+            .split_whitespace()
+            .map(|flag| match flag.as_bytes()[0] {
+                b'+' => (flag[1..].to_string(), Some(true)),
+                b'-' => (flag[1..].to_string(), Some(false)),
+                _ => (flag.to_string(), None),
+            })
+            .collect();
+        let usepkg: BTreeSet<String> = usepkg.split_whitespace().map(str::to_string).collect();
+
+        let (effective, enabled) = if iuse_effective.split_whitespace().next().is_some() {
+            let effective: BTreeSet<String> = iuse_effective.split_whitespace().map(str::to_string).collect();
+            let enabled = usepkg.intersection(&effective).cloned().collect();
+            (effective, enabled)
+        } else {
+            (iuse.keys().cloned().chain(usepkg.iter().cloned()).collect(), usepkg)
+        };
+
+        UseFlags {
+            iuse,
+            effective,
+            enabled,
+            use_expand: DEFAULT_USE_EXPAND.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// Replaces the known USE_EXPAND variables, e.g. with the USE_EXPAND and
+    /// USE_EXPAND_HIDDEN of the system's profile. Names are matched case-insensitively.
+    pub fn with_use_expand<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.use_expand = names.into_iter().map(|n| n.as_ref().to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Returns whether a flag is enabled, disabled or unknown to the package.
+    pub fn state(&self, flag: &str) -> FlagState {
+        if self.enabled.contains(flag) {
+            FlagState::Enabled
+        } else if self.effective.contains(flag) {
+            FlagState::Disabled
+        } else {
+            FlagState::NotInIuse
+        }
+    }
+
+    pub fn is_enabled(&self, flag: &str) -> bool {
+        self.enabled.contains(flag)
+    }
+
+    /// Returns the IUSE default of a flag, `None` if it has none or is not in IUSE.
+    pub fn default(&self, flag: &str) -> Option<bool> {
+        self.iuse.get(flag).copied().flatten()
+    }
+
+    /// Returns the flags of IUSE, without defaults, sorted.
+    pub fn iuse(&self) -> impl Iterator<Item = &str> {
+        self.iuse.keys().map(String::as_str)
+    }
+
+    /// Returns the enabled flags, sorted.
+    pub fn enabled(&self) -> impl Iterator<Item = &str> {
+        self.enabled.iter().map(String::as_str)
+    }
+
+    /// Returns the IUSE flags that are disabled, sorted.
+    pub fn disabled(&self) -> impl Iterator<Item = &str> {
+        self.iuse().filter(|flag| !self.enabled.contains(*flag))
+    }
+
+    /// Splits a USE_EXPAND flag into its variable and value, e.g.
+    /// `nginx_modules_http_gzip` into `("nginx_modules_http", "gzip")`.
+    /// The longest matching variable wins. Returns `None` for ordinary flags.
+    pub fn split_use_expand<'f>(&self, flag: &'f str) -> Option<(&'f str, &'f str)> {
+        self.use_expand
+            .iter()
+            .filter(|name| {
+                flag.len() > name.len() + 1
+                    && flag.as_bytes()[name.len()] == b'_'
+                    && flag[..name.len()].eq_ignore_ascii_case(name)
+            })
+            .max_by_key(|name| name.len())
+            .map(|name| (&flag[..name.len()], &flag[name.len() + 1..]))
+    }
+
+    pub fn is_use_expand(&self, flag: &str) -> bool {
+        self.split_use_expand(flag).is_some()
+    }
+
+    /// Returns the enabled flags that are not USE_EXPAND flags, sorted.
+    pub fn enabled_plain(&self) -> impl Iterator<Item = &str> {
+        self.enabled().filter(|flag| !self.is_use_expand(flag))
+    }
+
+    /// Groups the enabled USE_EXPAND flags by variable, e.g.
+    /// `python_targets` -> `["python3_12", "python3_13"]`.
+    pub fn enabled_use_expand(&self) -> BTreeMap<&str, Vec<&str>> {
+        let mut groups: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for (name, value) in self.enabled().filter_map(|flag| self.split_use_expand(flag)) {
+            groups.entry(name).or_default().push(value);
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nginx() -> UseFlags {
+        UseFlags::new(
+            "+http +pcre ssl -debug nginx_modules_http_gzip nginx_modules_http_v2 nginx_modules_stream_ssl",
+            "abi_x86_64 amd64 debug elibc_glibc http kernel_linux nginx_modules_http_gzip nginx_modules_http_v2 \
+             nginx_modules_stream_ssl pcre ssl",
+            "abi_x86_64 amd64 elibc_glibc http kernel_linux nginx_modules_http_gzip nginx_modules_stream_ssl \
+             pcre python_targets_python3_12 ssl userland_GNU",
+        )
+    }
+
+    #[test]
+    fn test_state() {
+        let flags = nginx();
+        assert_eq!(flags.state("ssl"), FlagState::Enabled);
+        assert_eq!(flags.state("debug"), FlagState::Disabled);
+        assert_eq!(flags.state("amd64"), FlagState::Enabled);
+        // In USE, but the package does not use it
+        assert_eq!(flags.state("python_targets_python3_12"), FlagState::NotInIuse);
+        assert_eq!(flags.state("gtk"), FlagState::NotInIuse);
+        assert_eq!(flags.default("http"), Some(true));
+        assert_eq!(flags.default("debug"), Some(false));
+        assert_eq!(flags.default("ssl"), None);
+        assert_eq!(flags.disabled().collect::<Vec<_>>(), ["debug", "nginx_modules_http_v2"]);
+    }
+
+    #[test]
+    fn test_use_expand() {
+        let flags = nginx();
+        assert_eq!(flags.split_use_expand("nginx_modules_http_gzip"), Some(("nginx_modules_http", "gzip")));
+        assert_eq!(flags.split_use_expand("python_targets_python3_12"), Some(("python_targets", "python3_12")));
+        assert_eq!(flags.split_use_expand("userland_GNU"), Some(("userland", "GNU")));
+        assert_eq!(flags.split_use_expand("ssl"), None);
+        assert_eq!(flags.split_use_expand("kernel_"), None);
+        assert_eq!(flags.enabled_plain().collect::<Vec<_>>(), ["amd64", "http", "pcre", "ssl"]);
+        let groups = flags.enabled_use_expand();
+        assert_eq!(groups["abi_x86"], ["64"]);
+        assert_eq!(groups["nginx_modules_http"], ["gzip"]);
+        assert_eq!(groups["nginx_modules_stream"], ["ssl"]);
+
+        let flags = nginx().with_use_expand(["NGINX_MODULES_HTTP"]);
+        assert!(flags.is_use_expand("nginx_modules_http_v2"));
+        assert!(!flags.is_use_expand("elibc_glibc"));
+    }
+
+    #[test]
+    fn test_without_iuse_effective() {
+        let flags = UseFlags::new("+nls readline", "", "amd64 elibc_glibc nls");
+        assert_eq!(flags.state("nls"), FlagState::Enabled);
+        assert_eq!(flags.state("elibc_glibc"), FlagState::Enabled);
+        assert_eq!(flags.state("readline"), FlagState::Disabled);
+        assert_eq!(flags.state("ssl"), FlagState::NotInIuse);
+    }
+}
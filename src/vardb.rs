@@ -0,0 +1,276 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::sync::Arc;
+use crate::error::{self, Diagnostic, Error};
+use crate::fields::Fields;
+use crate::{load_package, split_package_version, try_parse_package_dir_with, VarDbPkg};
+
+/// A handle on a vardb directory, typically `/var/db/pkg`.
+///
+/// Unlike `parse_vardb`, which collects every package into a `Vec`, `VarDb` reads
+/// one package directory at a time. Categories and packages are visited in the
+/// same sorted order as `try_parse_vardb` returns them.
+#[derive(Debug)]
+pub struct VarDb {
+    root: PathBuf,
+    categories: Vec<(String, PathBuf)>,
+    // Shared, as every call to `entries` reports them again
+    errors: Vec<(PathBuf, Arc<io::Error>)>,
+    fields: Fields,
+}
+
+impl VarDb {
+    /// Opens a vardb by listing its categories.
+    /// Fails if the directory itself cannot be read.
+    pub fn open<P: AsRef<Path>>(path: P) -> error::Result<Self> {
+        let root = path.as_ref().to_path_buf();
+        let entries = fs::read_dir(&root).map_err(|e| Error::from_read_dir(&root, e))?;
+        let mut categories = Vec::new();
+        let mut errors = Vec::new();
+
+        for entry in entries {
+            match entry {
+                Ok(entry) => {
+                    let category_path = entry.path();
+                    if category_path.is_dir() {
+                        categories.push((entry.file_name().to_string_lossy().into_owned(), category_path));
+                    }
+                }
+                Err(e) => errors.push((root.clone(), Arc::new(e))),
+            }
+        }
+        categories.sort();
+
+        Ok(VarDb {
+            root,
+            categories,
+            errors,
+            fields: Fields::all(),
+        })
+    }
+
+    /// Selects the fields read by `iter`. All fields are read by default.
+    pub fn with_fields(mut self, fields: Fields) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    /// Returns the path of the vardb.
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the category names, sorted.
+    pub fn categories(&self) -> impl Iterator<Item = &str> {
+        self.categories.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Iterates over the package directories without reading any package files.
+    /// Use this to filter packages by name before loading them with `PackageEntry::load`.
+    pub fn entries(&self) -> Entries<'_> {
+        Entries {
+            errors: self.errors.iter(),
+            categories: self.categories.iter(),
+            current: Vec::new().into_iter(),
+        }
+    }
+
+    /// Iterates over all packages, loading one package directory at a time.
+    /// Only the fields selected with `with_fields` are read.
+    /// See `PackageEntry::load` for the error behaviour.
+    pub fn iter(&self) -> impl Iterator<Item = error::Result<VarDbPkg>> + '_ {
+        self.entries().map(|entry| entry.and_then(|e| e.load_with(self.fields)))
+    }
+
+    /// Like `iter`, but a package with unreadable metadata files is still yielded together
+    /// with the problems found in its directory, as `try_parse_vardb` does.
+    /// See `PackageEntry::load_with_diagnostics`.
+    pub fn iter_with_diagnostics(&self) -> impl Iterator<Item = error::Result<(VarDbPkg, Vec<Diagnostic>)>> + '_ {
+        self.entries().map(|entry| entry.and_then(|e| e.load_with_diagnostics(self.fields)))
+    }
+}
+
+impl<'a> IntoIterator for &'a VarDb {
+    type Item = error::Result<VarDbPkg>;
+    type IntoIter = Box<dyn Iterator<Item = error::Result<VarDbPkg>> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+/// A package directory of the vardb that has not been read yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageEntry {
+    pub category: String,
+    pub package: String,
+    pub version: String,
+    pub path: PathBuf,
+    dir_name: String,
+}
+
+impl PackageEntry {
+    fn new(category: &str, dir_name: String, path: PathBuf) -> Self {
+        let (package, version) = split_package_version(&dir_name);
+        PackageEntry {
+            category: category.to_string(),
+            package,
+            version,
+            path,
+            dir_name,
+        }
+    }
+
+    /// Returns the package directory name, e.g. `bash-5.2_p37`.
+    pub fn dir_name(&self) -> &str {
+        &self.dir_name
+    }
+
+    /// Returns `category/package-version` as named by the directory.
+    pub fn cpv(&self) -> String {
+        format!("{}/{}", self.category, self.dir_name)
+    }
+
+    /// Reads the package directory including its ebuild, like `try_parse_package_dir`.
+    pub fn load(&self) -> error::Result<VarDbPkg> {
+        self.load_with(Fields::all())
+    }
+
+    /// Reads only the selected fields of the package directory.
+    pub fn load_with(&self, fields: Fields) -> error::Result<VarDbPkg> {
+        try_parse_package_dir_with(&self.category, &self.dir_name, &self.path, fields)
+    }
+
+    /// Reads the selected fields like `try_parse_vardb` does: fails only if the directory
+    /// cannot be listed, and returns the problems with single files alongside the package.
+    pub fn load_with_diagnostics(&self, fields: Fields) -> error::Result<(VarDbPkg, Vec<Diagnostic>)> {
+        let (pkg, errors) = load_package(&self.category, &self.dir_name, &self.path, fields)?;
+        let diagnostics = errors.into_iter().map(|error| Diagnostic { cpv: Some(self.cpv()), error }).collect();
+        Ok((pkg, diagnostics))
+    }
+}
+
+/// Iterator over the package directories of a `VarDb`, see `VarDb::entries`.
+#[derive(Debug)]
+pub struct Entries<'a> {
+    errors: slice::Iter<'a, (PathBuf, Arc<io::Error>)>,
+    categories: slice::Iter<'a, (String, PathBuf)>,
+    current: std::vec::IntoIter<PackageEntry>,
+}
+
+impl Iterator for Entries<'_> {
+    type Item = error::Result<PackageEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((path, error)) = self.errors.next() {
+            return Some(Err(Error::from_read_dir(path, io::Error::new(error.kind(), Arc::clone(error)))));
+        }
+        loop {
+            if let Some(entry) = self.current.next() {
+                return Some(Ok(entry));
+            }
+            let (category, category_path) = self.categories.next()?;
+            match list_category(category, category_path) {
+                Ok(entries) => self.current = entries.into_iter(),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Lists the package directories of one category, sorted by name.
+fn list_category(category: &str, path: &Path) -> error::Result<Vec<PackageEntry>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(path).map_err(|e| Error::from_read_dir(path, e))? {
+        let entry = entry.map_err(|e| Error::from_read_dir(path, e))?;
+        let pkg_path = entry.path();
+        if pkg_path.is_dir() {
+            entries.push(PackageEntry::new(category, entry.file_name().to_string_lossy().into_owned(), pkg_path));
+        }
+    }
+    entries.sort_by(|a, b| a.dir_name.cmp(&b.dir_name));
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn sample_vardb() -> tempfile::TempDir {
+        let dir = tempdir().unwrap();
+        for cpv in ["sys-libs/zlib-1.3.1", "app-misc/b-1", "app-misc/a-2", "dev-libs/openssl-3.3.2"] {
+            let pkg_path = dir.path().join(cpv);
+            fs::create_dir_all(&pkg_path).unwrap();
+            fs::write(pkg_path.join("SLOT"), "0\n").unwrap();
+        }
+        fs::write(dir.path().join("app-misc/a-2/a-2.ebuild"), "DESCRIPTION=\"A\"\n").unwrap();
+        fs::write(dir.path().join("app-misc/b-1/b-1.ebuild"), "DESCRIPTION=\"B\"\n").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_open_missing() {
+        assert!(matches!(VarDb::open("/nonexistent/vardb"), Err(Error::NotFound { .. })));
+    }
+
+    #[test]
+    fn test_iter_order_matches_parse_vardb() {
+        let dir = sample_vardb();
+        let vardb = VarDb::open(dir.path()).unwrap();
+        assert_eq!(vardb.categories().collect::<Vec<_>>(), ["app-misc", "dev-libs", "sys-libs"]);
+
+        let streamed: Vec<String> = vardb.iter().map(|p| p.unwrap().package).collect();
+        let collected: Vec<String> = crate::parse_vardb(dir.path()).into_iter().map(|p| p.package).collect();
+        assert_eq!(streamed, ["a", "b", "openssl", "zlib"]);
+        assert_eq!(streamed, collected);
+        assert_eq!((&vardb).into_iter().count(), 4);
+    }
+
+    #[test]
+    fn test_filter_before_load() {
+        let dir = sample_vardb();
+        let vardb = VarDb::open(dir.path()).unwrap();
+        // The ebuild of the rejected package is unreadable, so loading it would fail.
+        fs::write(dir.path().join("app-misc/b-1/b-1.ebuild"), b"\xff").unwrap();
+
+        let loaded: Vec<VarDbPkg> = vardb
+            .entries()
+            .filter_map(Result::ok)
+            .filter(|e| e.category == "app-misc" && e.package != "b")
+            .map(|e| e.load().unwrap())
+            .collect();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].ebuild_data["description"], "A");
+        assert!(vardb.iter().any(|p| p.is_err()));
+
+        // The diagnostics variant keeps the package and reports the file, like try_parse_vardb
+        let (b, diagnostics) = vardb.iter_with_diagnostics().map(Result::unwrap).find(|(p, _)| p.package == "b").unwrap();
+        assert_eq!(b.slot, "0");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].cpv.as_deref(), Some("app-misc/b-1"));
+        assert!(matches!(diagnostics[0].error, Error::InvalidUtf8 { .. }));
+    }
+
+    #[test]
+    fn test_iter_with_fields() {
+        let dir = sample_vardb();
+        fs::write(dir.path().join("app-misc/b-1/b-1.ebuild"), b"\xff").unwrap();
+        let vardb = VarDb::open(dir.path()).unwrap().with_fields(Fields::SLOT);
+        let packages: Vec<VarDbPkg> = vardb.iter().map(Result::unwrap).collect();
+        assert_eq!(packages.len(), 4);
+        assert!(packages.iter().all(|p| p.slot == "0" && p.loaded_fields == Fields::SLOT));
+    }
+
+    #[test]
+    fn test_entry_fields() {
+        let dir = sample_vardb();
+        let entry = VarDb::open(dir.path()).unwrap().entries().nth(2).unwrap().unwrap();
+        assert_eq!(entry.package, "openssl");
+        assert_eq!(entry.version, "3.3.2");
+        assert_eq!(entry.dir_name(), "openssl-3.3.2");
+        assert_eq!(entry.cpv(), "dev-libs/openssl-3.3.2");
+    }
+}
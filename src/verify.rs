@@ -0,0 +1,450 @@
+use md5::{Digest, Md5};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use crate::contents::{Contents, ContentsEntry, EntryType};
+use crate::error;
+use crate::owner::resolve_in_root;
+use crate::VarDbPkg;
+
+/// What is wrong with an installed file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The path does not exist.
+    Missing,
+    /// The path exists with another file type, `None` if it is none of the CONTENTS types (e.g. a socket).
+    TypeChanged { found: Option<EntryType> },
+    /// The content of a file changed.
+    Md5Mismatch { expected: String, actual: String },
+    /// The modification time of a file or symlink changed.
+    MtimeMismatch { expected: u64, actual: u64 },
+    /// A symlink points somewhere else.
+    TargetMismatch { expected: PathBuf, actual: PathBuf },
+    /// The path could not be checked.
+    Unreadable(io::ErrorKind),
+}
+
+impl Problem {
+    /// Returns true for a changed checksum, mtime or symlink target.
+    pub fn is_modified(&self) -> bool {
+        matches!(
+            self,
+            Problem::Md5Mismatch { .. } | Problem::MtimeMismatch { .. } | Problem::TargetMismatch { .. }
+        )
+    }
+}
+
+/// A problem found for a CONTENTS entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileProblem {
+    /// The path as recorded in CONTENTS, i.e. without the verifier's root.
+    pub path: PathBuf,
+    pub entry_type: EntryType,
+    pub problem: Problem,
+    /// Whether the path is protected by CONFIG_PROTECT, where changes are usually intended.
+    pub config_protected: bool,
+}
+
+/// The result of verifying the CONTENTS of a package.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of entries checked.
+    pub checked: usize,
+    /// Problems in CONTENTS order. A file can have more than one, e.g. MD5 and mtime.
+    pub problems: Vec<FileProblem>,
+}
+
+impl VerifyReport {
+    /// Returns true if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Returns the missing paths.
+    pub fn missing(&self) -> impl Iterator<Item = &FileProblem> {
+        self.problems.iter().filter(|p| p.problem == Problem::Missing)
+    }
+
+    /// Returns the changed files and symlinks.
+    pub fn modified(&self) -> impl Iterator<Item = &FileProblem> {
+        self.problems.iter().filter(|p| p.problem.is_modified())
+    }
+
+    /// Returns the paths whose file type changed.
+    pub fn type_changed(&self) -> impl Iterator<Item = &FileProblem> {
+        self.problems.iter().filter(|p| matches!(p.problem, Problem::TypeChanged { .. }))
+    }
+
+    /// Returns the problems outside of CONFIG_PROTECT, the ones that indicate tampering.
+    pub fn unprotected(&self) -> impl Iterator<Item = &FileProblem> {
+        self.problems.iter().filter(|p| !p.config_protected)
+    }
+
+    /// Returns the problems of config-protected paths, e.g. edited files in `/etc`.
+    pub fn config_protected(&self) -> impl Iterator<Item = &FileProblem> {
+        self.problems.iter().filter(|p| p.config_protected)
+    }
+}
+
+/// Compares the CONTENTS of installed packages with the filesystem, like `qcheck`.
+///
+/// `obj` entries are checked for existence, type, MD5 and mtime, `sym` entries for
+/// existence, type, target and mtime, all other entries for existence and type.
+#[derive(Debug, Clone)]
+pub struct Verifier {
+    root: PathBuf,
+    config_protect: Vec<PathBuf>,
+    config_protect_mask: Vec<PathBuf>,
+    check_mtime: bool,
+}
+
+impl Default for Verifier {
+    fn default() -> Self {
+        Verifier {
+            root: PathBuf::from("/"),
+            config_protect: vec![PathBuf::from("/etc")],
+            config_protect_mask: Vec::new(),
+            check_mtime: true,
+        }
+    }
+}
+
+impl Verifier {
+    /// Creates a verifier for `/` with the default CONFIG_PROTECT of `/etc`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks the files below another root, e.g. a mounted image.
+    pub fn with_root<P: AsRef<Path>>(mut self, root: P) -> Self {
+        self.root = root.as_ref().to_path_buf();
+        self
+    }
+
+    /// Sets CONFIG_PROTECT and CONFIG_PROTECT_MASK, as whitespace separated lists of paths.
+    pub fn with_config_protect(mut self, protect: &str, mask: &str) -> Self {
+        self.config_protect = protect.split_whitespace().map(PathBuf::from).collect();
+        self.config_protect_mask = mask.split_whitespace().map(PathBuf::from).collect();
+        self
+    }
+
+    /// Enables or disables the mtime check. Enabled by default.
+    pub fn with_mtime_check(mut self, check_mtime: bool) -> Self {
+        self.check_mtime = check_mtime;
+        self
+    }
+
+    /// Returns true if a path is below CONFIG_PROTECT and not below CONFIG_PROTECT_MASK.
+    /// The most specific entry wins, like in Portage.
+    pub fn is_config_protected<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = path.as_ref();
+        let depth = |dirs: &[PathBuf]| {
+            dirs.iter()
+                .filter(|dir| path.starts_with(dir))
+                .map(|dir| dir.components().count())
+                .max()
+        };
+        match (depth(&self.config_protect), depth(&self.config_protect_mask)) {
+            (Some(protect), Some(mask)) => protect > mask,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    /// Verifies the installed files of a package.
+    pub fn verify_package(&self, pkg: &VarDbPkg) -> error::Result<VerifyReport> {
+        Ok(self.verify(&pkg.contents()?))
+    }
+
+    /// Verifies all packages, in slice order. Packages without CONTENTS are reported with their error.
+    pub fn verify_packages(&self, packages: &[VarDbPkg]) -> Vec<error::Result<VerifyReport>> {
+        packages.iter().map(|pkg| self.verify_package(pkg)).collect()
+    }
+
+    /// Verifies the entries of a parsed CONTENTS file.
+    pub fn verify(&self, contents: &Contents) -> VerifyReport {
+        let mut report = VerifyReport::default();
+        for entry in contents {
+            report.checked += 1;
+            for problem in self.check(entry) {
+                report.problems.push(FileProblem {
+                    path: entry.path().to_path_buf(),
+                    entry_type: entry.entry_type(),
+                    problem,
+                    config_protected: self.is_config_protected(entry.path()),
+                });
+            }
+        }
+        report
+    }
+
+    fn check(&self, entry: &ContentsEntry) -> Vec<Problem> {
+        let path = entry.path();
+        let on_disk = match resolve_in_root(&self.root, path, false) {
+            Ok(on_disk) => on_disk,
+            Err(e) => return vec![Problem::Unreadable(e.kind())],
+        };
+        let metadata = match fs::symlink_metadata(&on_disk) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return vec![Problem::Missing],
+            Err(e) => return vec![Problem::Unreadable(e.kind())],
+        };
+        let found = entry_type_of(&metadata.file_type());
+        // A directory replaced by a symlink to a directory is fine, e.g. /lib64 on merged-usr systems
+        let dir_link = found == Some(EntryType::Sym)
+            && entry.entry_type() == EntryType::Dir
+            && resolve_in_root(&self.root, path, true)
+                .and_then(fs::symlink_metadata)
+                .is_ok_and(|m| m.is_dir());
+        if found != Some(entry.entry_type()) && !dir_link {
+            return vec![Problem::TypeChanged { found }];
+        }
+
+        let mut problems = Vec::new();
+        match entry {
+            ContentsEntry::Obj { md5, mtime, .. } => {
+                match file_md5(&on_disk) {
+                    Ok(actual) if !actual.eq_ignore_ascii_case(md5) => problems.push(Problem::Md5Mismatch {
+                        expected: md5.clone(),
+                        actual,
+                    }),
+                    Ok(_) => {}
+                    Err(e) => problems.push(Problem::Unreadable(e.kind())),
+                }
+                problems.extend(self.check_mtime(&metadata, *mtime));
+            }
+            ContentsEntry::Sym { target, mtime, .. } => {
+                match fs::read_link(&on_disk) {
+                    Ok(actual) if actual != *target => problems.push(Problem::TargetMismatch {
+                        expected: target.clone(),
+                        actual,
+                    }),
+                    Ok(_) => {}
+                    Err(e) => problems.push(Problem::Unreadable(e.kind())),
+                }
+                problems.extend(self.check_mtime(&metadata, *mtime));
+            }
+            ContentsEntry::Dir { .. } | ContentsEntry::Dev { .. } | ContentsEntry::Fif { .. } => {}
+        }
+        problems
+    }
+
+    fn check_mtime(&self, metadata: &fs::Metadata, expected: u64) -> Option<Problem> {
+        if !self.check_mtime {
+            return None;
+        }
+        let actual = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        (actual != expected).then_some(Problem::MtimeMismatch { expected, actual })
+    }
+}
+
+/// Maps a file type to the CONTENTS type describing it.
+pub(crate) fn entry_type_of(file_type: &fs::FileType) -> Option<EntryType> {
+    #[cfg(unix)]
+    use std::os::unix::fs::FileTypeExt;
+
+    if file_type.is_symlink() {
+        Some(EntryType::Sym)
+    } else if file_type.is_dir() {
+        Some(EntryType::Dir)
+    } else if file_type.is_file() {
+        Some(EntryType::Obj)
+    } else {
+        #[cfg(unix)]
+        if file_type.is_fifo() {
+            return Some(EntryType::Fif);
+        } else if file_type.is_block_device() || file_type.is_char_device() {
+            return Some(EntryType::Dev);
+        }
+        None
+    }
+}
+
+/// Returns the lowercase hex MD5 of a file, as recorded in CONTENTS.
+fn file_md5(path: &Path) -> io::Result<String> {
+    let mut hasher = Md5::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use std::time::{Duration, SystemTime};
+    use tempfile::tempdir;
+
+    fn set_mtime(path: &Path, mtime: u64) {
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(mtime)).unwrap();
+    }
+
+    #[test]
+    fn test_verify() {
+        let root = tempdir().unwrap();
+        fs::create_dir_all(root.path().join("usr/bin")).unwrap();
+        fs::create_dir_all(root.path().join("etc")).unwrap();
+        for (path, content) in [("usr/bin/good", "good\n"), ("usr/bin/changed", "evil\n"), ("etc/foo.conf", "edited\n")] {
+            fs::write(root.path().join(path), content).unwrap();
+            set_mtime(&root.path().join(path), 1700000000);
+        }
+        fs::create_dir(root.path().join("usr/bin/was-a-file")).unwrap();
+        std::os::unix::fs::symlink("elsewhere", root.path().join("usr/bin/link")).unwrap();
+
+        let md5 = file_md5(&root.path().join("usr/bin/good")).unwrap();
+        let contents = Contents::parse(&format!(
+            "dir /usr\n\
+             dir /usr/bin\n\
+             obj /usr/bin/good {md5} 1700000000\n\
+             obj /usr/bin/changed {md5} 1700000000\n\
+             obj /usr/bin/gone {md5} 1700000000\n\
+             obj /usr/bin/was-a-file {md5} 1700000000\n\
+             sym /usr/bin/link -> good 1700000000\n\
+             obj /etc/foo.conf {md5} 1700000001\n"
+        ));
+
+        let verifier = Verifier::new().with_root(root.path()).with_mtime_check(false);
+        let report = verifier.verify(&contents);
+        assert_eq!(report.checked, 8);
+        let summary: Vec<(&str, bool)> = report
+            .problems
+            .iter()
+            .map(|p| (p.path.to_str().unwrap(), p.config_protected))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("/usr/bin/changed", false),
+                ("/usr/bin/gone", false),
+                ("/usr/bin/was-a-file", false),
+                ("/usr/bin/link", false),
+                ("/etc/foo.conf", true),
+            ]
+        );
+        assert_eq!(report.missing().count(), 1);
+        assert_eq!(report.modified().count(), 3);
+        assert_eq!(report.type_changed().next().unwrap().problem, Problem::TypeChanged { found: Some(EntryType::Dir) });
+        assert_eq!(report.unprotected().count(), 4);
+        assert_eq!(report.config_protected().count(), 1);
+        assert!(matches!(
+            &report.problems[3].problem,
+            Problem::TargetMismatch { expected, actual } if expected == Path::new("good") && actual == Path::new("elsewhere")
+        ));
+
+        // The symlink's mtime is whatever it was created with
+        let report = Verifier::new().with_root(root.path()).verify(&contents);
+        let mtimes: Vec<&Path> = report
+            .problems
+            .iter()
+            .filter(|p| matches!(p.problem, Problem::MtimeMismatch { .. }))
+            .map(|p| p.path.as_path())
+            .collect();
+        assert_eq!(mtimes, [Path::new("/usr/bin/link"), Path::new("/etc/foo.conf")]);
+    }
+
+    #[test]
+    fn test_verify_merged_usr() {
+        let root = tempdir().unwrap();
+        fs::create_dir_all(root.path().join("usr/lib64")).unwrap();
+        fs::write(root.path().join("usr/lib64/libc.so.6"), "libc\n").unwrap();
+        std::os::unix::fs::symlink("usr/lib64", root.path().join("lib64")).unwrap();
+        std::os::unix::fs::symlink("usr/missing", root.path().join("bin")).unwrap();
+        fs::write(root.path().join("sbin"), "").unwrap();
+
+        let md5 = file_md5(&root.path().join("usr/lib64/libc.so.6")).unwrap();
+        let contents = Contents::parse(&format!(
+            "dir /lib64\nobj /lib64/libc.so.6 {md5} 1\ndir /bin\ndir /sbin\n"
+        ));
+        let report = Verifier::new().with_root(root.path()).with_mtime_check(false).verify(&contents);
+        let problems: Vec<(&Path, &Problem)> = report.problems.iter().map(|p| (p.path.as_path(), &p.problem)).collect();
+        assert_eq!(
+            problems,
+            [
+                (Path::new("/bin"), &Problem::TypeChanged { found: Some(EntryType::Sym) }),
+                (Path::new("/sbin"), &Problem::TypeChanged { found: Some(EntryType::Obj) }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verify_stays_in_root() {
+        let root = tempdir().unwrap();
+        fs::create_dir_all(root.path().join("usr/lib64")).unwrap();
+        fs::write(root.path().join("usr/lib64/libc.so.6"), "libc\n").unwrap();
+        // Absolute targets point into the image, not to the host
+        std::os::unix::fs::symlink("/usr/lib64", root.path().join("lib64")).unwrap();
+        std::os::unix::fs::symlink("../../../../../../../etc", root.path().join("escape")).unwrap();
+        std::os::unix::fs::symlink("/loop", root.path().join("loop")).unwrap();
+
+        let md5 = file_md5(&root.path().join("usr/lib64/libc.so.6")).unwrap();
+        let contents = Contents::parse(&format!(
+            "dir /lib64\nobj /lib64/libc.so.6 {md5} 1\nobj /escape/passwd {md5} 1\nobj /loop/x {md5} 1\n"
+        ));
+        let report = Verifier::new().with_root(root.path()).with_mtime_check(false).verify(&contents);
+        let problems: Vec<(&Path, &Problem)> = report.problems.iter().map(|p| (p.path.as_path(), &p.problem)).collect();
+        assert_eq!(
+            problems,
+            [
+                (Path::new("/escape/passwd"), &Problem::Missing),
+                (Path::new("/loop/x"), &Problem::Unreadable(io::ErrorKind::Other)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verify_packages_reports_path() {
+        let vardb = tempdir().unwrap();
+        let with_contents = vardb.path().join("app-misc/a-1");
+        fs::create_dir_all(&with_contents).unwrap();
+        fs::write(with_contents.join("CONTENTS"), "dir /usr\n").unwrap();
+        let packages = [
+            VarDbPkg { path: with_contents, ..Default::default() },
+            VarDbPkg { path: vardb.path().join("app-misc/b-1"), ..Default::default() },
+        ];
+
+        let root = tempdir().unwrap();
+        let reports = Verifier::new().with_root(root.path()).verify_packages(&packages);
+        assert_eq!(reports[0].as_ref().unwrap().checked, 1);
+        assert!(matches!(
+            &reports[1],
+            Err(Error::NotFound { path }) if *path == vardb.path().join("app-misc/b-1/CONTENTS")
+        ));
+    }
+
+    #[test]
+    fn test_verify_non_utf8_name() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let root = tempdir().unwrap();
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+        fs::write(root.path().join(name), "x\n").unwrap();
+        let md5 = file_md5(&root.path().join(name)).unwrap();
+        let mut raw = b"obj /caf\xe9.txt ".to_vec();
+        raw.extend_from_slice(format!("{md5} 1\n").as_bytes());
+
+        let report = Verifier::new().with_root(root.path()).with_mtime_check(false).verify(&Contents::parse_bytes(&raw));
+        assert_eq!(report.checked, 1);
+        assert!(report.is_ok(), "{:?}", report.problems);
+    }
+
+    #[test]
+    fn test_config_protect() {
+        let verifier = Verifier::new().with_config_protect("/etc /usr/share/config", "/etc/env.d");
+        assert!(verifier.is_config_protected("/etc/foo.conf"));
+        assert!(verifier.is_config_protected("/usr/share/config/kdeglobals"));
+        assert!(!verifier.is_config_protected("/etc/env.d/00basic"));
+        assert!(!verifier.is_config_protected("/etcetera"));
+        assert!(!verifier.is_config_protected("/usr/bin/bash"));
+        assert!(!Verifier::new().with_config_protect("", "").is_config_protected("/etc/passwd"));
+    }
+
+    #[test]
+    fn test_file_md5() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("f");
+        fs::write(&path, "").unwrap();
+        assert_eq!(file_md5(&path).unwrap(), "d41d8cd98f00b204e9800998ecf8427e");
+    }
+}
@@ -0,0 +1,346 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// Error returned when a string is not a valid PMS version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseVersionError {
+    version: String,
+}
+
+impl fmt::Display for ParseVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid version: {:?}", self.version)
+    }
+}
+
+impl std::error::Error for ParseVersionError {}
+
+/// A version suffix keyword, in PMS order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SuffixKind {
+    Alpha,
+    Beta,
+    Pre,
+    Rc,
+    P,
+}
+
+impl SuffixKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuffixKind::Alpha => "alpha",
+            SuffixKind::Beta => "beta",
+            SuffixKind::Pre => "pre",
+            SuffixKind::Rc => "rc",
+            SuffixKind::P => "p",
+        }
+    }
+}
+
+/// A version suffix such as `_rc1` or `_p`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suffix {
+    pub kind: SuffixKind,
+    /// The number following the keyword, empty if there is none.
+    pub number: String,
+}
+
+/// A package version following the PMS version grammar:
+/// `1.2.3b_rc1_p2-r3`.
+///
+/// Ordering follows the PMS version comparison algorithm, so
+/// `1.2_rc1 < 1.2 < 1.2_p1 < 1.2-r1`. Versions comparing equal may still
+/// differ textually (e.g. `1.0` and `1.00`, or `1` and `1-r0`).
+#[derive(Debug, Clone)]
+pub struct Version {
+    raw: String,
+    numbers: Vec<String>,
+    letter: Option<char>,
+    suffixes: Vec<Suffix>,
+    revision: String,
+}
+
+impl Version {
+    /// Parses a version string.
+    pub fn parse(s: &str) -> Result<Self, ParseVersionError> {
+        let err = || ParseVersionError { version: s.to_string() };
+
+        let (main, revision) = match s.rfind("-r") {
+            Some(idx) if is_digits(&s[idx + 2..]) => (&s[..idx], s[idx + 2..].to_string()),
+            _ => (s, String::new()),
+        };
+
+        let mut parts = main.split('_');
+        let base = parts.next().ok_or_else(err)?;
+
+        let (base, letter) = match base.chars().last() {
+            Some(c) if c.is_ascii_lowercase() => (&base[..base.len() - 1], Some(c)),
+            _ => (base, None),
+        };
+
+        let numbers: Vec<String> = base.split('.').map(str::to_string).collect();
+        if numbers.iter().any(|n| !is_digits(n)) {
+            return Err(err());
+        }
+
+        let mut suffixes = Vec::new();
+        for part in parts {
+            let (kind, number) = [
+                ("alpha", SuffixKind::Alpha),
+                ("beta", SuffixKind::Beta),
+                ("pre", SuffixKind::Pre),
+                ("rc", SuffixKind::Rc),
+                ("p", SuffixKind::P),
+            ]
+            .iter()
+            .find_map(|(name, kind)| part.strip_prefix(name).map(|n| (*kind, n)))
+            .ok_or_else(err)?;
+            if !number.is_empty() && !is_digits(number) {
+                return Err(err());
+            }
+            suffixes.push(Suffix { kind, number: number.to_string() });
+        }
+
+        Ok(Version {
+            raw: s.to_string(),
+            numbers,
+            letter,
+            suffixes,
+            revision,
+        })
+    }
+
+    /// Returns the version exactly as it was parsed.
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    /// Returns the numeric components, e.g. `["1", "2", "3"]` for `1.2.3`.
+    pub fn numbers(&self) -> &[String] {
+        &self.numbers
+    }
+
+    /// Returns the letter suffix, e.g. `b` for `1.2b`.
+    pub fn letter(&self) -> Option<char> {
+        self.letter
+    }
+
+    /// Returns the `_alpha`, `_beta`, `_pre`, `_rc` and `_p` suffixes.
+    pub fn suffixes(&self) -> &[Suffix] {
+        &self.suffixes
+    }
+
+    /// Returns the revision number, `0` if there is none.
+    pub fn revision(&self) -> u64 {
+        self.revision.parse().unwrap_or(if self.revision.is_empty() { 0 } else { u64::MAX })
+    }
+
+    /// Returns the version without the `-rN` part.
+    pub fn without_revision(&self) -> &str {
+        if self.revision.is_empty() {
+            &self.raw
+        } else {
+            &self.raw[..self.raw.len() - self.revision.len() - 2]
+        }
+    }
+
+    /// Returns true if the version matches `=<prefix>*`. The prefix must end at a component
+    /// boundary, so `1.2*` matches `1.2`, `1.2.3` and `1.2_rc1` but not `1.20`.
+    pub fn matches_glob(&self, prefix: &str) -> bool {
+        self.raw.strip_prefix(prefix).is_some_and(|rest| {
+            !(prefix.ends_with(|c: char| c.is_ascii_digit()) && rest.starts_with(|c: char| c.is_ascii_digit()))
+        })
+    }
+
+    /// Compares two versions ignoring the revision.
+    pub fn cmp_without_revision(&self, other: &Self) -> Ordering {
+        self.cmp_numbers(other)
+            .then_with(|| self.letter.cmp(&other.letter))
+            .then_with(|| self.cmp_suffixes(other))
+    }
+
+    fn cmp_numbers(&self, other: &Self) -> Ordering {
+        // The first component is always compared as an integer.
+        let ord = cmp_integers(&self.numbers[0], &other.numbers[0]);
+        if ord != Ordering::Equal {
+            return ord;
+        }
+        for (a, b) in self.numbers.iter().zip(&other.numbers).skip(1) {
+            let ord = if a.starts_with('0') || b.starts_with('0') {
+                a.trim_end_matches('0').cmp(b.trim_end_matches('0'))
+            } else {
+                cmp_integers(a, b)
+            };
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        self.numbers.len().cmp(&other.numbers.len())
+    }
+
+    fn cmp_suffixes(&self, other: &Self) -> Ordering {
+        for (a, b) in self.suffixes.iter().zip(&other.suffixes) {
+            let ord = a.kind.cmp(&b.kind).then_with(|| cmp_integers(&a.number, &b.number));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        // An additional `_p` suffix makes a version newer, any other suffix older.
+        match self.suffixes.len().cmp(&other.suffixes.len()) {
+            Ordering::Greater => match self.suffixes[other.suffixes.len()].kind {
+                SuffixKind::P => Ordering::Greater,
+                _ => Ordering::Less,
+            },
+            Ordering::Less => match other.suffixes[self.suffixes.len()].kind {
+                SuffixKind::P => Ordering::Less,
+                _ => Ordering::Greater,
+            },
+            Ordering::Equal => Ordering::Equal,
+        }
+    }
+}
+
+/// Compares two digit strings numerically without overflowing.
+fn cmp_integers(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+fn is_digits(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cmp_without_revision(other)
+            .then_with(|| cmp_integers(&self.revision, &other.revision))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl FromStr for Version {
+    type Err = ParseVersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.raw)
+    }
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Version::parse(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        Version::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_components() {
+        let ver = v("1.2.3b_rc1_p2-r3");
+        assert_eq!(ver.numbers(), &["1", "2", "3"]);
+        assert_eq!(ver.letter(), Some('b'));
+        assert_eq!(ver.suffixes().len(), 2);
+        assert_eq!(ver.suffixes()[0].kind, SuffixKind::Rc);
+        assert_eq!(ver.suffixes()[0].number, "1");
+        assert_eq!(ver.suffixes()[1].kind, SuffixKind::P);
+        assert_eq!(ver.revision(), 3);
+        assert_eq!(ver.without_revision(), "1.2.3b_rc1_p2");
+        assert_eq!(ver.to_string(), "1.2.3b_rc1_p2-r3");
+        assert_eq!(v("0-r2").revision(), 2);
+        assert_eq!(v("5.2_p37").without_revision(), "5.2_p37");
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for s in ["", "a", "1.", ".1", "1..2", "1.2-", "1.2_foo", "1.2_rcx", "1.2AB", "1.2-r", "1-2", "1.2ab"] {
+            assert!(Version::parse(s).is_err(), "{} should be invalid", s);
+        }
+    }
+
+    #[test]
+    fn test_ordering() {
+        let ordered = [
+            "0.9", "1", "1.0.1", "1.1", "1.2_alpha", "1.2_alpha1", "1.2_beta", "1.2_pre1", "1.2_rc1",
+            "1.2", "1.2-r1", "1.2_p", "1.2_p1", "1.2a", "1.2.1", "1.10", "2_rc1_p1", "2", "10",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_leading_zero_components() {
+        assert!(v("1.01") < v("1.1"));
+        assert!(v("1.010") == v("1.01"));
+        assert!(v("1.0") == v("1.00"));
+        assert!(v("1.09") < v("1.1"));
+        assert!(v("01") == v("1"));
+    }
+
+    #[test]
+    fn test_matches_glob() {
+        let v = Version::parse("1.2.3_rc1-r2").unwrap();
+        assert!(v.matches_glob("1"));
+        assert!(v.matches_glob("1.2"));
+        assert!(v.matches_glob("1.2.3_rc"));
+        assert!(!v.matches_glob("1.2.30"));
+        assert!(!Version::parse("1.20").unwrap().matches_glob("1.2"));
+        assert!(Version::parse("1.2").unwrap().matches_glob("1.2"));
+    }
+
+    #[test]
+    fn test_revision_zero() {
+        assert_eq!(v("1.2"), v("1.2-r0"));
+        assert!(v("1.2-r0") < v("1.2-r1"));
+        assert!(v("1.2-r10") > v("1.2-r9"));
+        assert_eq!(v("1.2-r3").cmp_without_revision(&v("1.2")), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_large_numbers() {
+        assert!(v("20240101000000000000000") < v("20240101000000000000001"));
+    }
+
+    #[test]
+    fn test_serde_roundtrip() {
+        let json = serde_json::to_string(&v("1.2_rc1-r1")).unwrap();
+        assert_eq!(json, "\"1.2_rc1-r1\"");
+        let back: Version = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.as_str(), "1.2_rc1-r1");
+    }
+}